@@ -0,0 +1,328 @@
+use mail_builder::headers::address::Address as BuilderAddress;
+use mail_parser::MessageParser;
+use std::fmt;
+use std::str::FromStr;
+
+/// An email address with its display name, e.g. `Jane Doe <jane@example.com>`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Address {
+    pub name: Option<String>,
+    pub mailbox: String,
+    pub host: String,
+    /// The group the address was listed under, e.g. `team` in `team: a@x, b@y;`
+    pub group: Option<String>,
+}
+
+impl Address {
+    pub fn new(name: Option<&str>, email: &str) -> Result<Address, String> {
+        let (mailbox, host) = email
+            .trim()
+            .rsplit_once('@')
+            .filter(|(mailbox, host)| !mailbox.is_empty() && !host.is_empty())
+            .ok_or_else(|| format!("invalid email address '{}'", email))?;
+
+        Ok(Address {
+            name: name
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty()),
+            mailbox: mailbox.to_string(),
+            host: host.to_string(),
+            group: None,
+        })
+    }
+
+    /// The bare `mailbox@host`, or an empty string if either part is missing
+    pub fn email(&self) -> String {
+        if self.mailbox.is_empty() || self.host.is_empty() {
+            return String::new();
+        }
+        format!("{}@{}", self.mailbox, self.host)
+    }
+
+    /// The display name if there is one, otherwise the email address
+    pub fn short(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.email())
+    }
+
+    /// Converts to a lettre mailbox for sending
+    pub fn to_mailbox(&self) -> Result<lettre::message::Mailbox, lettre::address::AddressError> {
+        Ok(lettre::message::Mailbox::new(
+            self.name.clone(),
+            lettre::Address::new(&self.mailbox, &self.host)?,
+        ))
+    }
+
+    /// Maps the addresses of an IMAP ENVELOPE field, where a group is framed by an entry
+    /// without a host (the group name) and one without a mailbox (the end of the group)
+    pub fn from_envelope(addrs: &[imap_proto::types::Address]) -> Vec<Address> {
+        let mut group = None;
+        let mut list = Vec::new();
+
+        for addr in addrs {
+            match (&addr.mailbox, &addr.host) {
+                (Some(name), None) => group = Some(decode_header_text(name)),
+                (None, None) => group = None,
+                (Some(mailbox), Some(host)) => list.push(Address {
+                    name: addr
+                        .name
+                        .as_ref()
+                        .map(|name| decode_header_text(name))
+                        .filter(|name| !name.is_empty()),
+                    mailbox: String::from_utf8_lossy(mailbox).to_string(),
+                    host: String::from_utf8_lossy(host).to_string(),
+                    group: group.clone(),
+                }),
+                (None, Some(_)) => {}
+            }
+        }
+
+        list
+    }
+
+    /// Maps an address header parsed by mail_parser, keeping group names
+    pub fn from_parsed(parsed: &mail_parser::Address) -> Vec<Address> {
+        let from_addr = |addr: &mail_parser::Addr, group: Option<&str>| {
+            let mut address = Address::new(addr.name.as_deref(), addr.address.as_deref()?).ok()?;
+            address.group = group.map(String::from);
+            Some(address)
+        };
+
+        match parsed {
+            mail_parser::Address::List(addrs) => addrs
+                .iter()
+                .filter_map(|addr| from_addr(addr, None))
+                .collect(),
+            mail_parser::Address::Group(groups) => groups
+                .iter()
+                .flat_map(|group| {
+                    group
+                        .addresses
+                        .iter()
+                        .filter_map(move |addr| from_addr(addr, group.name.as_deref()))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) if name.contains(|c: char| "()<>[]:;@\\,.\"".contains(c)) => write!(
+                f,
+                "\"{}\" <{}>",
+                name.replace('\\', "\\\\").replace('"', "\\\""),
+                self.email()
+            ),
+            Some(name) => write!(f, "{} <{}>", name, self.email()),
+            None => write!(f, "{}", self.email()),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    /// Parses `jane@example.com`, `Jane Doe <jane@example.com>` or
+    /// `"Doe, Jane" <jane@example.com>`, decoding RFC 2047 encoded-words in the name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let header = format!("To: {}\r\n\r\n", s.trim());
+        let message = MessageParser::default().parse(header.as_bytes());
+
+        let mut addresses = message
+            .as_ref()
+            .and_then(|message| message.to())
+            .map(Address::from_parsed)
+            .unwrap_or_default();
+
+        match addresses.len() {
+            1 => Ok(addresses.remove(0)),
+            0 => Err(format!("invalid email address '{}'", s)),
+            _ => Err(format!("expected a single address in '{}'", s)),
+        }
+    }
+}
+
+/// Parses a comma-separated address list as written in a `To:` line, e.g.
+/// `Jane <jane@example.com>, "Doe, John" <john@example.com>`. An empty list is fine;
+/// an entry that isn't an address is an error rather than being skipped.
+pub fn parse_address_list(s: &str) -> Result<Vec<Address>, String> {
+    if s.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let header = format!("To: {}\r\n\r\n", s.trim());
+    let message = MessageParser::default().parse(header.as_bytes());
+    let parsed = message
+        .as_ref()
+        .and_then(|message| message.to())
+        .ok_or_else(|| format!("invalid address list '{}'", s.trim()))?;
+
+    let entries: Vec<&mail_parser::Addr> = match parsed {
+        mail_parser::Address::List(addrs) => addrs.iter().collect(),
+        mail_parser::Address::Group(groups) => groups
+            .iter()
+            .flat_map(|group| group.addresses.iter())
+            .collect(),
+    };
+    for entry in entries {
+        let address = entry.address.as_deref().unwrap_or_default();
+        if Address::new(None, address).is_err() {
+            let text = entry.name.as_deref().unwrap_or(address);
+            return Err(format!("invalid email address '{}'", text));
+        }
+    }
+
+    Ok(Address::from_parsed(parsed))
+}
+
+/// Formats addresses for a mail_builder header, keeping consecutive members of the same
+/// group together
+pub fn builder_address_list(addrs: &[Address]) -> BuilderAddress<'static> {
+    let mut list: Vec<BuilderAddress<'static>> = Vec::new();
+    let mut members: Vec<BuilderAddress<'static>> = Vec::new();
+    let mut current_group: Option<&str> = None;
+
+    for addr in addrs {
+        if addr.group.as_deref() != current_group {
+            if let Some(group) = current_group {
+                list.push(BuilderAddress::new_group(
+                    Some(group.to_string()),
+                    std::mem::take(&mut members),
+                ));
+            }
+            current_group = addr.group.as_deref();
+        }
+
+        let address = BuilderAddress::new_address(addr.name.clone(), addr.email());
+        if current_group.is_some() {
+            members.push(address);
+        } else {
+            list.push(address);
+        }
+    }
+
+    if let Some(group) = current_group {
+        list.push(BuilderAddress::new_group(Some(group.to_string()), members));
+    }
+
+    BuilderAddress::new_list(list)
+}
+
+/// Decodes RFC 2047 encoded-words (`=?UTF-8?B?...?=`) in raw header text, such as the
+/// display names and subject of an IMAP ENVELOPE
+pub fn decode_header_text(raw: &[u8]) -> String {
+    let mut header = b"Subject: ".to_vec();
+    header.extend_from_slice(raw);
+    header.extend_from_slice(b"\r\n\r\n");
+
+    MessageParser::default()
+        .parse(&header)
+        .and_then(|message| message.subject().map(String::from))
+        .unwrap_or_else(|| String::from_utf8_lossy(raw).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope_address(
+        name: Option<&'static str>,
+        mailbox: Option<&'static str>,
+        host: Option<&'static str>,
+    ) -> imap_proto::types::Address<'static> {
+        imap_proto::types::Address {
+            name: name.map(str::as_bytes),
+            adl: None,
+            mailbox: mailbox.map(str::as_bytes),
+            host: host.map(str::as_bytes),
+        }
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let jane: Address = "Jane Doe <jane@example.com>".parse().unwrap();
+        assert_eq!(jane.name.as_deref(), Some("Jane Doe"));
+        assert_eq!(jane.email(), "jane@example.com");
+        assert_eq!(jane.to_string(), "Jane Doe <jane@example.com>");
+
+        let bare: Address = "bob@example.org".parse().unwrap();
+        assert_eq!(bare.name, None);
+        assert_eq!(bare.to_string(), "bob@example.org");
+        assert_eq!(bare.short(), "bob@example.org");
+
+        let quoted: Address = "\"Doe, Jane\" <jane@example.com>".parse().unwrap();
+        assert_eq!(quoted.name.as_deref(), Some("Doe, Jane"));
+        assert_eq!(quoted.to_string(), "\"Doe, Jane\" <jane@example.com>");
+
+        assert!("not an address".parse::<Address>().is_err());
+        assert!("a@x, b@y".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn test_email_requires_both_parts() {
+        assert_eq!(
+            Address::new(None, "testuser@example.com").unwrap().email(),
+            "testuser@example.com"
+        );
+        assert!(Address::new(None, "@example.com").is_err());
+        assert!(Address::new(None, "testuser@").is_err());
+        assert_eq!(Address::default().email(), "");
+        assert_eq!(Address::default().to_string(), "");
+    }
+
+    #[test]
+    fn test_encoded_words_are_decoded() {
+        let encoded: Address = "=?UTF-8?B?SsO8cmdlbg==?= <j@example.de>".parse().unwrap();
+        assert_eq!(encoded.name.as_deref(), Some("Jürgen"));
+
+        assert_eq!(
+            decode_header_text(b"=?ISO-8859-1?Q?Caf=E9?= menu"),
+            "Café menu"
+        );
+        assert_eq!(decode_header_text(b"plain text"), "plain text");
+    }
+
+    #[test]
+    fn test_from_envelope_with_group() {
+        let addrs = [
+            envelope_address(
+                Some("=?UTF-8?Q?Ren=C3=A9e?="),
+                Some("renee"),
+                Some("example.com"),
+            ),
+            envelope_address(None, Some("team"), None),
+            envelope_address(None, Some("a"), Some("x.org")),
+            envelope_address(Some("B"), Some("b"), Some("y.org")),
+            envelope_address(None, None, None),
+        ];
+
+        let list = Address::from_envelope(&addrs);
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[0].to_string(), "Renée <renee@example.com>");
+        assert_eq!(list[0].group, None);
+        assert_eq!(list[1].group.as_deref(), Some("team"));
+        assert_eq!(list[2].group.as_deref(), Some("team"));
+        assert_eq!(list[2].short(), "B");
+    }
+
+    #[test]
+    fn test_parse_address_list() {
+        let list = parse_address_list("Jane <jane@example.com>, \"Doe, John\" <john@example.com>")
+            .unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[1].name.as_deref(), Some("Doe, John"));
+
+        assert_eq!(parse_address_list("  ").unwrap(), vec![]);
+        assert!(parse_address_list("jane@example.com, bob").is_err());
+        assert!(parse_address_list("not an address").is_err());
+    }
+
+    #[test]
+    fn test_lettre_mailbox_keeps_name() {
+        let jane: Address = "Jane Doe <jane@example.com>".parse().unwrap();
+        let mailbox = jane.to_mailbox().unwrap();
+        assert_eq!(mailbox.name.as_deref(), Some("Jane Doe"));
+        assert_eq!(mailbox.email.to_string(), "jane@example.com");
+    }
+}
@@ -0,0 +1,185 @@
+use super::{MailBackend, MimePart};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A file carried by a message, with its content
+#[derive(Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub filename: String,
+    /// Lowercase `type/subtype`, e.g. `application/pdf`
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    /// Reads a file to attach, guessing its type from the extension
+    pub fn from_path(path: &Path) -> Result<Attachment, Box<dyn std::error::Error>> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| format!("{}: not a file", path.display()))?;
+
+        Ok(Attachment {
+            filename,
+            content_type: mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .essence_str()
+                .to_string(),
+            data,
+        })
+    }
+}
+
+// The content can be megabytes, so Debug (and `hermes inbox one`) only shows its size
+impl fmt::Debug for Attachment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Attachment")
+            .field("filename", &self.filename)
+            .field("content_type", &self.content_type)
+            .field("size", &self.data.len())
+            .finish()
+    }
+}
+
+/// The attachments of a message, found from its structure alone
+pub fn list_attachments(
+    backend: &mut dyn MailBackend,
+    folder: &str,
+    uid: u32,
+) -> Result<Vec<MimePart>, Box<dyn std::error::Error>> {
+    let structure = backend.fetch_structure(folder, uid)?;
+    Ok(structure.attachments().into_iter().cloned().collect())
+}
+
+/// Downloads one attachment and writes it into `dir`, next to any existing file of the
+/// same name rather than over it. `index` is the attachment's 1-based position, used to
+/// name attachments that don't carry a filename. Returns the path written.
+pub fn save_attachment(
+    backend: &mut dyn MailBackend,
+    folder: &str,
+    uid: u32,
+    part: &MimePart,
+    index: usize,
+    dir: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let contents = backend.fetch_part(folder, uid, part)?;
+    let name = file_name(part, index);
+
+    // create_new closes the gap between picking a free name and writing to it
+    let mut copy = 0;
+    loop {
+        let path = dir.join(numbered(&name, copy));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(&contents)?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => copy += 1,
+            Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+        }
+    }
+}
+
+/// A safe name to save a part under: the sender's filename without any directories, or
+/// `attachment-N` if there is none
+pub fn file_name(part: &MimePart, index: usize) -> String {
+    let name = part
+        .filename
+        .as_deref()
+        .and_then(|name| name.rsplit(['/', '\\']).next())
+        .map(|name| name.trim().trim_start_matches('.'))
+        .map(|name| name.replace(|c: char| c.is_control() || c == ':', "_"))
+        .unwrap_or_default();
+
+    if name.is_empty() {
+        format!("attachment-{}", index)
+    } else {
+        name
+    }
+}
+
+/// `report.pdf`, then `report-1.pdf`, `report-2.pdf`, ...
+fn numbered(name: &str, copy: usize) -> String {
+    if copy == 0 {
+        return name.to_string();
+    }
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}-{}.{}", stem, copy, extension)
+        }
+        _ => format!("{}-{}", name, copy),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_tools::MemoryBackend;
+
+    fn part(filename: Option<&str>) -> MimePart {
+        MimePart {
+            filename: filename.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_attachment_from_path_guesses_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("figures.csv");
+        std::fs::write(&path, "a,b\n1,2\n").unwrap();
+
+        let attachment = Attachment::from_path(&path).unwrap();
+        assert_eq!(attachment.filename, "figures.csv");
+        assert_eq!(attachment.content_type, "text/csv");
+        assert_eq!(attachment.data, b"a,b\n1,2\n");
+
+        let unknown = dir.path().join("blob.zzz");
+        std::fs::write(&unknown, [0u8, 1, 2]).unwrap();
+        assert_eq!(
+            Attachment::from_path(&unknown).unwrap().content_type,
+            "application/octet-stream"
+        );
+        assert!(Attachment::from_path(&dir.path().join("missing.pdf")).is_err());
+    }
+
+    #[test]
+    fn test_file_name_is_sanitized() {
+        assert_eq!(file_name(&part(Some("report.pdf")), 1), "report.pdf");
+        assert_eq!(file_name(&part(Some("../../etc/passwd")), 1), "passwd");
+        assert_eq!(file_name(&part(Some("C:\\temp\\x.txt")), 1), "x.txt");
+        assert_eq!(file_name(&part(Some(".bashrc")), 1), "bashrc");
+        assert_eq!(file_name(&part(Some("  ")), 2), "attachment-2");
+        assert_eq!(file_name(&part(None), 3), "attachment-3");
+    }
+
+    #[test]
+    fn test_numbered_copies() {
+        assert_eq!(numbered("report.pdf", 0), "report.pdf");
+        assert_eq!(numbered("report.pdf", 2), "report-2.pdf");
+        assert_eq!(numbered("README", 1), "README-1");
+    }
+
+    #[test]
+    fn test_save_demo_attachment_twice() {
+        let mut backend = MemoryBackend::demo();
+        let attachments = list_attachments(&mut backend, "INBOX", 3).unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].filename.as_deref(), Some("q4-figures.csv"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = save_attachment(&mut backend, "INBOX", 3, &attachments[0], 1, dir.path());
+        let second = save_attachment(&mut backend, "INBOX", 3, &attachments[0], 1, dir.path());
+
+        let first = first.unwrap();
+        assert_eq!(first.file_name().unwrap(), "q4-figures.csv");
+        assert_eq!(second.unwrap().file_name().unwrap(), "q4-figures-1.csv");
+        assert_eq!(
+            std::fs::read_to_string(first).unwrap(),
+            "region,revenue\nnorth,1200\nsouth,950\n"
+        );
+    }
+}
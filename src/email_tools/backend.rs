@@ -0,0 +1,759 @@
+use super::folder::find_special_use;
+use super::mime::decode_transfer;
+use super::{
+    Email, Flag, Folder, FolderStatus, Inbox, MailSession, MimePart, SearchQuery, SendError,
+    SendReport, SentCopy, SpecialUse, build_message, get_envelopes, get_inbox_one,
+    search_envelopes, smtp,
+};
+use imap::types::NameAttribute;
+use imap_proto::types::SectionPath;
+use std::str::FromStr;
+
+/// Which envelopes of a folder to fetch. Results always come back newest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    All,
+    /// The `limit` newest messages, after skipping the `offset` newest
+    Newest {
+        offset: u32,
+        limit: u32,
+    },
+    /// Messages with a UID in `first..=last`, or `first` and up when `last` is `None`
+    Uids {
+        first: u32,
+        last: Option<u32>,
+    },
+}
+
+impl Window {
+    /// Page `page` (1-based) of `limit` messages, newest first
+    pub fn page(page: u32, limit: u32) -> Window {
+        Window::Newest {
+            offset: page.saturating_sub(1).saturating_mul(limit),
+            limit,
+        }
+    }
+
+    /// The sequence-number set covering this window in a folder of `exists` messages, or
+    /// `None` if the window is empty. Not meaningful for `Uids`.
+    pub fn sequence_range(&self, exists: u32) -> Option<String> {
+        match *self {
+            Window::All if exists > 0 => Some(format!("1:{}", exists)),
+            Window::Newest { offset, limit } if limit > 0 && offset < exists => {
+                let last = exists - offset;
+                let first = last.saturating_sub(limit - 1).max(1);
+                Some(format!("{}:{}", first, last))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn contains_uid(&self, uid: u32) -> bool {
+        match *self {
+            Window::Uids { first, last } => uid >= first && last.is_none_or(|last| uid <= last),
+            _ => true,
+        }
+    }
+
+    /// The UIDs of this window among `uids`, which must be in ascending order
+    pub fn select_uids(&self, uids: &[u32]) -> Vec<u32> {
+        match *self {
+            Window::All => uids.to_vec(),
+            Window::Newest { offset, limit } => {
+                let end = uids.len().saturating_sub(offset as usize);
+                let start = end.saturating_sub(limit as usize);
+                uids[start..end].to_vec()
+            }
+            Window::Uids { .. } => uids
+                .iter()
+                .copied()
+                .filter(|&uid| self.contains_uid(uid))
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    /// Parses a UID range such as `100:200`, `100:*` or `42`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("invalid UID '{}' in range '{}'", n, s))
+        };
+
+        match s.split_once(':') {
+            Some((first, "*")) => Ok(Window::Uids {
+                first: parse(first)?,
+                last: None,
+            }),
+            Some((first, last)) => Ok(Window::Uids {
+                first: parse(first)?,
+                last: Some(parse(last)?),
+            }),
+            None => {
+                let uid = parse(s)?;
+                Ok(Window::Uids {
+                    first: uid,
+                    last: Some(uid),
+                })
+            }
+        }
+    }
+}
+
+/// Everything the CLI and TUI need from a mail store.
+///
+/// Messages are addressed by UID within a folder. `ImapBackend` talks to a real server;
+/// `MemoryBackend` keeps everything in memory for tests and `--demo`.
+pub trait MailBackend {
+    /// Lists every folder, or only the subscribed ones (LSUB)
+    fn list_folders(
+        &mut self,
+        subscribed_only: bool,
+    ) -> Result<Vec<Folder>, Box<dyn std::error::Error>>;
+
+    /// Total and unread message counts of a folder, without selecting it
+    fn folder_status(&mut self, folder: &str) -> Result<FolderStatus, Box<dyn std::error::Error>>;
+
+    /// Fetches the envelopes (no bodies) of the messages of `folder` in `window`, newest
+    /// first
+    fn fetch_envelopes(
+        &mut self,
+        folder: &str,
+        window: &Window,
+    ) -> Result<Inbox, Box<dyn std::error::Error>>;
+
+    /// Fetches the envelopes of the messages of `folder` matching `query` that fall in
+    /// `window`, newest first
+    fn search(
+        &mut self,
+        folder: &str,
+        query: &SearchQuery,
+        window: &Window,
+    ) -> Result<Inbox, Box<dyn std::error::Error>>;
+
+    /// Fetches a single message by UID, including its body
+    fn fetch_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+    ) -> Result<Email, Box<dyn std::error::Error>>;
+
+    /// Fetches the MIME structure of a message without downloading any content
+    fn fetch_structure(
+        &mut self,
+        folder: &str,
+        uid: u32,
+    ) -> Result<MimePart, Box<dyn std::error::Error>>;
+
+    /// Fetches the content of a single part, with its transfer encoding undone
+    fn fetch_part(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        part: &MimePart,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+
+    /// Fetches a message exactly as stored on the server
+    fn fetch_raw(&mut self, folder: &str, uid: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+
+    /// Adds (`set == true`) or removes `flags` on a message
+    fn flag(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        flags: &[Flag],
+        set: bool,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Moves a message to another folder, where it gets a new UID
+    fn move_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        destination: &str,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Copies a message to another folder, flags included
+    fn copy_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        destination: &str,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Permanently removes a message (STORE \Deleted + EXPUNGE)
+    fn delete(&mut self, folder: &str, uid: u32) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Stores a raw RFC 5322 message in `folder`
+    fn append(
+        &mut self,
+        folder: &str,
+        raw: &[u8],
+        flags: &[Flag],
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Sends a message, reporting which recipients the server took
+    fn send(&mut self, email: Email) -> Result<SendReport, SendError>;
+}
+
+/// The real backend: IMAP for reading and filing, SMTP for sending
+pub struct ImapBackend {
+    session: MailSession,
+    /// File a copy of sent mail in the Sent folder
+    save_sent: bool,
+}
+
+impl ImapBackend {
+    /// Sent mail is saved to the Sent folder unless the provider does that itself
+    pub fn new(session: MailSession) -> ImapBackend {
+        let save_sent = !session.provider().saves_sent_mail();
+        ImapBackend { session, save_sent }
+    }
+
+    pub fn with_save_sent(mut self, save_sent: bool) -> ImapBackend {
+        self.save_sent = save_sent;
+        self
+    }
+
+    /// Marks a message of the selected folder \Deleted and expunges it
+    fn expunge_uid(&mut self, uid: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.session
+            .run(|s| s.uid_store(uid.to_string(), "+FLAGS (\\Deleted)"))?;
+
+        // Without UIDPLUS, EXPUNGE also removes anything else already marked \Deleted
+        if self.session.has_capability("UIDPLUS")? {
+            self.session.run_once(|s| s.uid_expunge(uid.to_string()))?;
+        } else {
+            self.session.run_once(|s| s.expunge())?;
+        }
+        Ok(())
+    }
+}
+
+impl MailBackend for ImapBackend {
+    fn list_folders(
+        &mut self,
+        subscribed_only: bool,
+    ) -> Result<Vec<Folder>, Box<dyn std::error::Error>> {
+        let names = self.session.run(|s| s.list(None, Some("*")))?;
+        let mut folders: Vec<Folder> = names.iter().map(folder_from_name).collect();
+
+        if subscribed_only {
+            // LSUB attributes are not authoritative, so keep the ones LIST reported
+            let subscribed = self.session.run(|s| s.lsub(None, Some("*")))?;
+            folders.retain(|folder| subscribed.iter().any(|name| name.name() == folder.name));
+        }
+
+        Ok(folders)
+    }
+
+    fn folder_status(&mut self, folder: &str) -> Result<FolderStatus, Box<dyn std::error::Error>> {
+        if folder.contains(['\r', '\n']) {
+            return Err(format!("invalid folder name {:?}", folder).into());
+        }
+        // imap's own status() hands the counts to the unsolicited response channel, where
+        // they could only be found by draining the updates for the selected folder too
+        let command = format!(
+            "STATUS \"{}\" (MESSAGES UNSEEN)",
+            folder.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        let response = self
+            .session
+            .run(|s| s.run_command_and_read_response(&command))?;
+        Ok(status_counts(&response, folder))
+    }
+
+    fn fetch_envelopes(
+        &mut self,
+        folder: &str,
+        window: &Window,
+    ) -> Result<Inbox, Box<dyn std::error::Error>> {
+        get_envelopes(&mut self.session, folder, window)
+    }
+
+    fn search(
+        &mut self,
+        folder: &str,
+        query: &SearchQuery,
+        window: &Window,
+    ) -> Result<Inbox, Box<dyn std::error::Error>> {
+        search_envelopes(&mut self.session, folder, query, window)
+    }
+
+    fn fetch_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+    ) -> Result<Email, Box<dyn std::error::Error>> {
+        get_inbox_one(&mut self.session, folder, uid)
+    }
+
+    fn fetch_structure(
+        &mut self,
+        folder: &str,
+        uid: u32,
+    ) -> Result<MimePart, Box<dyn std::error::Error>> {
+        self.session.select(folder)?;
+        let messages = self
+            .session
+            .run(|s| s.uid_fetch(uid.to_string(), "(UID BODYSTRUCTURE)"))?;
+
+        messages
+            .iter()
+            .find(|m| m.uid == Some(uid))
+            .and_then(|m| m.bodystructure())
+            .map(MimePart::from_bodystructure)
+            .ok_or_else(|| "Could not find requested email".into())
+    }
+
+    fn fetch_part(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        part: &MimePart,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let path = section_path(&part.section)
+            .ok_or_else(|| format!("Cannot fetch part '{}' on its own", part.section))?;
+
+        // Only this part is transferred, not the rest of the message
+        self.session.select(folder)?;
+        let query = format!("(UID BODY.PEEK[{}])", part.section);
+        let messages = self.session.run(|s| s.uid_fetch(uid.to_string(), &query))?;
+
+        let data = messages
+            .iter()
+            .find(|m| m.uid == Some(uid))
+            .and_then(|m| m.section(&SectionPath::Part(path.clone(), None)))
+            .ok_or("Could not find requested part")?;
+
+        Ok(decode_transfer(data, &part.encoding)?)
+    }
+
+    fn fetch_raw(&mut self, folder: &str, uid: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.session.select(folder)?;
+        let messages = self
+            .session
+            .run(|s| s.uid_fetch(uid.to_string(), "(UID BODY.PEEK[])"))?;
+
+        messages
+            .iter()
+            .find(|m| m.uid == Some(uid))
+            .and_then(|m| m.body())
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| "Could not find requested email".into())
+    }
+
+    fn flag(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        flags: &[Flag],
+        set: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if flags.is_empty() {
+            return Ok(());
+        }
+        let query = store_query(flags, set);
+
+        self.session.select(folder)?;
+        self.session.run(|s| s.uid_store(uid.to_string(), &query))?;
+        Ok(())
+    }
+
+    fn move_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        destination: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.session.select(folder)?;
+
+        // MOVE (RFC 6851) is atomic; otherwise fall back to COPY + STORE + EXPUNGE
+        if self.session.has_capability("MOVE")? {
+            self.session
+                .run_once(|s| s.uid_mv(uid.to_string(), destination))?;
+            return Ok(());
+        }
+
+        self.session
+            .run_once(|s| s.uid_copy(uid.to_string(), destination))?;
+        self.expunge_uid(uid)
+    }
+
+    fn copy_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        destination: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.session.select(folder)?;
+        self.session
+            .run_once(|s| s.uid_copy(uid.to_string(), destination))?;
+        Ok(())
+    }
+
+    fn delete(&mut self, folder: &str, uid: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.session.select(folder)?;
+        self.expunge_uid(uid)
+    }
+
+    fn append(
+        &mut self,
+        folder: &str,
+        raw: &[u8],
+        flags: &[Flag],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let imap_flags: Vec<imap::types::Flag> = flags
+            .iter()
+            .map(|f| imap::types::Flag::from(f.to_imap()))
+            .collect();
+
+        self.session
+            .run_once(|s| s.append_with_flags(folder, raw, &imap_flags))?;
+        Ok(())
+    }
+
+    fn send(&mut self, email: Email) -> Result<SendReport, SendError> {
+        let message = build_message(&email).map_err(|e| SendError::Message(e.to_string()))?;
+        let mut report = smtp::send_message(
+            self.session.provider(),
+            self.session.credentials(),
+            &message,
+        )?;
+
+        if self.save_sent {
+            report.copy = save_sent_copy(self, &message.formatted());
+        }
+        Ok(report)
+    }
+}
+
+/// Files the bytes of a message just sent in the folder the server advertises as `\Sent`,
+/// marked \Seen
+pub fn save_sent_copy(backend: &mut dyn MailBackend, raw: &[u8]) -> SentCopy {
+    let sent = backend.list_folders(false).map(|folders| {
+        find_special_use(&folders, SpecialUse::Sent).map(|folder| folder.name.clone())
+    });
+
+    match sent {
+        Ok(Some(folder)) => match backend.append(&folder, raw, &[Flag::Seen]) {
+            Ok(()) => SentCopy::Saved(folder),
+            Err(e) => SentCopy::Failed(format!("could not save to {}: {}", folder, e)),
+        },
+        Ok(None) => SentCopy::Failed("the server has no \\Sent folder".to_string()),
+        Err(e) => SentCopy::Failed(format!("could not list folders: {}", e)),
+    }
+}
+
+/// Deletes messages the way a mail client is expected to: moved to the folder the server
+/// advertises as `\Trash`, or expunged for good when there is no Trash, when `folder` is
+/// the Trash itself, or when `permanent` is set.
+///
+/// Returns the name of the Trash folder the messages were moved to, if any.
+pub fn delete_messages(
+    backend: &mut dyn MailBackend,
+    folder: &str,
+    uids: &[u32],
+    permanent: bool,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let trash = if permanent {
+        None
+    } else {
+        let folders = backend.list_folders(false)?;
+        find_special_use(&folders, SpecialUse::Trash)
+            .map(|trash| trash.name.clone())
+            .filter(|trash| trash != folder)
+    };
+
+    for &uid in uids {
+        match &trash {
+            Some(trash) => backend.move_message(folder, uid, trash)?,
+            None => backend.delete(folder, uid)?,
+        }
+    }
+
+    Ok(trash)
+}
+
+fn folder_from_name(name: &imap::types::Name) -> Folder {
+    Folder {
+        name: name.name().to_string(),
+        delimiter: name.delimiter().map(String::from),
+        attributes: name
+            .attributes()
+            .iter()
+            .map(|attr| match attr {
+                NameAttribute::NoInferiors => "\\Noinferiors".to_string(),
+                NameAttribute::NoSelect => "\\Noselect".to_string(),
+                NameAttribute::Marked => "\\Marked".to_string(),
+                NameAttribute::Unmarked => "\\Unmarked".to_string(),
+                NameAttribute::Custom(custom) => custom.to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// Splits an IMAP section number like `2.1` into its parts, or `None` if it isn't one
+fn section_path(section: &str) -> Option<Vec<u32>> {
+    section.split('.').map(|n| n.parse().ok()).collect()
+}
+
+/// Builds the argument of a STORE command, e.g. `+FLAGS (\Seen \Flagged)`
+fn store_query(flags: &[Flag], set: bool) -> String {
+    let list: Vec<String> = flags.iter().map(Flag::to_imap).collect();
+    format!("{}FLAGS ({})", if set { "+" } else { "-" }, list.join(" "))
+}
+
+/// The counts in the untagged STATUS reply for `folder`, ignoring anything else the
+/// server sent along with it
+fn status_counts(response: &[u8], folder: &str) -> FolderStatus {
+    let mut status = FolderStatus::default();
+    let mut rest = response;
+    while let Ok((remaining, reply)) = imap_proto::parse_response(rest) {
+        rest = remaining;
+        let imap_proto::Response::MailboxData(imap_proto::MailboxDatum::Status {
+            mailbox,
+            status: attributes,
+        }) = reply
+        else {
+            continue;
+        };
+        let same = mailbox == folder
+            || (mailbox.eq_ignore_ascii_case("INBOX") && folder.eq_ignore_ascii_case("INBOX"));
+        if !same {
+            continue;
+        }
+        for attribute in attributes {
+            match attribute {
+                imap_proto::StatusAttribute::Messages(n) => status.total = n,
+                imap_proto::StatusAttribute::Unseen(n) => status.unseen = n,
+                _ => {}
+            }
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_tools::MemoryBackend;
+
+    #[test]
+    fn test_status_counts_only_for_the_folder_asked_about() {
+        let response = b"* 12 EXISTS\r\n\
+                         * STATUS Archive (MESSAGES 900 UNSEEN 4)\r\n\
+                         * STATUS \"Work/Inbox\" (MESSAGES 31 UNSEEN 2)\r\n\
+                         * 3 EXPUNGE\r\n";
+        assert_eq!(
+            status_counts(response, "Work/Inbox"),
+            FolderStatus {
+                total: 31,
+                unseen: 2
+            }
+        );
+        assert_eq!(status_counts(response, "Sent"), FolderStatus::default());
+        assert_eq!(
+            status_counts(b"* STATUS inbox (MESSAGES 5 UNSEEN 0)\r\n", "INBOX").total,
+            5
+        );
+    }
+
+    fn backend_with_messages() -> MemoryBackend {
+        let mut backend = MemoryBackend::new();
+        for subject in ["one", "two", "three"] {
+            backend.insert(
+                "INBOX",
+                Email {
+                    subject: subject.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+        backend
+    }
+
+    #[test]
+    fn test_delete_moves_to_trash() {
+        let mut backend = backend_with_messages();
+        backend.create_special_folder("Bin", SpecialUse::Trash);
+
+        let trash = delete_messages(&mut backend, "INBOX", &[1, 3], false).unwrap();
+        assert_eq!(trash.as_deref(), Some("Bin"));
+        assert_eq!(
+            backend
+                .fetch_envelopes("INBOX", &Window::All)
+                .unwrap()
+                .inbox
+                .len(),
+            1
+        );
+        assert_eq!(
+            backend
+                .fetch_envelopes("Bin", &Window::All)
+                .unwrap()
+                .inbox
+                .len(),
+            2
+        );
+
+        // Deleting from the Trash itself expunges
+        let trash = delete_messages(&mut backend, "Bin", &[1], false).unwrap();
+        assert_eq!(trash, None);
+        assert_eq!(
+            backend
+                .fetch_envelopes("Bin", &Window::All)
+                .unwrap()
+                .inbox
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_delete_without_trash_or_permanent_expunges() {
+        let mut backend = backend_with_messages();
+        assert_eq!(
+            delete_messages(&mut backend, "INBOX", &[2], false).unwrap(),
+            None
+        );
+
+        backend.create_special_folder("Trash", SpecialUse::Trash);
+        assert_eq!(
+            delete_messages(&mut backend, "INBOX", &[1], true).unwrap(),
+            None
+        );
+
+        assert_eq!(
+            backend
+                .fetch_envelopes("INBOX", &Window::All)
+                .unwrap()
+                .inbox
+                .len(),
+            1
+        );
+        assert!(
+            backend
+                .fetch_envelopes("Trash", &Window::All)
+                .unwrap()
+                .inbox
+                .is_empty()
+        );
+        assert!(delete_messages(&mut backend, "INBOX", &[42], false).is_err());
+    }
+
+    fn outgoing() -> Email {
+        Email {
+            from: "me@example.com".parse().unwrap(),
+            to: vec!["ann@example.com".parse().unwrap()],
+            subject: "Minutes".to_string(),
+            body: "Attached below.".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sent_mail_is_saved_to_sent_folder() {
+        let mut backend = MemoryBackend::new();
+        backend.save_sent = true;
+        backend.create_special_folder("Sent Items", SpecialUse::Sent);
+
+        let report = backend.send(outgoing()).unwrap();
+        assert_eq!(report.copy, SentCopy::Saved("Sent Items".to_string()));
+
+        let copy = backend.fetch_message("Sent Items", 1).unwrap();
+        assert_eq!(copy.message_id, report.message_id);
+        assert_eq!(copy.subject, "Minutes");
+        assert_eq!(copy.flags, vec![Flag::Seen]);
+    }
+
+    #[test]
+    fn test_sent_copy_is_optional_and_reports_failure() {
+        let mut backend = MemoryBackend::new();
+        backend.create_special_folder("Sent", SpecialUse::Sent);
+        assert_eq!(backend.send(outgoing()).unwrap().copy, SentCopy::NotSaved);
+        assert_eq!(backend.folder_status("Sent").unwrap().total, 0);
+
+        // Without a \Sent folder the message still goes out
+        let mut backend = MemoryBackend::new();
+        backend.save_sent = true;
+        let report = backend.send(outgoing()).unwrap();
+        assert!(matches!(report.copy, SentCopy::Failed(_)));
+        assert_eq!(backend.sent.len(), 1);
+    }
+
+    #[test]
+    fn test_window_sequence_ranges() {
+        assert_eq!(Window::All.sequence_range(10).as_deref(), Some("1:10"));
+        assert_eq!(Window::All.sequence_range(0), None);
+        assert_eq!(
+            Window::page(1, 3).sequence_range(10).as_deref(),
+            Some("8:10")
+        );
+        assert_eq!(
+            Window::page(4, 3).sequence_range(10).as_deref(),
+            Some("1:1")
+        );
+        assert_eq!(Window::page(5, 3).sequence_range(10), None);
+        assert_eq!(
+            Window::page(1, 50).sequence_range(10).as_deref(),
+            Some("1:10")
+        );
+        assert_eq!(Window::page(1, 0).sequence_range(10), None);
+    }
+
+    #[test]
+    fn test_window_selects_uids() {
+        let uids = [3, 5, 8, 13, 21];
+        assert_eq!(Window::All.select_uids(&uids), uids);
+        assert_eq!(Window::page(1, 2).select_uids(&uids), [13, 21]);
+        assert_eq!(Window::page(3, 2).select_uids(&uids), [3]);
+        assert!(Window::page(4, 2).select_uids(&uids).is_empty());
+        assert_eq!(
+            "5:13".parse::<Window>().unwrap().select_uids(&uids),
+            [5, 8, 13]
+        );
+    }
+
+    #[test]
+    fn test_window_uid_range_parsing() {
+        assert_eq!(
+            "100:200".parse::<Window>(),
+            Ok(Window::Uids {
+                first: 100,
+                last: Some(200)
+            })
+        );
+        assert_eq!(
+            "100:*".parse::<Window>(),
+            Ok(Window::Uids {
+                first: 100,
+                last: None
+            })
+        );
+        assert!("42".parse::<Window>().unwrap().contains_uid(42));
+        assert!(!"100:*".parse::<Window>().unwrap().contains_uid(99));
+        assert!("abc:5".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn test_store_query() {
+        assert_eq!(
+            store_query(&[Flag::Seen, Flag::Keyword("$Work".to_string())], true),
+            "+FLAGS (\\Seen $Work)"
+        );
+        assert_eq!(store_query(&[Flag::Flagged], false), "-FLAGS (\\Flagged)");
+    }
+
+    #[test]
+    fn test_section_path() {
+        assert_eq!(section_path("1"), Some(vec![1]));
+        assert_eq!(section_path("2.1.3"), Some(vec![2, 1, 3]));
+        assert_eq!(section_path(""), None);
+        assert_eq!(section_path("1.x"), None);
+    }
+}
@@ -1,9 +1,23 @@
-
-use clap::{Parser, Subcommand};
+use super::{Address, Flag, Window};
+use clap::{ArgGroup, Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(name = "hermes")]
 pub struct Cli {
+    /// Use a built-in in-memory mailbox instead of a real account
+    #[arg(long, global = true)]
+    pub demo: bool,
+
+    /// Config file to read accounts from, instead of hermes/config.toml in the user's
+    /// config directory
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Account to use, by its name in the config file; defaults to `default_account`
+    #[arg(long, global = true)]
+    pub account: Option<String>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -11,28 +25,398 @@ pub struct Cli {
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Inbox {
+        /// Mailbox to operate on; defaults to the account's default folder
+        #[arg(long, global = true)]
+        folder: Option<String>,
+
         #[command(subcommand)]
         command: InboxCommands,
     },
 
+    /// List the accounts in the config file as `NAME<TAB>ADDRESS<TAB>SERVER`, marking
+    /// the default one
+    Accounts,
+
+    /// Keep account passwords (or OAuth2 refresh tokens) in a local file encrypted with a
+    /// master passphrase, for accounts with neither `password` nor `password_command` in
+    /// the config file
+    Credentials {
+        #[command(subcommand)]
+        command: CredentialCommands,
+    },
+
+    /// List mailboxes with unread/total message counts
+    Folders {
+        /// Only show subscribed mailboxes
+        #[arg(long)]
+        subscribed: bool,
+    },
+
+    /// Send a message; every --to, --cc, --bcc and --reply-to can be repeated
+    ///
+    /// Exits with status 1 if nothing was sent, and 3 if the message went out but the
+    /// server refused some of the recipients.
+    #[command(group(ArgGroup::new("recipients").required(true).multiple(true).args(["to", "cc", "bcc"])))]
     Send {
+        /// Sender, e.g. `me@example.com` or `"Jane Doe <jane@example.com>"`; defaults to
+        /// the account's first identity
+        #[arg(long, value_parser = parse_mailbox)]
+        from: Option<Address>,
+        #[arg(long, value_parser = parse_mailbox)]
+        to: Vec<Address>,
+        #[arg(long, value_parser = parse_mailbox)]
+        cc: Vec<Address>,
+        /// Recipient left out of the headers the others see
+        #[arg(long, value_parser = parse_mailbox)]
+        bcc: Vec<Address>,
+        /// Where replies should go, if not to --from
+        #[arg(long, value_parser = parse_mailbox)]
+        reply_to: Vec<Address>,
         #[arg(long)]
-        from: String,
+        subject: String,
+        /// Message text; read from --body-file or standard input when not given
+        #[arg(long, conflicts_with = "body_file")]
+        body: Option<String>,
+        /// File holding the message text
         #[arg(long)]
-        to: Vec<String>,
+        body_file: Option<PathBuf>,
+        /// File with an HTML version of the body, sent as an alternative to the text
         #[arg(long)]
+        html: Option<PathBuf>,
+        /// File to attach; repeat for more than one
+        #[arg(long)]
+        attach: Vec<PathBuf>,
+    },
+
+    /// Write a message in $EDITOR (vi if unset), review it, then send it
+    ///
+    /// The editor opens on To, Cc, Bcc and Subject lines above the body; options given
+    /// here fill them in. The message can also be saved to the Drafts folder to finish
+    /// later with `hermes drafts edit`. Exits like `send`.
+    Compose {
+        /// Sender; defaults to the account's first identity
+        #[arg(long, value_parser = parse_mailbox)]
+        from: Option<Address>,
+        #[arg(long, value_parser = parse_mailbox)]
+        to: Vec<Address>,
+        #[arg(long, value_parser = parse_mailbox)]
+        cc: Vec<Address>,
+        #[arg(long, value_parser = parse_mailbox)]
+        bcc: Vec<Address>,
+        #[arg(long, value_parser = parse_mailbox)]
+        reply_to: Vec<Address>,
+        #[arg(long, default_value = "")]
         subject: String,
+        /// File to attach; repeat for more than one
+        #[arg(long)]
+        attach: Vec<PathBuf>,
+    },
+
+    /// Reply to a message, editing the quoted reply in $EDITOR first; exits like `send`
+    Reply {
+        uid: u32,
+        /// Mailbox to operate on; defaults to the account's default folder
+        #[arg(long)]
+        folder: Option<String>,
+        /// Also reply to everyone else the message went to
+        #[arg(long)]
+        all: bool,
+        /// Sender; defaults to the identity the message was sent to
+        #[arg(long, value_parser = parse_mailbox)]
+        from: Option<Address>,
+    },
+
+    /// Forward a message as an attachment, editing the covering note in $EDITOR first;
+    /// recipients can also be filled in there. Exits like `send`.
+    Forward {
+        uid: u32,
+        /// Mailbox to operate on; defaults to the account's default folder
+        #[arg(long)]
+        folder: Option<String>,
+        #[arg(long, value_parser = parse_mailbox)]
+        to: Vec<Address>,
+        #[arg(long, value_parser = parse_mailbox)]
+        cc: Vec<Address>,
+        /// Sender; defaults to the identity the message was sent to
+        #[arg(long, value_parser = parse_mailbox)]
+        from: Option<Address>,
+    },
+
+    /// List drafts saved from the editor as `UID<TAB>TO<TAB>SUBJECT`, or resume one
+    Drafts {
+        #[command(subcommand)]
+        command: Option<DraftCommands>,
+    },
+
+    /// Search a folder on the server, e.g. `from:alice subject:"weekly report" unseen`
+    Search {
+        /// Terms: from:, to:, subject:, body:, since:YYYY-MM-DD, before:YYYY-MM-DD,
+        /// larger:SIZE, unseen, flagged, or bare words to match anywhere
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Mailbox to operate on; defaults to the account's default folder
+        #[arg(long)]
+        folder: Option<String>,
+        /// Only list this many of the newest matches
+        #[arg(long)]
+        limit: Option<u32>,
+        /// Which page of `--limit` matches to list, starting at 1
+        #[arg(long, default_value_t = 1, requires = "limit")]
+        page: u32,
+        /// Only list matches in a UID range such as `100:200` or `100:*`
+        #[arg(long, conflicts_with = "limit")]
+        uids: Option<Window>,
+    },
+
+    /// Add or remove flags, e.g. `hermes flag 42 --add seen --remove flagged`
+    #[command(group(ArgGroup::new("change").required(true).multiple(true).args(["add", "remove"])))]
+    Flag {
+        #[arg(required = true)]
+        uids: Vec<u32>,
+        /// Mailbox to operate on; defaults to the account's default folder
+        #[arg(long)]
+        folder: Option<String>,
+        /// Flag to set: seen, answered, flagged, deleted, draft or a keyword like `$Work`
+        #[arg(long)]
+        add: Vec<Flag>,
+        /// Flag to clear
+        #[arg(long)]
+        remove: Vec<Flag>,
+    },
+
+    /// File messages into another folder
+    Move {
+        #[arg(required = true)]
+        uids: Vec<u32>,
+        /// Mailbox to operate on; defaults to the account's default folder
+        #[arg(long)]
+        folder: Option<String>,
+        /// Destination folder
+        #[arg(long)]
+        to: String,
+    },
+
+    /// Copy messages into another folder, leaving the originals in place
+    Copy {
+        #[arg(required = true)]
+        uids: Vec<u32>,
+        /// Mailbox to operate on; defaults to the account's default folder
+        #[arg(long)]
+        folder: Option<String>,
+        /// Destination folder
+        #[arg(long)]
+        to: String,
+    },
+
+    /// Move messages to the Trash, or expunge them if there is no Trash folder
+    Delete {
+        #[arg(required = true)]
+        uids: Vec<u32>,
+        /// Mailbox to operate on; defaults to the account's default folder
+        #[arg(long)]
+        folder: Option<String>,
+        /// Expunge immediately instead of moving to the Trash
+        #[arg(long)]
+        permanent: bool,
+    },
+
+    /// List a message's attachments as `INDEX<TAB>FILENAME<TAB>TYPE<TAB>SIZE`
+    Attachments {
+        uid: u32,
+        /// Mailbox to operate on; defaults to the account's default folder
         #[arg(long)]
-        body: String,
+        folder: Option<String>,
+    },
+
+    /// Save attachments to disk, e.g. `hermes save-attachment 42 --all --dir ~/Downloads`
+    #[command(group(ArgGroup::new("which").required(true).args(["index", "all"])))]
+    SaveAttachment {
+        uid: u32,
+        /// Mailbox to operate on; defaults to the account's default folder
+        #[arg(long)]
+        folder: Option<String>,
+        /// Which attachment to save, as numbered by `hermes attachments`
+        #[arg(long)]
+        index: Option<usize>,
+        /// Save every attachment
+        #[arg(long)]
+        all: bool,
+        /// Directory to save into; existing files are never overwritten
+        #[arg(long)]
+        dir: PathBuf,
     },
 
     /// Launch the TUI (Neomutt-style interface)
     Ui,
 }
 
+/// Parses an address and checks that SMTP will accept it, so a typo in the fifth
+/// recipient fails before anything is sent
+fn parse_mailbox(s: &str) -> Result<Address, String> {
+    let address: Address = s.parse()?;
+    address
+        .to_mailbox()
+        .map_err(|e| format!("invalid email address '{}': {}", address.email(), e))?;
+    Ok(address)
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CredentialCommands {
+    /// Store an account's password, asking for it and the passphrase
+    Set { account: String },
+    /// Sign in to an OAuth2 account in the browser and store its refresh token
+    Authorize { account: String },
+    /// Forget an account's password or refresh token
+    Remove { account: String },
+    /// List the accounts with a stored password or refresh token
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DraftCommands {
+    /// Open a draft in $EDITOR to finish it; it is deleted once sent, or replaced when
+    /// saved again
+    Edit { uid: u32 },
+}
+
 #[derive(Subcommand, Debug)]
 pub enum InboxCommands {
-    One { id: u32 },
-    All,
+    /// Show a single message by UID
+    One {
+        uid: u32,
+        /// Fail instead of reading a different message if the folder's UIDVALIDITY changed
+        #[arg(long)]
+        uid_validity: Option<u32>,
+    },
+    /// List messages, newest first, as `UID<TAB>FROM<TAB>SUBJECT`
+    All {
+        /// Only list this many messages
+        #[arg(long)]
+        limit: Option<u32>,
+        /// Which page of `--limit` messages to list, starting at 1
+        #[arg(long, default_value_t = 1, requires = "limit")]
+        page: u32,
+        /// Only list messages in a UID range such as `100:200` or `100:*`
+        #[arg(long, conflicts_with = "limit")]
+        uids: Option<Window>,
+    },
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_takes_every_recipient() {
+        let cli = Cli::try_parse_from([
+            "hermes",
+            "send",
+            "--from",
+            "Me <me@example.com>",
+            "--to",
+            "a@example.com",
+            "--to",
+            "B <b@example.com>",
+            "--cc",
+            "c@example.com",
+            "--bcc",
+            "d@example.com",
+            "--reply-to",
+            "list@example.com",
+            "--subject",
+            "Hi",
+            "--body",
+            "Hello",
+        ])
+        .unwrap();
+
+        let Commands::Send {
+            to,
+            cc,
+            bcc,
+            reply_to,
+            ..
+        } = cli.command
+        else {
+            panic!("expected send");
+        };
+        assert_eq!(to.len(), 2);
+        assert_eq!(to[1].to_string(), "B <b@example.com>");
+        assert_eq!(cc[0].email(), "c@example.com");
+        assert_eq!(bcc[0].email(), "d@example.com");
+        assert_eq!(reply_to[0].email(), "list@example.com");
+    }
+
+    #[test]
+    fn test_account_options_are_global() {
+        let cli = Cli::try_parse_from([
+            "hermes",
+            "inbox",
+            "all",
+            "--account",
+            "work",
+            "--config",
+            "/tmp/h.toml",
+        ])
+        .unwrap();
+        assert_eq!(cli.account.as_deref(), Some("work"));
+        assert_eq!(cli.config, Some(PathBuf::from("/tmp/h.toml")));
+
+        let Commands::Inbox { folder, .. } = cli.command else {
+            panic!("expected inbox");
+        };
+        assert_eq!(folder, None);
+
+        // Commands that take --folder leave it to the account when it is omitted
+        let cli = Cli::try_parse_from(["hermes", "move", "7", "--to", "Archive"]).unwrap();
+        let Commands::Move { folder, .. } = cli.command else {
+            panic!("expected move");
+        };
+        assert_eq!(folder, None);
+    }
+
+    #[test]
+    fn test_send_body_sources() {
+        let send = |extra: &[&str]| {
+            let mut args = vec!["hermes", "send", "--from", "me@x.org", "--to", "a@x.org"];
+            args.extend_from_slice(&["--subject", "Hi"]);
+            args.extend_from_slice(extra);
+            Cli::try_parse_from(args)
+        };
+
+        // Without --body or --body-file the text comes from standard input
+        assert!(send(&[]).is_ok());
+        assert!(send(&["--body-file", "msg.txt"]).is_ok());
+        assert!(send(&["--body", "Hello", "--body-file", "msg.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_send_rejects_bad_or_missing_recipients() {
+        let send = |extra: &[&str]| {
+            let mut args = vec![
+                "hermes",
+                "send",
+                "--from",
+                "me@example.com",
+                "--subject",
+                "Hi",
+                "--body",
+                "Hello",
+            ];
+            args.extend_from_slice(extra);
+            Cli::try_parse_from(args)
+        };
+
+        assert!(send(&["--bcc", "hidden@example.com"]).is_ok());
+        assert!(send(&[]).is_err());
+
+        let error = send(&["--to", "a@example.com", "--cc", "not an address"])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("--cc"), "{}", error);
+        assert!(error.contains("not an address"), "{}", error);
+
+        // Parses as an address, but SMTP would refuse it
+        assert!(send(&["--to", "a b@example.com"]).is_err());
+    }
+}
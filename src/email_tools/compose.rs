@@ -0,0 +1,344 @@
+use super::address::parse_address_list;
+use super::mime::format_size;
+use super::{Address, Email};
+use std::io::{BufRead, Write};
+use std::process::Command;
+
+/// The editor to write messages in: $EDITOR, or vi
+pub fn editor() -> String {
+    std::env::var("EDITOR")
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// The text handed to the editor: editable header lines, a blank line, then the body
+pub fn template(email: &Email) -> String {
+    format!(
+        "To: {}\nCc: {}\nBcc: {}\nSubject: {}\n\n{}",
+        address_line(&email.to),
+        address_line(&email.cc),
+        address_line(&email.bcc),
+        email.subject,
+        email.body
+    )
+}
+
+fn address_line(addrs: &[Address]) -> String {
+    addrs
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Reads an edited template back into `draft`. Header lines may be folded onto indented
+/// continuation lines; everything after the first blank line is the body. Recipients may
+/// be left out, as in a draft; see `missing_recipients`.
+pub fn parse(text: &str, draft: &Email) -> Result<Email, String> {
+    let mut email = draft.clone();
+    let (head, body) = match text.split_once("\n\n") {
+        Some((head, body)) => (head, body),
+        None => (text.trim_end_matches('\n'), ""),
+    };
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in head.lines() {
+        if line.starts_with([' ', '\t'])
+            && let Some((_, value)) = headers.last_mut()
+        {
+            value.push(' ');
+            value.push_str(line.trim());
+            continue;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("expected a header line such as 'To: ...', found '{}'", line))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    for (name, value) in headers {
+        match name.to_ascii_lowercase().as_str() {
+            "to" => email.to = checked_list(&value)?,
+            "cc" => email.cc = checked_list(&value)?,
+            "bcc" => email.bcc = checked_list(&value)?,
+            "subject" => email.subject = value,
+            _ => {
+                return Err(format!(
+                    "unknown header '{}' (only To, Cc, Bcc and Subject can be edited)",
+                    name
+                ));
+            }
+        }
+    }
+    let body = body.trim_end();
+    email.body = if body.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", body)
+    };
+    Ok(email)
+}
+
+/// Why `email` can't be sent yet, if it has nobody to go to
+pub fn missing_recipients(email: &Email) -> Option<&'static str> {
+    (email.to.is_empty() && email.cc.is_empty() && email.bcc.is_empty())
+        .then_some("the message has no recipients")
+}
+
+/// An address list that SMTP will also accept
+fn checked_list(value: &str) -> Result<Vec<Address>, String> {
+    let list = parse_address_list(value)?;
+    for addr in &list {
+        addr.to_mailbox()
+            .map_err(|e| format!("invalid email address '{}': {}", addr, e))?;
+    }
+    Ok(list)
+}
+
+/// What is about to be sent, for a last look before sending
+pub fn summary(email: &Email) -> String {
+    let mut lines = vec![format!("From:    {}", email.from)];
+    for (name, addrs) in [("To:", &email.to), ("Cc:", &email.cc), ("Bcc:", &email.bcc)] {
+        if !addrs.is_empty() {
+            lines.push(format!("{:<8} {}", name, address_line(addrs)));
+        }
+    }
+    lines.push(format!("Subject: {}", email.subject));
+    for attachment in &email.attachments {
+        lines.push(format!(
+            "Attach:  {} ({})",
+            attachment.filename,
+            format_size(attachment.data.len())
+        ));
+    }
+    lines.push(format!("Body:    {} line(s)", email.body.lines().count()));
+    lines.join("\n")
+}
+
+/// Opens `text` in `editor` and returns what was saved. `editor` may carry arguments,
+/// e.g. `code --wait`.
+pub fn edit_text(text: &str, editor: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut file = tempfile::Builder::new()
+        .prefix("hermes-")
+        .suffix(".eml")
+        .tempfile()?;
+    file.write_all(text.as_bytes())?;
+    file.flush()?;
+
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or("no editor configured")?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(file.path())
+        .status()
+        .map_err(|e| format!("could not run '{}': {}", program, e))?;
+    if !status.success() {
+        return Err(format!("'{}' exited with {}", program, status).into());
+    }
+
+    // Read by path: editors often save by replacing the file
+    Ok(std::fs::read_to_string(file.path())?)
+}
+
+/// `draft` with `signature` below the space left for writing, above any quoted or
+/// forwarded text
+pub fn sign(draft: Email, signature: &str) -> Email {
+    let rest = draft.body.trim_start_matches('\n');
+    let body = if rest.is_empty() {
+        format!("\n\n{}", signature_block(signature))
+    } else {
+        format!("\n\n{}\n{}", signature_block(signature), rest)
+    };
+    Email { body, ..draft }
+}
+
+/// `body` as written in full, with `signature` at the end
+pub fn append_signature(body: &str, signature: &str) -> String {
+    format!("{}\n\n{}", body.trim_end(), signature_block(signature))
+}
+
+/// The signature under its `-- ` separator line
+fn signature_block(signature: &str) -> String {
+    format!("-- \n{}\n", signature.trim_end())
+}
+
+/// What the user decided to do with a message written in the editor
+pub enum Composed {
+    Send(Email),
+    /// Keep it in the Drafts folder to finish later
+    Draft(Email),
+    Discard,
+}
+
+/// Edits `draft` until it parses and the user decides to send it, save it as a draft or
+/// give up on it
+pub fn compose(
+    draft: &Email,
+    editor: &str,
+    input: &mut dyn BufRead,
+) -> Result<Composed, Box<dyn std::error::Error>> {
+    let mut text = template(draft);
+
+    loop {
+        text = edit_text(&text, editor)?;
+
+        match parse(&text, draft) {
+            Ok(email) => {
+                println!("{}", summary(&email));
+                if email.body.trim().is_empty() {
+                    println!("Warning: the body is empty");
+                }
+                let answer = match missing_recipients(&email) {
+                    Some(problem) => {
+                        eprintln!("Can't send yet: {}", problem);
+                        ask("[e]dit, save as [d]raft or [n]o: ", input)?
+                    }
+                    None => ask("Send it? [y]es, [e]dit, save as [d]raft, [n]o: ", input)?,
+                };
+                match answer {
+                    'y' if missing_recipients(&email).is_none() => {
+                        return Ok(Composed::Send(email));
+                    }
+                    'd' => return Ok(Composed::Draft(email)),
+                    'e' => {}
+                    _ => return Ok(Composed::Discard),
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                if ask("[e]dit again or [n]o: ", input)? != 'e' {
+                    return Ok(Composed::Discard);
+                }
+            }
+        }
+    }
+}
+
+/// The first letter of the answer, lowercased; end of input counts as no
+fn ask(prompt: &str, input: &mut dyn BufRead) -> Result<char, Box<dyn std::error::Error>> {
+    print!("{}", prompt);
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer
+        .trim()
+        .chars()
+        .next()
+        .map(|c| c.to_ascii_lowercase())
+        .unwrap_or('n'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draft() -> Email {
+        Email {
+            from: "me@example.com".parse().unwrap(),
+            to: vec![],
+            cc: vec![],
+            bcc: vec![],
+            subject: String::new(),
+            body: String::new(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_template_roundtrip() {
+        let email = Email {
+            to: vec!["\"Doe, Jane\" <jane@example.com>".parse().unwrap()],
+            bcc: vec!["boss@example.com".parse().unwrap()],
+            subject: "Q4 numbers".to_string(),
+            body: "Attached.\n\nBob\n".to_string(),
+            ..draft()
+        };
+
+        let parsed = parse(&template(&email), &draft()).unwrap();
+        assert_eq!(parsed.to, email.to);
+        assert_eq!(parsed.cc, vec![]);
+        assert_eq!(parsed.bcc, email.bcc);
+        assert_eq!(parsed.subject, "Q4 numbers");
+        assert_eq!(parsed.body, "Attached.\n\nBob\n");
+        assert_eq!(parsed.from, email.from);
+    }
+
+    #[test]
+    fn test_parse_edited_headers() {
+        let text = "to: ann@example.com,\n  bob@example.com\nSubject: Lunch?\n\nNoon?\n\n\n";
+        let email = parse(text, &draft()).unwrap();
+        assert_eq!(email.to.len(), 2);
+        assert_eq!(email.to[1].email(), "bob@example.com");
+        assert_eq!(email.subject, "Lunch?");
+        assert_eq!(email.body, "Noon?\n");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        // Fine for a draft, but not for sending
+        let no_recipients = parse("To:\nSubject: Hi\n\nHello", &draft()).unwrap();
+        assert_eq!(
+            missing_recipients(&no_recipients),
+            Some("the message has no recipients")
+        );
+
+        assert!(parse("To: bob\n\nHello", &draft()).is_err());
+        assert!(parse("From: x@example.com\nTo: a@example.com\n\nHi", &draft()).is_err());
+        assert!(parse("To: a@example.com\nnot a header\n\nHi", &draft()).is_err());
+    }
+
+    #[test]
+    fn test_summary() {
+        let email = Email {
+            to: vec!["ann@example.com".parse().unwrap()],
+            subject: "Hi".to_string(),
+            body: "one\ntwo\n".to_string(),
+            ..draft()
+        };
+        assert_eq!(
+            summary(&email),
+            "From:    me@example.com\nTo:      ann@example.com\nSubject: Hi\nBody:    2 line(s)"
+        );
+    }
+
+    #[test]
+    fn test_signatures() {
+        let signed = sign(draft(), "Jane\nACME");
+        assert_eq!(signed.body, "\n\n-- \nJane\nACME\n");
+
+        let reply = Email {
+            body: "\n\nAnn wrote:\n> Hi\n".to_string(),
+            ..draft()
+        };
+        assert_eq!(
+            sign(reply, "Jane\n").body,
+            "\n\n-- \nJane\n\nAnn wrote:\n> Hi\n"
+        );
+        assert_eq!(
+            append_signature("Hello\n\n", "Jane"),
+            "Hello\n\n-- \nJane\n"
+        );
+    }
+
+    #[test]
+    fn test_compose_with_scripted_editor() {
+        // sed stands in for the user, filling in the recipient
+        let editor = "sed -i s/^To:/To:ann@example.com/";
+        let mut input = "y\n".as_bytes();
+
+        let Composed::Send(email) = compose(&draft(), editor, &mut input).unwrap() else {
+            panic!("expected the message to be sent");
+        };
+        assert_eq!(email.to[0].email(), "ann@example.com");
+
+        // Without recipients it can't be sent, only kept as a draft
+        let mut input = "y\n".as_bytes();
+        let sent = compose(&draft(), "true", &mut input).unwrap();
+        assert!(matches!(sent, Composed::Discard));
+        let mut input = "d\n".as_bytes();
+        let kept = compose(&draft(), "true", &mut input).unwrap();
+        assert!(matches!(kept, Composed::Draft(email) if email.to.is_empty()));
+    }
+}
@@ -0,0 +1,740 @@
+use super::credentials::{CredentialStore, Secret, password_from_command};
+use super::oauth::{OAuthConfig, TokenSource};
+use super::provider::{EmailProvider, Security, Server, ServerConfig, default_port};
+use super::{Address, UserCredentials};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// A mail account ready to connect: where, as whom, and what to send as
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub name: String,
+    pub provider: EmailProvider,
+    /// Login for both IMAP and SMTP
+    pub username: String,
+    pub password: PasswordSource,
+    /// Log in with OAuth2 instead of a password; the password source then gives the
+    /// refresh token
+    pub oauth2: Option<OAuthConfig>,
+    /// Addresses to send from, the first being the default
+    pub identities: Vec<Address>,
+    /// The folder the TUI opens and commands act on when not given `--folder`
+    pub default_folder: String,
+    /// Added under new messages, replies and forwards
+    pub signature: Option<String>,
+    /// Whether to file sent mail in the Sent folder; `None` leaves it to the provider
+    pub save_sent: Option<bool>,
+}
+
+/// Where an account's password comes from
+#[derive(Debug, Clone)]
+pub enum PasswordSource {
+    /// Written out in the config file or EMAIL_PASSWORD
+    Plain(Secret),
+    /// Printed by a command, e.g. `pass show mail/work`
+    Command(String),
+    /// Kept under the account's name in the encrypted credentials file at this path
+    Stored(PathBuf),
+}
+
+impl Account {
+    /// `folder`, or the default folder if none was given
+    pub fn folder(&self, folder: Option<String>) -> String {
+        folder.unwrap_or_else(|| self.default_folder.clone())
+    }
+
+    /// The login for this account, running its password command or unlocking the
+    /// credentials file as needed. `passphrase` is only asked for in the latter case.
+    pub fn credentials<F>(&self, passphrase: F) -> Result<UserCredentials, String>
+    where
+        F: FnOnce() -> Result<Secret, String>,
+    {
+        let (what, command) = match self.oauth2 {
+            Some(_) => ("refresh token", "authorize"),
+            None => ("password", "set"),
+        };
+        let (secret, passphrase) = match &self.password {
+            PasswordSource::Plain(password) => (password.clone(), None),
+            PasswordSource::Command(command) => (
+                password_from_command(command)
+                    .map_err(|e| format!("password_command of account '{}': {}", self.name, e))?,
+                None,
+            ),
+            PasswordSource::Stored(path) => {
+                if !path.exists() {
+                    return Err(format!(
+                        "no {} for account '{}': set password_command, or store one \
+                         with `hermes credentials {} {}`",
+                        what, self.name, command, self.name
+                    ));
+                }
+                let passphrase = passphrase()?;
+                let store = CredentialStore::open(path, &passphrase)?;
+                let secret = store.get(&self.name).cloned().ok_or_else(|| {
+                    format!(
+                        "{} has no {} for account '{}'; add one with \
+                         `hermes credentials {} {}`",
+                        path.display(),
+                        what,
+                        self.name,
+                        command,
+                        self.name
+                    )
+                })?;
+                (secret, Some(passphrase))
+            }
+        };
+
+        Ok(match &self.oauth2 {
+            Some(config) => UserCredentials::oauth2(
+                self.username.clone(),
+                TokenSource::new(config.clone(), secret)
+                    .on_rotate(self.rotated_token_keeper(passphrase)),
+            ),
+            None => UserCredentials::new(self.username.clone(), secret),
+        })
+    }
+
+    /// Puts a refresh token the provider rotated back into the credentials file it came
+    /// from, unlocked with the same `passphrase`. A token from the config file or a
+    /// password command can't be updated from here.
+    fn rotated_token_keeper(
+        &self,
+        passphrase: Option<Secret>,
+    ) -> impl Fn(&Secret) -> Result<(), String> + Send + Sync + 'static {
+        let name = self.name.clone();
+        let source = self.password.clone();
+
+        move |token| match (&source, &passphrase) {
+            (PasswordSource::Stored(path), Some(passphrase)) => {
+                let mut store = CredentialStore::open(path, passphrase)?;
+                store.set(&name, token.clone());
+                store.save(path, passphrase)
+            }
+            _ => Err(format!(
+                "the password_command of account '{}' still gives the old one, which the \
+                 provider may stop accepting; drop password_command and run \
+                 `hermes credentials authorize {}` so that it can be kept up to date",
+                name, name
+            )),
+        }
+    }
+
+    /// The account described by EMAIL_* variables, used when there is no config file
+    pub fn from_env() -> Result<Account, String> {
+        Account::from_lookup(|key| env::var(key).ok())
+    }
+
+    fn from_lookup<F>(lookup: F) -> Result<Account, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let username = lookup("EMAIL_USERNAME")
+            .ok_or("EMAIL_USERNAME not set. Add it to your .env file or export it.")?;
+        let password = match (lookup("EMAIL_PASSWORD"), lookup("EMAIL_PASSWORD_COMMAND")) {
+            (Some(password), _) => PasswordSource::Plain(Secret::new(password)),
+            (None, Some(command)) => PasswordSource::Command(command),
+            (None, None) => match CredentialStore::default_path() {
+                Some(path) => PasswordSource::Stored(path),
+                None => {
+                    return Err(
+                        "EMAIL_PASSWORD not set. Add it to your .env file or export it.".into(),
+                    );
+                }
+            },
+        };
+        let provider = EmailProvider::from_lookup(&lookup)
+            .map_err(|e| format!("Invalid provider configuration: {}", e))?;
+        let save_sent = match lookup("EMAIL_SAVE_SENT") {
+            Some(value) => Some(parse_switch(&value).ok_or_else(|| {
+                format!("EMAIL_SAVE_SENT must be true or false, not '{}'", value)
+            })?),
+            None => None,
+        };
+
+        Ok(Account {
+            name: "default".to_string(),
+            provider,
+            // A login name that isn't an address leaves nothing to send from
+            identities: username.parse().into_iter().collect(),
+            username,
+            password,
+            oauth2: None,
+            default_folder: "INBOX".to_string(),
+            signature: None,
+            save_sent,
+        })
+    }
+
+    /// The account behind `--demo`, addressed the way the demo mailbox's messages are
+    pub fn demo() -> Account {
+        Account {
+            name: "demo".to_string(),
+            provider: EmailProvider::Google,
+            username: "me@example.com".to_string(),
+            password: PasswordSource::Plain(Secret::default()),
+            oauth2: None,
+            identities: vec!["me@example.com".parse().unwrap()],
+            default_folder: "INBOX".to_string(),
+            signature: None,
+            save_sent: None,
+        }
+    }
+
+    /// The address new messages are sent from, unless told otherwise
+    pub fn address(&self) -> Option<&Address> {
+        self.identities.first()
+    }
+}
+
+/// The accounts of a config file
+#[derive(Debug)]
+pub struct Config {
+    /// Accounts in name order
+    pub accounts: Vec<Account>,
+    default_account: Option<String>,
+    /// Where passwords not given in the file are kept
+    pub credentials_file: Option<PathBuf>,
+}
+
+impl Config {
+    /// `hermes/config.toml` in the user's config directory, e.g. `~/.config` or
+    /// `$XDG_CONFIG_HOME`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("hermes").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        Config::parse(&text, dir).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Parses a config file that lives in `dir`, which relative paths are taken from
+    fn parse(text: &str, dir: &Path) -> Result<Config, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.message().to_string())?;
+        let credentials_file = file
+            .credentials_file
+            .map(|path| dir.join(path))
+            .or_else(CredentialStore::default_path);
+
+        let accounts = file
+            .accounts
+            .into_iter()
+            .map(|(name, entry)| {
+                entry
+                    .into_account(&name, credentials_file.as_deref())
+                    .map_err(|e| format!("account '{}': {}", name, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(name) = &file.default_account
+            && !accounts.iter().any(|account| &account.name == name)
+        {
+            return Err(format!("default_account '{}' is not defined", name));
+        }
+
+        Ok(Config {
+            accounts,
+            default_account: file.default_account,
+            credentials_file,
+        })
+    }
+
+    /// The account used without `--account`: `default_account`, or the only one there is
+    pub fn default_name(&self) -> Option<&str> {
+        match (&self.default_account, self.accounts.as_slice()) {
+            (Some(name), _) => Some(name),
+            (None, [only]) => Some(&only.name),
+            (None, _) => None,
+        }
+    }
+
+    /// The account called `name`, or the default one
+    pub fn account(&self, name: Option<&str>) -> Result<&Account, String> {
+        let name = match name.or(self.default_name()) {
+            Some(name) => name,
+            None if self.accounts.is_empty() => return Err("no accounts are configured".into()),
+            None => {
+                return Err(
+                    "several accounts are configured; pick one with --account or set \
+                     default_account"
+                        .into(),
+                );
+            }
+        };
+
+        self.accounts
+            .iter()
+            .find(|account| account.name == name)
+            .ok_or_else(|| format!("no account named '{}'", name))
+    }
+}
+
+/// The config file at `path`, or at the default location if it exists there
+pub fn load_config(path: Option<&Path>) -> Result<Option<Config>, String> {
+    match path {
+        Some(path) => Config::load(path).map(Some),
+        None => match Config::default_path() {
+            Some(path) if path.exists() => Config::load(&path).map(Some),
+            _ => Ok(None),
+        },
+    }
+}
+
+/// The account to use: `name`, or the default, from the config file; without a config
+/// file, the one described by EMAIL_* variables
+pub fn select_account(path: Option<&Path>, name: Option<&str>) -> Result<Account, String> {
+    match (load_config(path)?, name) {
+        (Some(config), name) => config.account(name).cloned(),
+        (None, Some(name)) => Err(format!(
+            "there is no config file to find account '{}' in (expected one at {})",
+            name,
+            Config::default_path()
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|| "--config".to_string())
+        )),
+        (None, None) => Account::from_env(),
+    }
+}
+
+/// Reads an on/off setting such as `true`, `no` or `1`
+pub fn parse_switch(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// `config.toml` as written
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    default_account: Option<String>,
+    /// The encrypted credentials file, if not the default one
+    credentials_file: Option<PathBuf>,
+    #[serde(default)]
+    accounts: BTreeMap<String, AccountEntry>,
+}
+
+/// One `[accounts.NAME]` table
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountEntry {
+    /// google, outlook or custom; custom is implied by `imap` and `smtp` tables
+    provider: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    password_command: Option<String>,
+    #[serde(default)]
+    identities: Vec<String>,
+    imap: Option<ServerEntry>,
+    smtp: Option<ServerEntry>,
+    oauth2: Option<OAuthEntry>,
+    default_folder: Option<String>,
+    signature: Option<String>,
+    save_sent: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServerEntry {
+    host: String,
+    port: Option<u16>,
+    security: Option<String>,
+}
+
+/// `[accounts.NAME.oauth2]`; the endpoints and scopes default to the provider's
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OAuthEntry {
+    client_id: String,
+    client_secret: Option<String>,
+    authorization_url: Option<String>,
+    token_url: Option<String>,
+    scopes: Option<Vec<String>>,
+}
+
+impl AccountEntry {
+    fn into_account(self, name: &str, credentials_file: Option<&Path>) -> Result<Account, String> {
+        let provider = match (self.provider.as_deref(), self.imap, self.smtp) {
+            (Some(provider), None, None) if provider != "custom" => EmailProvider::named(provider)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown provider '{}' (expected google, outlook or custom)",
+                        provider
+                    )
+                })?,
+            (None | Some("custom"), Some(imap), Some(smtp)) => {
+                EmailProvider::Custom(ServerConfig {
+                    imap: imap.into_server(993, 143)?,
+                    smtp: smtp.into_server(465, 587)?,
+                })
+            }
+            (Some(provider), _, _) if provider != "custom" => {
+                return Err(format!(
+                    "imap and smtp servers can only be given for a custom provider, not {}",
+                    provider
+                ));
+            }
+            _ => return Err("needs a provider, or both imap and smtp servers".to_string()),
+        };
+
+        let identities = self
+            .identities
+            .iter()
+            .map(|identity| {
+                let address: Address = identity.parse()?;
+                address
+                    .to_mailbox()
+                    .map_err(|e| format!("invalid identity '{}': {}", identity, e))?;
+                Ok(address)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let username = match (self.username, identities.first()) {
+            (Some(username), _) => username,
+            (None, Some(address)) => address.email(),
+            (None, None) => return Err("needs a username or an identity".to_string()),
+        };
+        let password = match (self.password, self.password_command, credentials_file) {
+            (Some(_), Some(_), _) => {
+                return Err("set only one of password and password_command".to_string());
+            }
+            (Some(password), None, _) => PasswordSource::Plain(Secret::new(password)),
+            (None, Some(command), _) => PasswordSource::Command(command),
+            (None, None, Some(path)) => PasswordSource::Stored(path.to_path_buf()),
+            (None, None, None) => return Err("needs a password or password_command".to_string()),
+        };
+        let oauth2 = self
+            .oauth2
+            .map(|entry| entry.into_config(&provider))
+            .transpose()?;
+        if oauth2.is_some() && matches!(password, PasswordSource::Plain(_)) {
+            return Err(
+                "an oauth2 account's refresh token goes in the credentials file or comes from \
+                 password_command, not password"
+                    .to_string(),
+            );
+        }
+
+        Ok(Account {
+            name: name.to_string(),
+            provider,
+            username,
+            password,
+            oauth2,
+            identities,
+            default_folder: self.default_folder.unwrap_or_else(|| "INBOX".to_string()),
+            signature: self
+                .signature
+                .filter(|signature| !signature.trim().is_empty()),
+            save_sent: self.save_sent,
+        })
+    }
+}
+
+impl ServerEntry {
+    fn into_server(self, tls_port: u16, starttls_port: u16) -> Result<Server, String> {
+        let security = match self.security {
+            Some(security) => security.parse::<Security>()?,
+            None => Security::Tls,
+        };
+        Ok(Server {
+            host: self.host,
+            port: self
+                .port
+                .unwrap_or_else(|| default_port(security, tls_port, starttls_port)),
+            security,
+        })
+    }
+}
+
+impl OAuthEntry {
+    fn into_config(self, provider: &EmailProvider) -> Result<OAuthConfig, String> {
+        let client_secret = self.client_secret.map(Secret::new);
+        let defaults = OAuthConfig::for_provider(provider, self.client_id.clone(), None);
+        let (authorization_url, token_url, scopes) = match (defaults, self.scopes) {
+            (Some(defaults), scopes) => (
+                self.authorization_url.unwrap_or(defaults.authorization_url),
+                self.token_url.unwrap_or(defaults.token_url),
+                scopes.unwrap_or(defaults.scopes),
+            ),
+            (None, Some(scopes)) => match (self.authorization_url, self.token_url) {
+                (Some(authorization_url), Some(token_url)) => {
+                    (authorization_url, token_url, scopes)
+                }
+                _ => {
+                    return Err(
+                        "oauth2 for a custom provider needs authorization_url and token_url"
+                            .to_string(),
+                    );
+                }
+            },
+            (None, None) => return Err("oauth2 for a custom provider needs scopes".to_string()),
+        };
+
+        Ok(OAuthConfig {
+            client_id: self.client_id,
+            client_secret,
+            authorization_url,
+            token_url,
+            scopes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+default_account = "work"
+
+[accounts.personal]
+provider = "gmail"
+identities = ["Jane Doe <jane@gmail.com>"]
+password_command = "printf app-password"
+signature = "Jane"
+
+[accounts.work]
+username = "jdoe"
+password = "hunter2"
+identities = ["Jane Doe <jane@work.example>", "Support <support@work.example>"]
+default_folder = "Work/Inbox"
+save_sent = false
+
+[accounts.work.imap]
+host = "mail.work.example"
+
+[accounts.work.smtp]
+host = "mail.work.example"
+security = "starttls"
+"#;
+
+    fn parse(text: &str) -> Result<Config, String> {
+        Config::parse(text, Path::new("/etc/hermes"))
+    }
+
+    #[test]
+    fn test_parse_accounts() {
+        let config = parse(CONFIG).unwrap();
+        let names: Vec<&str> = config.accounts.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["personal", "work"]);
+
+        let personal = config.account(Some("personal")).unwrap();
+        assert_eq!(personal.provider, EmailProvider::Google);
+        assert_eq!(personal.username, "jane@gmail.com");
+        assert_eq!(personal.default_folder, "INBOX");
+        assert_eq!(personal.signature.as_deref(), Some("Jane"));
+        assert_eq!(personal.save_sent, None);
+
+        let work = config.account(None).unwrap();
+        assert_eq!(work.name, "work");
+        assert_eq!(work.username, "jdoe");
+        assert_eq!(work.identities.len(), 2);
+        assert_eq!(work.address().unwrap().email(), "jane@work.example");
+        assert_eq!(work.default_folder, "Work/Inbox");
+        assert_eq!(work.save_sent, Some(false));
+        assert_eq!(
+            work.provider.imap_server(),
+            Server::new("mail.work.example", 993, Security::Tls)
+        );
+        assert_eq!(
+            work.provider.smtp_server(),
+            Server::new("mail.work.example", 587, Security::StartTls)
+        );
+    }
+
+    #[test]
+    fn test_choosing_an_account() {
+        let config = parse(CONFIG).unwrap();
+        assert_eq!(
+            config.account(Some("nope")).unwrap_err(),
+            "no account named 'nope'"
+        );
+
+        // Without default_account, only a lone account is picked on its own
+        let two = parse(&CONFIG.replace("default_account = \"work\"", "")).unwrap();
+        assert_eq!(two.default_name(), None);
+        assert!(two.account(None).is_err());
+        assert!(two.account(Some("work")).is_ok());
+
+        let one =
+            parse("[accounts.only]\nprovider = \"outlook\"\nusername = \"a\"\npassword = \"b\"\n")
+                .unwrap();
+        assert_eq!(one.account(None).unwrap().name, "only");
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let error = |text: &str| parse(text).unwrap_err();
+
+        assert!(error("default_account = \"ghost\"").contains("'ghost' is not defined"));
+        assert!(error("[accounts.a]\nprovider = \"gmail\"\npasword = \"x\"").contains("pasword"));
+        assert_eq!(
+            error(
+                "[accounts.a]\nprovider = \"gmail\"\nusername = \"a\"\npassword = \"x\"\npassword_command = \"y\""
+            ),
+            "account 'a': set only one of password and password_command"
+        );
+        assert!(
+            error(
+                "[accounts.a]\npassword = \"x\"\nusername = \"a\"\n[accounts.a.imap]\nhost = \"h\""
+            )
+            .contains("needs a provider")
+        );
+        assert!(error("[accounts.a]\nprovider = \"aol\"\npassword = \"x\"").contains("aol"));
+        assert!(
+            error("[accounts.a]\nprovider = \"gmail\"\npassword = \"x\"\nidentities = [\"nope\"]")
+                .contains("nope")
+        );
+    }
+
+    #[test]
+    fn test_password_sources() {
+        let config = parse(CONFIG).unwrap();
+        let login = |name: &str| {
+            let account = config.account(Some(name)).unwrap();
+            account.credentials(|| panic!("no passphrase needed"))
+        };
+        assert!(format!("{:?}", login("work").unwrap()).contains("jdoe"));
+        assert!(login("personal").is_ok());
+
+        // Without either, the password is looked up in the credentials file
+        let dir = tempfile::tempdir().unwrap();
+        let text =
+            "credentials_file = \"secrets\"\n[accounts.a]\nprovider = \"gmail\"\nusername = \"a\"";
+        let config = Config::parse(text, dir.path()).unwrap();
+        let account = config.account(None).unwrap();
+        let path = dir.path().join("secrets");
+        assert!(matches!(&account.password, PasswordSource::Stored(p) if *p == path));
+        let error = account
+            .credentials(|| panic!("no file to unlock"))
+            .unwrap_err();
+        assert!(error.contains("hermes credentials set a"), "{}", error);
+
+        let mut store = CredentialStore::default();
+        store.set("a", Secret::from("stored"));
+        store.save(&path, &Secret::from("pass")).unwrap();
+        assert!(account.credentials(|| Ok(Secret::from("pass"))).is_ok());
+        assert!(account.credentials(|| Ok(Secret::from("nope"))).is_err());
+    }
+
+    #[test]
+    fn test_oauth2_accounts() {
+        let text = "[accounts.a]\nprovider = \"outlook\"\nusername = \"a\"\n\
+                    password_command = \"printf refresh\"\n[accounts.a.oauth2]\n\
+                    client_id = \"hermes\"\n";
+        let config = parse(text).unwrap();
+        let account = config.account(None).unwrap();
+        let oauth2 = account.oauth2.as_ref().unwrap();
+        assert_eq!(oauth2.client_id, "hermes");
+        assert!(
+            oauth2
+                .token_url
+                .starts_with("https://login.microsoftonline.com/")
+        );
+        assert!(oauth2.scopes.contains(&"offline_access".to_string()));
+        let login = account.credentials(|| panic!("no passphrase needed"));
+        assert!(format!("{:?}", login.unwrap()).contains("OAuth2"));
+
+        let custom = "[accounts.a]\nusername = \"a\"\npassword_command = \"x\"\n\
+                      [accounts.a.imap]\nhost = \"h\"\n[accounts.a.smtp]\nhost = \"h\"\n\
+                      [accounts.a.oauth2]\nclient_id = \"c\"\nscopes = [\"mail\"]\n";
+        assert!(
+            parse(custom)
+                .unwrap_err()
+                .contains("authorization_url and token_url")
+        );
+        let custom = custom.replace(
+            "scopes",
+            "authorization_url = \"https://h/auth\"\ntoken_url = \"https://h/token\"\nscopes",
+        );
+        assert_eq!(
+            parse(&custom).unwrap().accounts[0]
+                .oauth2
+                .as_ref()
+                .unwrap()
+                .token_url,
+            "https://h/token"
+        );
+
+        let plain = text.replace("password_command", "password");
+        assert!(parse(&plain).unwrap_err().contains("refresh token"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let stored = text.replace("password_command = \"printf refresh\"\n", "");
+        let stored = format!("credentials_file = \"secrets\"\n{}", stored);
+        let error = Config::parse(&stored, dir.path()).unwrap().accounts[0]
+            .credentials(|| panic!("no file to unlock"))
+            .unwrap_err();
+        assert!(
+            error.contains("hermes credentials authorize a"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_rotated_refresh_token_is_saved_to_the_credentials_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = "credentials_file = \"secrets\"\n[accounts.a]\nprovider = \"outlook\"\n\
+                    username = \"a\"\n[accounts.a.oauth2]\nclient_id = \"hermes\"\n";
+        let account = Config::parse(text, dir.path()).unwrap().accounts.remove(0);
+        let path = dir.path().join("secrets");
+        let passphrase = Secret::from("correct horse");
+
+        let mut store = CredentialStore::default();
+        store.set("a", Secret::from("old"));
+        store.set("b", Secret::from("other"));
+        store.save(&path, &passphrase).unwrap();
+
+        let keep = account.rotated_token_keeper(Some(passphrase.clone()));
+        keep(&Secret::from("new")).unwrap();
+        let store = CredentialStore::open(&path, &passphrase).unwrap();
+        assert_eq!(store.get("a").unwrap().expose(), "new");
+        assert_eq!(store.get("b").unwrap().expose(), "other");
+
+        // Nowhere to put it when the token comes from a command
+        let account = Account {
+            password: PasswordSource::Command("printf old".to_string()),
+            ..account
+        };
+        let error = account.rotated_token_keeper(None)(&Secret::from("new")).unwrap_err();
+        assert!(
+            error.contains("hermes credentials authorize a"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_account_from_environment() {
+        let vars = [
+            ("EMAIL_USERNAME", "me@example.com"),
+            ("EMAIL_PASSWORD", "secret"),
+            ("EMAIL_SAVE_SENT", "no"),
+        ];
+        let lookup = |key: &str| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        };
+        let account = Account::from_lookup(lookup).unwrap();
+        assert_eq!(account.provider, EmailProvider::Google);
+        assert_eq!(account.address().unwrap().email(), "me@example.com");
+        assert_eq!(account.save_sent, Some(false));
+
+        assert!(Account::from_lookup(|_| None).is_err());
+    }
+
+    #[test]
+    fn test_parse_switch() {
+        assert_eq!(parse_switch(" Yes"), Some(true));
+        assert_eq!(parse_switch("0"), Some(false));
+        assert_eq!(parse_switch("maybe"), None);
+    }
+}
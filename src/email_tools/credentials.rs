@@ -0,0 +1,252 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+/// A password or passphrase. The memory is wiped when it is dropped, and Debug never
+/// shows it.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(secret: String) -> Secret {
+        Secret(Zeroizing::new(secret))
+    }
+
+    /// The secret itself, for handing to the server
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Secret {
+        Secret::new(secret)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Secret {
+        Secret::new(secret.to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Runs `command` through the shell, e.g. `pass show mail/work`, and takes the first line
+/// it prints as the password
+pub fn password_from_command(command: &str) -> Result<Secret, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("could not run '{}': {}", command, e))?;
+    let stdout = Zeroizing::new(output.stdout);
+    if !output.status.success() {
+        return Err(format!("'{}' exited with {}", command, output.status));
+    }
+
+    let text = std::str::from_utf8(&stdout)
+        .map_err(|_| format!("'{}' printed something that isn't text", command))?;
+    let password = text
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('\r');
+    if password.is_empty() {
+        return Err(format!("'{}' printed no password", command));
+    }
+    Ok(Secret::from(password))
+}
+
+/// Starts every credentials file, so a file of something else is recognised as such
+const MAGIC: &[u8] = b"HERMESC1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Account passwords kept in a local file, encrypted with a key derived from a master
+/// passphrase (Argon2id, then ChaCha20-Poly1305)
+#[derive(Debug, Default)]
+pub struct CredentialStore {
+    passwords: BTreeMap<String, Secret>,
+}
+
+impl CredentialStore {
+    /// `hermes/credentials` in the user's config directory, next to `config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("hermes").join("credentials"))
+    }
+
+    /// Unlocks the file at `path`
+    pub fn open(path: &Path, passphrase: &Secret) -> Result<CredentialStore, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let plain = decrypt(&data, passphrase).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let text = std::str::from_utf8(&plain)
+            .map_err(|_| format!("{}: the credentials file is damaged", path.display()))?;
+        let entries: BTreeMap<String, String> = toml::from_str(text)
+            .map_err(|_| format!("{}: the credentials file is damaged", path.display()))?;
+
+        Ok(CredentialStore {
+            passwords: entries
+                .into_iter()
+                .map(|(account, password)| (account, Secret::new(password)))
+                .collect(),
+        })
+    }
+
+    /// Encrypts the store into `path`, readable by the owner only. The file is replaced in
+    /// one step, so a failed write leaves the old one intact.
+    pub fn save(&self, path: &Path, passphrase: &Secret) -> Result<(), String> {
+        let entries: BTreeMap<&str, &str> = self
+            .passwords
+            .iter()
+            .map(|(account, password)| (account.as_str(), password.expose()))
+            .collect();
+        let plain = Zeroizing::new(toml::to_string(&entries).map_err(|e| e.to_string())?);
+        let data = encrypt(plain.as_bytes(), passphrase)?;
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        // tempfile creates the file with owner-only permissions
+        let mut file = tempfile::NamedTempFile::new_in(dir)
+            .map_err(|e| format!("{}: {}", dir.display(), e))?;
+        file.write_all(&data)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        file.persist(path)
+            .map_err(|e| format!("{}: {}", path.display(), e.error))?;
+        Ok(())
+    }
+
+    pub fn get(&self, account: &str) -> Option<&Secret> {
+        self.passwords.get(account)
+    }
+
+    pub fn set(&mut self, account: &str, password: Secret) {
+        self.passwords.insert(account.to_string(), password);
+    }
+
+    /// Forgets an account's password; false if there was none
+    pub fn remove(&mut self, account: &str) -> bool {
+        self.passwords.remove(account).is_some()
+    }
+
+    /// The accounts with a stored password, in name order
+    pub fn accounts(&self) -> impl Iterator<Item = &str> {
+        self.passwords.keys().map(String::as_str)
+    }
+}
+
+fn derive_key(passphrase: &Secret, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, String> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.expose().as_bytes(), salt, key.as_mut())
+        .map_err(|e| format!("could not derive a key from the passphrase: {}", e))?;
+    Ok(key)
+}
+
+/// `MAGIC`, a random salt and nonce, then the ciphertext
+fn encrypt(plain: &[u8], passphrase: &Secret) -> Result<Vec<u8>, String> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt)?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+        .encrypt(&nonce, plain)
+        .map_err(|_| "could not encrypt the credentials".to_string())?;
+
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    Ok(data)
+}
+
+fn decrypt(data: &[u8], passphrase: &Secret) -> Result<Zeroizing<Vec<u8>>, String> {
+    let rest = data
+        .strip_prefix(MAGIC)
+        .filter(|rest| rest.len() > SALT_LEN + NONCE_LEN)
+        .ok_or("not a hermes credentials file")?;
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let key = derive_key(passphrase, salt)?;
+    ChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| "wrong passphrase, or the credentials file is damaged".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_is_never_printed() {
+        let secret = Secret::from("hunter2");
+        assert_eq!(secret.expose(), "hunter2");
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+    }
+
+    #[test]
+    fn test_password_from_command() {
+        let password = password_from_command("printf 'hunter2\\nuser: jdoe\\n'").unwrap();
+        assert_eq!(password.expose(), "hunter2");
+
+        assert!(
+            password_from_command("exit 3")
+                .unwrap_err()
+                .contains("exited")
+        );
+        assert!(
+            password_from_command("true")
+                .unwrap_err()
+                .contains("no password")
+        );
+    }
+
+    #[test]
+    fn test_store_roundtrip_and_wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hermes").join("credentials");
+        let passphrase = Secret::from("correct horse");
+
+        let mut store = CredentialStore::default();
+        store.set("work", Secret::from("hunter2"));
+        store.set("home", Secret::from("pa55 word"));
+        store.save(&path, &passphrase).unwrap();
+
+        let raw = std::fs::read(&path).unwrap();
+        assert!(raw.starts_with(MAGIC));
+        assert!(!raw.windows(7).any(|w| w == b"hunter2"));
+
+        let mut store = CredentialStore::open(&path, &passphrase).unwrap();
+        assert_eq!(store.accounts().collect::<Vec<_>>(), vec!["home", "work"]);
+        assert_eq!(store.get("work").unwrap().expose(), "hunter2");
+        assert!(store.remove("home"));
+        assert!(!store.remove("home"));
+
+        let error = CredentialStore::open(&path, &Secret::from("wrong")).unwrap_err();
+        assert!(error.contains("wrong passphrase"), "{}", error);
+
+        std::fs::write(&path, b"user=hunter2").unwrap();
+        let error = CredentialStore::open(&path, &passphrase).unwrap_err();
+        assert!(error.contains("not a hermes credentials file"), "{}", error);
+    }
+}
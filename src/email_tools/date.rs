@@ -0,0 +1,123 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, TimeZone};
+use mail_parser::MessageParser;
+
+/// Parses a message date from any of the places one turns up: RFC 2822 `Date:` headers
+/// and ENVELOPE fields (including obsolete forms such as named zones or a trailing
+/// `(UTC)` comment) and RFC 3339 timestamps. Returns `None` if nothing sensible is found.
+pub fn parse_date(raw: &str) -> Option<DateTime<FixedOffset>> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return None;
+    }
+
+    if let Ok(date) = DateTime::parse_from_rfc2822(raw) {
+        return Some(date);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(raw) {
+        return Some(date);
+    }
+
+    // mail_parser's date parser copes with most of what real mailers produce
+    let header = format!("Date: {}\r\n\r\n", raw);
+    let message = MessageParser::default().parse(header.as_bytes())?;
+    from_parsed(message.date()?)
+}
+
+/// Converts a date parsed by mail_parser, keeping its UTC offset
+pub fn from_parsed(date: &mail_parser::DateTime) -> Option<DateTime<FixedOffset>> {
+    let offset = (date.tz_hour as i32 * 3600 + date.tz_minute as i32 * 60)
+        * if date.tz_before_gmt { -1 } else { 1 };
+
+    let naive = NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)?
+        .and_hms_opt(date.hour as u32, date.minute as u32, date.second as u32)?;
+
+    FixedOffset::east_opt(offset)?
+        .from_local_datetime(&naive)
+        .single()
+}
+
+/// A short date for lists, relative to `now`: `just now`, `5 min ago`, `14:05` today,
+/// `yesterday`, the weekday within the last week, `Jan 15` this year and `2023-01-15`
+/// before that
+pub fn format_relative(date: &DateTime<FixedOffset>, now: &DateTime<FixedOffset>) -> String {
+    // Calendar comparisons happen in the reader's timezone
+    let local = date.with_timezone(&now.timezone());
+    let elapsed = now.signed_duration_since(date);
+    let days = now
+        .date_naive()
+        .signed_duration_since(local.date_naive())
+        .num_days();
+
+    if elapsed.num_seconds() < 0 {
+        // Clock skew or a bogus header; don't claim it is "ago"
+        local.format("%Y-%m-%d").to_string()
+    } else if elapsed.num_minutes() < 1 {
+        "just now".to_string()
+    } else if elapsed.num_minutes() < 60 {
+        format!("{} min ago", elapsed.num_minutes())
+    } else if days == 0 {
+        local.format("%H:%M").to_string()
+    } else if days == 1 {
+        "yesterday".to_string()
+    } else if days < 7 {
+        local.format("%a").to_string()
+    } else if local.year() == now.year() {
+        local.format("%b %-d").to_string()
+    } else {
+        local.format("%Y-%m-%d").to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_common_forms() {
+        let expected = date("2024-01-15T10:30:00+01:00");
+
+        assert_eq!(
+            parse_date("Mon, 15 Jan 2024 10:30:00 +0100"),
+            Some(expected)
+        );
+        assert_eq!(parse_date("2024-01-15T10:30:00+01:00"), Some(expected));
+        assert_eq!(parse_date("15 Jan 2024 10:30:00 +0100"), Some(expected));
+        assert_eq!(
+            parse_date("Mon, 15 Jan 2024 09:30:00 +0000 (UTC)"),
+            Some(expected)
+        );
+        assert_eq!(parse_date("Mon, 15 Jan 2024 09:30:00 GMT"), Some(expected));
+
+        // The offset is kept, not just the instant
+        let parsed = parse_date("Mon, 15 Jan 2024 10:30:00 -0500").unwrap();
+        assert_eq!(parsed.offset().local_minus_utc(), -5 * 3600);
+    }
+
+    #[test]
+    fn test_parse_garbage() {
+        assert_eq!(parse_date(""), None);
+        assert_eq!(parse_date("   "), None);
+        assert_eq!(parse_date("not a date"), None);
+    }
+
+    #[test]
+    fn test_format_relative() {
+        let now = date("2024-03-20T15:00:00+00:00");
+        let relative = |s: &str| format_relative(&date(s), &now);
+
+        assert_eq!(relative("2024-03-20T14:59:30+00:00"), "just now");
+        assert_eq!(relative("2024-03-20T14:35:00+00:00"), "25 min ago");
+        assert_eq!(relative("2024-03-20T08:05:00+00:00"), "08:05");
+        assert_eq!(relative("2024-03-19T23:00:00+00:00"), "yesterday");
+        assert_eq!(relative("2024-03-16T12:00:00+00:00"), "Sat");
+        assert_eq!(relative("2024-01-15T10:30:00+00:00"), "Jan 15");
+        assert_eq!(relative("2023-12-31T10:30:00+00:00"), "2023-12-31");
+
+        // Shown in the reader's timezone: 01:30 on the 20th in +02:00 is the 19th in UTC
+        assert_eq!(relative("2024-03-20T01:30:00+02:00"), "yesterday");
+    }
+}
@@ -0,0 +1,133 @@
+use super::folder::find_special_use;
+use super::{Email, Flag, MailBackend, SpecialUse, Window, build_email_bytes, parse_email_bytes};
+
+/// The folder the server advertises as `\Drafts`
+pub fn drafts_folder(backend: &mut dyn MailBackend) -> Result<String, Box<dyn std::error::Error>> {
+    let folders = backend.list_folders(false)?;
+    find_special_use(&folders, SpecialUse::Drafts)
+        .map(|folder| folder.name.clone())
+        .ok_or_else(|| "the server has no \\Drafts folder".into())
+}
+
+/// The drafts waiting to be finished, newest first, with the folder they live in
+pub fn list_drafts(
+    backend: &mut dyn MailBackend,
+) -> Result<(String, Vec<Email>), Box<dyn std::error::Error>> {
+    let folder = drafts_folder(backend)?;
+    let drafts = backend.fetch_envelopes(&folder, &Window::All)?.inbox;
+    Ok((folder, drafts))
+}
+
+/// Stores `email` in the Drafts folder, marked \Draft, Bcc included. `replaces` is the UID
+/// of an earlier version of the same draft, removed once the new one is safely stored.
+/// Returns the folder the draft went to.
+pub fn save_draft(
+    backend: &mut dyn MailBackend,
+    email: &Email,
+    replaces: Option<u32>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let folder = drafts_folder(backend)?;
+    let raw = build_email_bytes(email)?;
+    backend.append(&folder, &raw, &[Flag::Draft, Flag::Seen])?;
+
+    if let Some(uid) = replaces {
+        backend.delete(&folder, uid)?;
+    }
+    Ok(folder)
+}
+
+/// Reads a draft back for editing. It is parsed from the stored message rather than the
+/// envelope so Bcc and threading headers come back too; the date is dropped so the
+/// message is dated when it's sent, and line endings are made the editor's.
+pub fn open_draft(
+    backend: &mut dyn MailBackend,
+    folder: &str,
+    uid: u32,
+) -> Result<Email, Box<dyn std::error::Error>> {
+    let raw = backend.fetch_raw(folder, uid)?;
+    let draft = parse_email_bytes(&raw)?;
+    Ok(Email {
+        date: None,
+        body: draft.body.replace("\r\n", "\n"),
+        mime: None,
+        ..draft
+    })
+}
+
+/// Removes a draft that has been sent
+pub fn discard_draft(
+    backend: &mut dyn MailBackend,
+    uid: u32,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let folder = drafts_folder(backend)?;
+    backend.delete(&folder, uid)?;
+    Ok(folder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_tools::MemoryBackend;
+
+    fn unfinished() -> Email {
+        Email {
+            from: "me@example.com".parse().unwrap(),
+            bcc: vec!["boss@example.com".parse().unwrap()],
+            subject: "Half a thought".to_string(),
+            body: "To be continued\n".to_string(),
+            in_reply_to: Some("<demo-1@hermes.local>".to_string()),
+            references: vec!["<demo-1@hermes.local>".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_save_list_and_reopen_draft() {
+        let mut backend = MemoryBackend::demo();
+        assert_eq!(
+            save_draft(&mut backend, &unfinished(), None).unwrap(),
+            "Drafts"
+        );
+
+        let (folder, drafts) = list_drafts(&mut backend).unwrap();
+        assert_eq!(folder, "Drafts");
+        assert_eq!(drafts.len(), 1);
+        assert!(drafts[0].flags.contains(&Flag::Draft));
+
+        let draft = open_draft(&mut backend, &folder, drafts[0].uid).unwrap();
+        assert!(draft.to.is_empty());
+        assert_eq!(draft.bcc[0].email(), "boss@example.com");
+        assert_eq!(draft.subject, "Half a thought");
+        assert_eq!(draft.body, "To be continued\n");
+        assert!(draft.message_id.contains('@'));
+        assert_eq!(draft.in_reply_to.as_deref(), Some("<demo-1@hermes.local>"));
+        assert!(draft.date.is_none());
+    }
+
+    #[test]
+    fn test_saving_again_replaces_and_sending_discards() {
+        let mut backend = MemoryBackend::demo();
+        save_draft(&mut backend, &unfinished(), None).unwrap();
+        let (_, drafts) = list_drafts(&mut backend).unwrap();
+        let first = drafts[0].uid;
+
+        let edited = Email {
+            subject: "A whole thought".to_string(),
+            ..unfinished()
+        };
+        save_draft(&mut backend, &edited, Some(first)).unwrap();
+        let (_, drafts) = list_drafts(&mut backend).unwrap();
+        assert_eq!(drafts.len(), 1);
+        assert_eq!(drafts[0].subject, "A whole thought");
+
+        discard_draft(&mut backend, drafts[0].uid).unwrap();
+        assert!(list_drafts(&mut backend).unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn test_no_drafts_folder() {
+        let mut backend = MemoryBackend::new();
+        let error = save_draft(&mut backend, &unfinished(), None).unwrap_err();
+        assert_eq!(error.to_string(), "the server has no \\Drafts folder");
+    }
+}
@@ -0,0 +1,183 @@
+/// Role of a mailbox as advertised by the server (RFC 6154 SPECIAL-USE)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialUse {
+    All,
+    Archive,
+    Drafts,
+    Flagged,
+    Junk,
+    Sent,
+    Trash,
+}
+
+impl SpecialUse {
+    pub fn from_attribute(attribute: &str) -> Option<SpecialUse> {
+        match attribute.to_ascii_lowercase().as_str() {
+            "\\all" => Some(SpecialUse::All),
+            "\\archive" => Some(SpecialUse::Archive),
+            "\\drafts" => Some(SpecialUse::Drafts),
+            "\\flagged" => Some(SpecialUse::Flagged),
+            "\\junk" => Some(SpecialUse::Junk),
+            "\\sent" => Some(SpecialUse::Sent),
+            "\\trash" => Some(SpecialUse::Trash),
+            _ => None,
+        }
+    }
+
+    pub fn attribute(&self) -> &'static str {
+        match self {
+            SpecialUse::All => "\\All",
+            SpecialUse::Archive => "\\Archive",
+            SpecialUse::Drafts => "\\Drafts",
+            SpecialUse::Flagged => "\\Flagged",
+            SpecialUse::Junk => "\\Junk",
+            SpecialUse::Sent => "\\Sent",
+            SpecialUse::Trash => "\\Trash",
+        }
+    }
+}
+
+/// A mailbox on the server, as returned by LIST or LSUB
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folder {
+    pub name: String,
+    pub delimiter: Option<String>,
+    /// Raw attributes such as `\Noselect` or `\Sent`
+    pub attributes: Vec<String>,
+}
+
+impl Folder {
+    pub fn special_use(&self) -> Option<SpecialUse> {
+        self.attributes
+            .iter()
+            .find_map(|attr| SpecialUse::from_attribute(attr))
+    }
+
+    /// Whether the folder can be selected, i.e. it actually holds messages rather than
+    /// only existing as a level of the hierarchy
+    pub fn is_selectable(&self) -> bool {
+        !self
+            .attributes
+            .iter()
+            .any(|attr| attr.eq_ignore_ascii_case("\\Noselect"))
+    }
+
+    /// How many levels deep the folder is, e.g. 1 for `Work/Projects`
+    pub fn depth(&self) -> usize {
+        match &self.delimiter {
+            Some(delimiter) if !delimiter.is_empty() => {
+                self.name.matches(delimiter.as_str()).count()
+            }
+            _ => 0,
+        }
+    }
+
+    /// The last component of the name, e.g. `Projects` for `Work/Projects`
+    pub fn leaf_name(&self) -> &str {
+        match &self.delimiter {
+            Some(delimiter) if !delimiter.is_empty() => self
+                .name
+                .rsplit(delimiter.as_str())
+                .next()
+                .unwrap_or(&self.name),
+            _ => &self.name,
+        }
+    }
+}
+
+/// Message counts of a folder, from STATUS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FolderStatus {
+    pub total: u32,
+    pub unseen: u32,
+}
+
+/// Finds the folder with the given role, if the server advertises one
+pub fn find_special_use(folders: &[Folder], special_use: SpecialUse) -> Option<&Folder> {
+    folders
+        .iter()
+        .find(|folder| folder.special_use() == Some(special_use))
+}
+
+/// Orders folders so that every folder comes right after its parent, with INBOX first
+pub fn sort_folders(folders: &mut [Folder]) {
+    folders.sort_by_cached_key(|folder| {
+        let components: Vec<String> = match &folder.delimiter {
+            Some(delimiter) if !delimiter.is_empty() => folder
+                .name
+                .split(delimiter.as_str())
+                .map(String::from)
+                .collect(),
+            _ => vec![folder.name.clone()],
+        };
+        (!folder.name.eq_ignore_ascii_case("INBOX"), components)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(name: &str, attributes: &[&str]) -> Folder {
+        Folder {
+            name: name.to_string(),
+            delimiter: Some("/".to_string()),
+            attributes: attributes.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_special_use_from_attributes() {
+        assert_eq!(
+            folder("[Gmail]/Sent Mail", &["\\HasNoChildren", "\\Sent"]).special_use(),
+            Some(SpecialUse::Sent)
+        );
+        assert_eq!(folder("Work", &["\\HasChildren"]).special_use(), None);
+        assert_eq!(
+            SpecialUse::from_attribute("\\TRASH"),
+            Some(SpecialUse::Trash)
+        );
+    }
+
+    #[test]
+    fn test_hierarchy_helpers() {
+        let nested = folder("Work/Projects/Hermes", &[]);
+        assert_eq!(nested.depth(), 2);
+        assert_eq!(nested.leaf_name(), "Hermes");
+
+        let flat = Folder {
+            delimiter: None,
+            ..folder("Archive", &[])
+        };
+        assert_eq!(flat.depth(), 0);
+        assert_eq!(flat.leaf_name(), "Archive");
+
+        assert!(!folder("[Gmail]", &["\\Noselect"]).is_selectable());
+    }
+
+    #[test]
+    fn test_sort_and_find() {
+        let mut folders = vec![
+            folder("Work/Projects", &[]),
+            folder("Work Stuff", &[]),
+            folder("Archive", &[]),
+            folder("INBOX", &[]),
+            folder("Work", &[]),
+            folder("Trash", &["\\Trash"]),
+        ];
+        sort_folders(&mut folders);
+
+        let names: Vec<&str> = folders.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "INBOX",
+                "Archive",
+                "Trash",
+                "Work",
+                "Work/Projects",
+                "Work Stuff"
+            ]
+        );
+    }
+}
@@ -0,0 +1,668 @@
+use super::backend::save_sent_copy;
+use super::date::parse_date;
+use super::mime::part_contents;
+use super::smtp::SmtpReply;
+use super::{
+    Email, Flag, Folder, FolderStatus, Inbox, MailBackend, MimePart, SearchQuery, SendError,
+    SendReport, SentCopy, SpecialUse, Window, build_email_bytes, build_message, parse_email_bytes,
+};
+use mail_builder::MessageBuilder;
+use mail_builder::headers::raw::Raw;
+use mail_parser::MessageParser;
+use std::collections::{BTreeMap, HashMap};
+
+/// UIDVALIDITY reported for every in-memory folder
+const UID_VALIDITY: u32 = 1;
+
+struct MemoryFolder {
+    messages: Vec<Email>,
+    /// The original bytes of messages that arrived raw, by UID
+    raw: HashMap<u32, Vec<u8>>,
+    uid_next: u32,
+    attributes: Vec<String>,
+}
+
+impl Default for MemoryFolder {
+    fn default() -> Self {
+        MemoryFolder {
+            messages: Vec::new(),
+            raw: HashMap::new(),
+            uid_next: 1,
+            attributes: Vec::new(),
+        }
+    }
+}
+
+impl MemoryFolder {
+    /// Stores a message under the next free UID, like an IMAP APPEND
+    fn push(&mut self, mut email: Email, raw: Option<Vec<u8>>) -> u32 {
+        let uid = self.uid_next;
+        self.uid_next += 1;
+
+        email.uid = uid;
+        email.uid_validity = UID_VALIDITY;
+        self.messages.push(email);
+        if let Some(raw) = raw {
+            self.raw.insert(uid, raw);
+        }
+        uid
+    }
+
+    fn position(&self, uid: u32) -> Option<usize> {
+        self.messages.iter().position(|m| m.uid == uid)
+    }
+}
+
+/// A mail store that lives entirely in memory.
+///
+/// Messages get UIDs in arrival order, and like on a real server a UID is never reused
+/// within a folder. Sent mail is recorded in `sent` instead of going anywhere.
+#[derive(Default)]
+pub struct MemoryBackend {
+    folders: BTreeMap<String, MemoryFolder>,
+    pub sent: Vec<Email>,
+    /// Also file sent mail in the `\Sent` folder, as ImapBackend does
+    pub save_sent: bool,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        let mut backend = MemoryBackend::default();
+        backend.create_folder("INBOX");
+        backend
+    }
+
+    /// A backend pre-filled with a handful of messages, used by `--demo`
+    pub fn demo() -> MemoryBackend {
+        let mut backend = MemoryBackend::new();
+        backend.save_sent = true;
+        backend.create_special_folder("Drafts", SpecialUse::Drafts);
+        backend.create_special_folder("Sent", SpecialUse::Sent);
+        backend.create_special_folder("Archive", SpecialUse::Archive);
+        backend.create_special_folder("Trash", SpecialUse::Trash);
+
+        let messages = [
+            (
+                "Alice Martin <alice@example.com>",
+                "Lunch on Friday?",
+                "Are you free for lunch on Friday? The usual place at noon.",
+                vec![],
+            ),
+            (
+                "CI <builds@ci.example.com>",
+                "Build #1042 passed",
+                "All 318 tests passed on main.",
+                vec![Flag::Seen],
+            ),
+        ];
+
+        for (i, (from, subject, body, flags)) in messages.into_iter().enumerate() {
+            backend.insert(
+                "INBOX",
+                Email {
+                    uid: 0,
+                    uid_validity: 0,
+                    flags,
+                    from: from.parse().unwrap(),
+                    to: vec!["me@example.com".parse().unwrap()],
+                    cc: vec![],
+                    bcc: vec![],
+                    reply_to: vec![],
+                    subject: subject.to_string(),
+                    date: parse_date("Mon, 15 Jan 2024 10:30:00 +0000"),
+                    message_id: format!("<demo-{}@hermes.local>", i + 1),
+                    in_reply_to: None,
+                    references: Vec::new(),
+                    other_headers: HashMap::new(),
+                    body: body.to_string(),
+                    html: None,
+                    mime: None,
+                    attachments: Vec::new(),
+                },
+            );
+        }
+
+        let report = MessageBuilder::new()
+            .from(("Bob Stone", "bob@example.org"))
+            .to("me@example.com")
+            .subject("Quarterly report draft")
+            .message_id("demo-3@hermes.local")
+            .header("Date", Raw::new("Mon, 15 Jan 2024 10:30:00 +0000"))
+            .text_body("Hi,\n\nThe draft of the quarterly report is ready for review.\n\nBob")
+            .attachment(
+                "text/csv",
+                "q4-figures.csv",
+                "region,revenue\nnorth,1200\nsouth,950\n",
+            )
+            .write_to_vec()
+            .expect("demo message should build");
+        backend
+            .insert_raw("INBOX", &report, &[Flag::Flagged])
+            .expect("demo message should parse");
+
+        backend
+    }
+
+    pub fn create_folder(&mut self, name: &str) {
+        self.folders.entry(name.to_string()).or_default();
+    }
+
+    /// Creates a folder advertised with a SPECIAL-USE attribute, like `\Trash`
+    pub fn create_special_folder(&mut self, name: &str, special_use: SpecialUse) {
+        self.folders.entry(name.to_string()).or_default().attributes =
+            vec![special_use.attribute().to_string()];
+    }
+
+    /// Adds a message to `folder`, creating the folder if needed, keeping whatever flags
+    /// it carries. Returns its UID.
+    pub fn insert(&mut self, folder: &str, email: Email) -> u32 {
+        self.folders
+            .entry(folder.to_string())
+            .or_default()
+            .push(email, None)
+    }
+
+    /// Adds a raw RFC 5322 message to `folder`, creating the folder if needed. The bytes
+    /// are kept, so its attachments can be fetched later. Returns its UID.
+    pub fn insert_raw(&mut self, folder: &str, raw: &[u8], flags: &[Flag]) -> Result<u32, String> {
+        let email = Email {
+            flags: flags.to_vec(),
+            ..parse_email_bytes(raw)?
+        };
+
+        Ok(self
+            .folders
+            .entry(folder.to_string())
+            .or_default()
+            .push(email, Some(raw.to_vec())))
+    }
+
+    fn folder_mut(
+        &mut self,
+        folder: &str,
+    ) -> Result<&mut MemoryFolder, Box<dyn std::error::Error>> {
+        self.folders
+            .get_mut(folder)
+            .ok_or_else(|| format!("No such folder: {}", folder).into())
+    }
+
+    fn message_mut(
+        &mut self,
+        folder: &str,
+        uid: u32,
+    ) -> Result<&mut Email, Box<dyn std::error::Error>> {
+        let folder = self.folder_mut(folder)?;
+        let position = folder
+            .position(uid)
+            .ok_or("Could not find requested email")?;
+        Ok(&mut folder.messages[position])
+    }
+
+    fn remove(&mut self, folder: &str, uid: u32) -> Result<Email, Box<dyn std::error::Error>> {
+        let folder = self.folder_mut(folder)?;
+        let position = folder
+            .position(uid)
+            .ok_or("Could not find requested email")?;
+        folder.raw.remove(&uid);
+        Ok(folder.messages.remove(position))
+    }
+
+    /// The message as it would come off a server: the original bytes if it arrived raw,
+    /// otherwise built from the Email
+    fn raw_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if let Some(raw) = self.folder_mut(folder)?.raw.get(&uid) {
+            return Ok(raw.clone());
+        }
+        Ok(build_email_bytes(self.message_mut(folder, uid)?)?)
+    }
+}
+
+impl MailBackend for MemoryBackend {
+    fn list_folders(
+        &mut self,
+        _subscribed_only: bool,
+    ) -> Result<Vec<Folder>, Box<dyn std::error::Error>> {
+        // Every in-memory folder counts as subscribed
+        Ok(self
+            .folders
+            .iter()
+            .map(|(name, folder)| Folder {
+                name: name.clone(),
+                delimiter: Some("/".to_string()),
+                attributes: folder.attributes.clone(),
+            })
+            .collect())
+    }
+
+    fn folder_status(&mut self, folder: &str) -> Result<FolderStatus, Box<dyn std::error::Error>> {
+        let messages = &self.folder_mut(folder)?.messages;
+
+        Ok(FolderStatus {
+            total: messages.len() as u32,
+            unseen: messages
+                .iter()
+                .filter(|m| !m.flags.contains(&Flag::Seen))
+                .count() as u32,
+        })
+    }
+
+    fn fetch_envelopes(
+        &mut self,
+        folder: &str,
+        window: &Window,
+    ) -> Result<Inbox, Box<dyn std::error::Error>> {
+        let messages = &self.folder_mut(folder)?.messages;
+        Ok(envelopes(messages.iter().rev(), window))
+    }
+
+    fn search(
+        &mut self,
+        folder: &str,
+        query: &SearchQuery,
+        window: &Window,
+    ) -> Result<Inbox, Box<dyn std::error::Error>> {
+        let messages = &self.folder_mut(folder)?.messages;
+        let matching = messages
+            .iter()
+            .rev()
+            .filter(|message| query.matches(message));
+        Ok(envelopes(matching, window))
+    }
+
+    fn fetch_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+    ) -> Result<Email, Box<dyn std::error::Error>> {
+        Ok(self.message_mut(folder, uid)?.clone())
+    }
+
+    fn fetch_structure(
+        &mut self,
+        folder: &str,
+        uid: u32,
+    ) -> Result<MimePart, Box<dyn std::error::Error>> {
+        let raw = self.raw_message(folder, uid)?;
+        let message = MessageParser::default()
+            .parse(&raw)
+            .ok_or("Failed to parse email")?;
+        Ok(MimePart::from_message(&message))
+    }
+
+    fn fetch_part(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        part: &MimePart,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let raw = self.raw_message(folder, uid)?;
+        let message = MessageParser::default()
+            .parse(&raw)
+            .ok_or("Failed to parse email")?;
+        Ok(part_contents(&message, &part.section).ok_or("Could not find requested part")?)
+    }
+
+    fn fetch_raw(&mut self, folder: &str, uid: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.raw_message(folder, uid)
+    }
+
+    fn flag(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        flags: &[Flag],
+        set: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let message = self.message_mut(folder, uid)?;
+
+        for flag in flags {
+            message.flags.retain(|f| f != flag);
+            if set {
+                message.flags.push(flag.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn move_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        destination: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.folders.contains_key(destination) {
+            return Err(format!("No such folder: {}", destination).into());
+        }
+
+        let raw = self.folder_mut(folder)?.raw.get(&uid).cloned();
+        let message = self.remove(folder, uid)?;
+        self.folder_mut(destination)?.push(message, raw);
+        Ok(())
+    }
+
+    fn copy_message(
+        &mut self,
+        folder: &str,
+        uid: u32,
+        destination: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.folders.contains_key(destination) {
+            return Err(format!("No such folder: {}", destination).into());
+        }
+
+        let message = self.message_mut(folder, uid)?.clone();
+        let raw = self.folder_mut(folder)?.raw.get(&uid).cloned();
+        self.folder_mut(destination)?.push(message, raw);
+        Ok(())
+    }
+
+    fn delete(&mut self, folder: &str, uid: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.remove(folder, uid)?;
+        Ok(())
+    }
+
+    fn append(
+        &mut self,
+        folder: &str,
+        raw: &[u8],
+        flags: &[Flag],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.folder_mut(folder)?;
+        self.insert_raw(folder, raw, flags)?;
+        Ok(())
+    }
+
+    /// Records the message and accepts every recipient, after the same checks SMTP makes
+    fn send(&mut self, email: Email) -> Result<SendReport, SendError> {
+        let message = build_message(&email).map_err(|e| SendError::Message(e.to_string()))?;
+        let mut report = SendReport {
+            reply: SmtpReply {
+                code: 250,
+                message: "2.0.0 Ok: queued".to_string(),
+            },
+            message_id: message
+                .headers()
+                .get_raw("Message-ID")
+                .unwrap_or_default()
+                .to_string(),
+            accepted: message
+                .envelope()
+                .to()
+                .iter()
+                .map(|a| a.to_string())
+                .collect(),
+            rejected: Vec::new(),
+            copy: SentCopy::NotSaved,
+        };
+
+        self.sent.push(email);
+        if self.save_sent {
+            report.copy = save_sent_copy(self, &message.formatted());
+        }
+        Ok(report)
+    }
+}
+
+/// The envelopes of `newest_first` that fall in `window`, without their bodies
+fn envelopes<'a>(newest_first: impl Iterator<Item = &'a Email>, window: &Window) -> Inbox {
+    let selected: Vec<&Email> = match *window {
+        Window::All => newest_first.collect(),
+        Window::Newest { offset, limit } => newest_first
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect(),
+        Window::Uids { .. } => newest_first
+            .filter(|m| window.contains_uid(m.uid))
+            .collect(),
+    };
+
+    let inbox = selected
+        .into_iter()
+        .map(|message| Email {
+            body: String::new(),
+            html: None,
+            mime: None,
+            attachments: Vec::new(),
+            ..message.clone()
+        })
+        .collect();
+
+    Inbox { inbox }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(subject: &str) -> Email {
+        Email {
+            from: "sender@example.com".parse().unwrap(),
+            to: vec!["me@example.com".parse().unwrap()],
+            cc: vec![],
+            bcc: vec![],
+            reply_to: vec![],
+            subject: subject.to_string(),
+            body: format!("Body of {}", subject),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fetch_envelopes_omits_bodies() {
+        let mut backend = MemoryBackend::new();
+        backend.insert("INBOX", email("one"));
+        backend.insert("INBOX", email("two"));
+
+        let inbox = backend.fetch_envelopes("INBOX", &Window::All).unwrap();
+        assert_eq!(inbox.inbox.len(), 2);
+        assert!(inbox.inbox.iter().all(|e| e.body.is_empty()));
+
+        let full = backend.fetch_message("INBOX", 2).unwrap();
+        assert_eq!(full.subject, "two");
+        assert_eq!(full.body, "Body of two");
+    }
+
+    #[test]
+    fn test_fetch_envelopes_windows() {
+        let mut backend = MemoryBackend::new();
+        for subject in ["one", "two", "three", "four", "five"] {
+            backend.insert("INBOX", email(subject));
+        }
+
+        let subjects = |backend: &mut MemoryBackend, window: Window| -> Vec<String> {
+            backend
+                .fetch_envelopes("INBOX", &window)
+                .unwrap()
+                .inbox
+                .into_iter()
+                .map(|e| e.subject)
+                .collect()
+        };
+
+        assert_eq!(subjects(&mut backend, Window::page(1, 2)), ["five", "four"]);
+        assert_eq!(subjects(&mut backend, Window::page(3, 2)), ["one"]);
+        assert!(subjects(&mut backend, Window::page(4, 2)).is_empty());
+        assert_eq!(
+            subjects(&mut backend, "2:3".parse().unwrap()),
+            ["three", "two"]
+        );
+    }
+
+    #[test]
+    fn test_fetch_missing_message_or_folder() {
+        let mut backend = MemoryBackend::new();
+        assert!(backend.fetch_message("INBOX", 1).is_err());
+        assert!(backend.fetch_message("INBOX", 0).is_err());
+        assert!(backend.fetch_envelopes("Nope", &Window::All).is_err());
+    }
+
+    #[test]
+    fn test_flag_and_unflag() {
+        let mut backend = MemoryBackend::new();
+        backend.insert("INBOX", email("one"));
+
+        backend
+            .flag("INBOX", 1, &[Flag::Seen, Flag::Flagged], true)
+            .unwrap();
+        backend.flag("INBOX", 1, &[Flag::Seen], true).unwrap();
+        assert_eq!(
+            backend.fetch_message("INBOX", 1).unwrap().flags,
+            &[Flag::Flagged, Flag::Seen]
+        );
+
+        backend.flag("INBOX", 1, &[Flag::Flagged], false).unwrap();
+        assert_eq!(
+            backend.fetch_message("INBOX", 1).unwrap().flags,
+            &[Flag::Seen]
+        );
+    }
+
+    #[test]
+    fn test_move_and_delete() {
+        let mut backend = MemoryBackend::new();
+        backend.create_folder("Archive");
+        backend.insert("INBOX", email("one"));
+        backend.insert("INBOX", email("two"));
+
+        backend.move_message("INBOX", 1, "Archive").unwrap();
+        assert_eq!(backend.fetch_message("Archive", 1).unwrap().subject, "one");
+        assert_eq!(backend.fetch_message("INBOX", 2).unwrap().subject, "two");
+        assert!(backend.move_message("INBOX", 2, "Missing").is_err());
+
+        backend.delete("INBOX", 2).unwrap();
+        assert!(
+            backend
+                .fetch_envelopes("INBOX", &Window::All)
+                .unwrap()
+                .inbox
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let mut backend = MemoryBackend::new();
+        backend.insert("INBOX", email("Quarterly report"));
+        let second = backend.insert("INBOX", email("Lunch"));
+        let third = backend.insert("INBOX", email("Report draft"));
+        backend.flag("INBOX", third, &[Flag::Seen], true).unwrap();
+
+        let query: SearchQuery = "subject:report unseen".parse().unwrap();
+        let results = backend.search("INBOX", &query, &Window::All);
+        let results = results.unwrap().inbox;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].subject, "Quarterly report");
+        assert!(results[0].body.is_empty());
+
+        let everything = backend.search("INBOX", &SearchQuery::default(), &Window::All);
+        assert_eq!(everything.unwrap().inbox[1].uid, second);
+
+        let newest = backend.search("INBOX", &SearchQuery::default(), &Window::page(1, 1));
+        assert_eq!(newest.unwrap().inbox[0].uid, third);
+    }
+
+    #[test]
+    fn test_copy_keeps_original_and_flags() {
+        let mut backend = MemoryBackend::new();
+        backend.create_folder("Archive");
+        backend.insert("Archive", email("already there"));
+        let uid = backend.insert("INBOX", email("one"));
+        backend.flag("INBOX", uid, &[Flag::Flagged], true).unwrap();
+
+        backend.copy_message("INBOX", uid, "Archive").unwrap();
+        let copy = backend.fetch_message("Archive", 2).unwrap();
+        assert_eq!(copy.subject, "one");
+        assert_eq!(copy.flags, &[Flag::Flagged]);
+        assert!(backend.fetch_message("INBOX", uid).is_ok());
+        assert!(backend.copy_message("INBOX", uid, "Missing").is_err());
+    }
+
+    #[test]
+    fn test_raw_messages_keep_their_parts() {
+        let mut backend = MemoryBackend::demo();
+        backend.move_message("INBOX", 3, "Archive").unwrap();
+
+        let structure = backend.fetch_structure("Archive", 1).unwrap();
+        let attachment = structure.attachments()[0].clone();
+        let contents = backend.fetch_part("Archive", 1, &attachment).unwrap();
+        assert!(contents.starts_with(b"region,revenue"));
+
+        // Messages stored as an Email are served as a single text part
+        let plain = backend.fetch_structure("INBOX", 1).unwrap();
+        assert_eq!(plain.section, "1");
+        assert!(plain.attachments().is_empty());
+        let body = backend.fetch_part("INBOX", 1, &plain).unwrap();
+        assert!(String::from_utf8(body).unwrap().contains("lunch"));
+    }
+
+    #[test]
+    fn test_uids_survive_removal_and_are_not_reused() {
+        let mut backend = MemoryBackend::new();
+        let first = backend.insert("INBOX", email("one"));
+        let second = backend.insert("INBOX", email("two"));
+
+        backend.delete("INBOX", first).unwrap();
+        let fetched = backend.fetch_message("INBOX", second).unwrap();
+        assert_eq!(fetched.subject, "two");
+        assert_eq!(fetched.uid, second);
+        assert_eq!(fetched.uid_validity, UID_VALIDITY);
+
+        let third = backend.insert("INBOX", email("three"));
+        assert!(third > second);
+        assert!(backend.fetch_message("INBOX", first).is_err());
+    }
+
+    #[test]
+    fn test_append_parses_raw_message() {
+        let mut backend = MemoryBackend::new();
+        let raw = build_email_bytes(&email("appended")).unwrap();
+
+        backend.append("INBOX", &raw, &[Flag::Seen]).unwrap();
+        assert_eq!(
+            backend.fetch_message("INBOX", 1).unwrap().subject,
+            "appended"
+        );
+        assert_eq!(
+            backend.fetch_message("INBOX", 1).unwrap().flags,
+            &[Flag::Seen]
+        );
+    }
+
+    #[test]
+    fn test_folders_and_status() {
+        let mut backend = MemoryBackend::demo();
+        let folders = backend.list_folders(false).unwrap();
+        assert!(folders.iter().any(|f| f.name == "INBOX"));
+        let trash = folders.iter().find(|f| f.name == "Trash").unwrap();
+        assert_eq!(trash.special_use(), Some(SpecialUse::Trash));
+
+        let before = backend.folder_status("INBOX").unwrap();
+        assert_eq!(before.total, 3);
+        assert_eq!(before.unseen, 2);
+
+        backend.flag("INBOX", 1, &[Flag::Seen], true).unwrap();
+        assert_eq!(backend.folder_status("INBOX").unwrap().unseen, 1);
+    }
+
+    #[test]
+    fn test_send_records_message() {
+        let mut backend = MemoryBackend::new();
+        let report = backend.send(email("outgoing")).unwrap();
+        assert_eq!(report.accepted, vec!["me@example.com"]);
+        assert!(report.rejected.is_empty());
+        assert_eq!(backend.sent.len(), 1);
+        assert_eq!(backend.sent[0].subject, "outgoing");
+
+        let nobody = Email {
+            to: vec![],
+            ..email("nowhere")
+        };
+        assert!(matches!(backend.send(nobody), Err(SendError::Message(_))));
+        assert_eq!(backend.sent.len(), 1);
+    }
+}
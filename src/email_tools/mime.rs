@@ -0,0 +1,452 @@
+use super::address::decode_header_text;
+use imap_proto::types::{BodyContentCommon, BodyStructure, ContentEncoding};
+use mail_parser::decoders::base64::base64_decode;
+use mail_parser::decoders::quoted_printable::quoted_printable_decode;
+use mail_parser::{Message, MessagePart, MimeHeaders, PartType};
+
+/// How a part asks to be presented, from its Content-Disposition header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+/// One node of a message's MIME structure. Multipart containers have children; everything
+/// else is a leaf holding content.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MimePart {
+    /// IMAP section number such as `1` or `2.1`; empty for a multipart message root
+    pub section: String,
+    /// Lowercase `type/subtype`, e.g. `text/plain`
+    pub content_type: String,
+    pub charset: Option<String>,
+    pub disposition: Option<Disposition>,
+    pub filename: Option<String>,
+    /// Content-Transfer-Encoding, lowercase; `7bit` when the part doesn't say
+    pub encoding: String,
+    /// Size of the decoded content in bytes, estimated when built from a BODYSTRUCTURE
+    pub size: usize,
+    /// Without the angle brackets, as referenced by `cid:` URLs
+    pub content_id: Option<String>,
+    pub children: Vec<MimePart>,
+}
+
+impl MimePart {
+    /// Builds the part tree of a parsed message
+    pub fn from_message(message: &Message) -> MimePart {
+        match message.parts.first() {
+            Some(root) => build(message, root, String::new()),
+            None => MimePart {
+                section: "1".to_string(),
+                content_type: "text/plain".to_string(),
+                encoding: "7bit".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Builds the part tree from a FETCH BODYSTRUCTURE response, so attachments can be
+    /// listed without downloading them
+    pub fn from_bodystructure(structure: &BodyStructure) -> MimePart {
+        from_structure(structure, String::new())
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.content_type.starts_with("multipart/")
+    }
+
+    /// Whether the part is meant to be saved rather than read as the message body.
+    /// Inline text without a filename is body; anything named, explicitly attached or
+    /// non-textual is an attachment.
+    pub fn is_attachment(&self) -> bool {
+        if self.is_multipart() {
+            return false;
+        }
+        let is_text = self.content_type.starts_with("text/");
+        match self.disposition {
+            Some(Disposition::Attachment) => true,
+            Some(Disposition::Inline) => self.filename.is_some() && !is_text,
+            None => self.filename.is_some() || !is_text,
+        }
+    }
+
+    /// This part and everything below it, depth first
+    pub fn walk(&self) -> Vec<&MimePart> {
+        let mut parts = vec![self];
+        for child in &self.children {
+            parts.extend(child.walk());
+        }
+        parts
+    }
+
+    /// The attachments in the order they appear in the message
+    pub fn attachments(&self) -> Vec<&MimePart> {
+        self.walk()
+            .into_iter()
+            .filter(|part| part.is_attachment())
+            .collect()
+    }
+}
+
+fn build(message: &Message, part: &MessagePart, section: String) -> MimePart {
+    let content_type = part
+        .content_type()
+        .map(|ct| match ct.subtype() {
+            Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+            None => ct.ctype().to_string(),
+        })
+        .map(|ct| ct.to_ascii_lowercase())
+        .unwrap_or_else(|| match part.body {
+            PartType::Text(_) => "text/plain".to_string(),
+            PartType::Html(_) => "text/html".to_string(),
+            PartType::Message(_) => "message/rfc822".to_string(),
+            PartType::Multipart(_) => "multipart/mixed".to_string(),
+            PartType::Binary(_) | PartType::InlineBinary(_) => {
+                "application/octet-stream".to_string()
+            }
+        });
+
+    let disposition =
+        part.content_disposition()
+            .and_then(|cd| match cd.ctype().to_ascii_lowercase().as_str() {
+                "inline" => Some(Disposition::Inline),
+                "attachment" => Some(Disposition::Attachment),
+                _ => None,
+            });
+
+    let children = part
+        .sub_parts()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|id| message.parts.get(*id))
+        .enumerate()
+        .map(|(i, child)| {
+            let child_section = if section.is_empty() {
+                (i + 1).to_string()
+            } else {
+                format!("{}.{}", section, i + 1)
+            };
+            build(message, child, child_section)
+        })
+        .collect();
+
+    MimePart {
+        // A message that isn't multipart has its content at section 1
+        section: if section.is_empty() && !part.is_multipart() {
+            "1".to_string()
+        } else {
+            section
+        },
+        content_type,
+        charset: part
+            .content_type()
+            .and_then(|ct| ct.attribute("charset"))
+            .map(|charset| charset.to_ascii_lowercase()),
+        disposition,
+        filename: part.attachment_name().map(String::from),
+        encoding: part
+            .content_transfer_encoding()
+            .map(|encoding| encoding.to_ascii_lowercase())
+            .unwrap_or_else(|| "7bit".to_string()),
+        size: part.len(),
+        content_id: part
+            .content_id()
+            .map(|id| id.trim_start_matches('<').trim_end_matches('>').to_string()),
+        children,
+    }
+}
+
+fn from_structure(structure: &BodyStructure, section: String) -> MimePart {
+    // Leaves have their content at section 1 even when the message isn't multipart
+    let leaf_section = if section.is_empty() {
+        "1".to_string()
+    } else {
+        section.clone()
+    };
+
+    let (common, other, multipart) = match structure {
+        BodyStructure::Basic { common, other, .. }
+        | BodyStructure::Text { common, other, .. }
+        | BodyStructure::Message { common, other, .. } => (common, Some(other), None),
+        BodyStructure::Multipart { common, bodies, .. } => (common, None, Some(bodies)),
+    };
+
+    let children = multipart
+        .map(|bodies| {
+            bodies
+                .iter()
+                .enumerate()
+                .map(|(i, body)| {
+                    let child_section = if section.is_empty() {
+                        (i + 1).to_string()
+                    } else {
+                        format!("{}.{}", section, i + 1)
+                    };
+                    from_structure(body, child_section)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let encoding = match other.map(|other| &other.transfer_encoding) {
+        Some(ContentEncoding::SevenBit) | None => "7bit".to_string(),
+        Some(ContentEncoding::EightBit) => "8bit".to_string(),
+        Some(ContentEncoding::Binary) => "binary".to_string(),
+        Some(ContentEncoding::Base64) => "base64".to_string(),
+        Some(ContentEncoding::QuotedPrintable) => "quoted-printable".to_string(),
+        Some(ContentEncoding::Other(other)) => other.to_ascii_lowercase(),
+    };
+
+    let octets = other.map(|other| other.octets as usize).unwrap_or_default();
+    let size = if encoding == "base64" {
+        octets / 4 * 3
+    } else {
+        octets
+    };
+
+    MimePart {
+        section: if multipart.is_some() {
+            section
+        } else {
+            leaf_section
+        },
+        content_type: format!("{}/{}", common.ty.ty, common.ty.subtype).to_ascii_lowercase(),
+        charset: param(&common.ty.params, "charset").map(|charset| charset.to_ascii_lowercase()),
+        disposition: common.disposition.as_ref().and_then(|cd| {
+            match cd.ty.to_ascii_lowercase().as_str() {
+                "inline" => Some(Disposition::Inline),
+                "attachment" => Some(Disposition::Attachment),
+                _ => None,
+            }
+        }),
+        filename: structure_filename(common),
+        encoding,
+        size,
+        content_id: other
+            .and_then(|other| other.id)
+            .map(|id| id.trim_start_matches('<').trim_end_matches('>').to_string()),
+        children,
+    }
+}
+
+/// Looks up a body parameter case-insensitively
+fn param<'a>(params: &'a imap_proto::types::BodyParams, name: &str) -> Option<&'a str> {
+    params
+        .as_ref()?
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| *value)
+}
+
+/// The filename from Content-Disposition, falling back to the Content-Type `name`
+fn structure_filename(common: &BodyContentCommon) -> Option<String> {
+    let disposition = common.disposition.as_ref().map(|cd| &cd.params);
+    disposition
+        .and_then(|params| param(params, "filename"))
+        .or_else(|| param(&common.ty.params, "name"))
+        .map(|name| decode_header_text(name.as_bytes()))
+        .filter(|name| !name.is_empty())
+}
+
+/// Undoes a Content-Transfer-Encoding
+pub fn decode_transfer(data: &[u8], encoding: &str) -> Result<Vec<u8>, String> {
+    match encoding.to_ascii_lowercase().as_str() {
+        "base64" => base64_decode(data).ok_or_else(|| "invalid base64 content".to_string()),
+        "quoted-printable" => quoted_printable_decode(data)
+            .ok_or_else(|| "invalid quoted-printable content".to_string()),
+        _ => Ok(data.to_vec()),
+    }
+}
+
+/// The decoded content of the part at IMAP `section` of a parsed message
+pub fn part_contents(message: &Message, section: &str) -> Option<Vec<u8>> {
+    let mut part = message.parts.first()?;
+
+    if !part.is_multipart() {
+        return (section == "1").then(|| part.contents().to_vec());
+    }
+
+    for index in section.split('.') {
+        let index: usize = index.parse().ok()?;
+        let id = part.sub_parts()?.get(index.checked_sub(1)?)?;
+        part = message.parts.get(*id)?;
+    }
+
+    (!part.is_multipart()).then(|| part.contents().to_vec())
+}
+
+/// Formats a byte count for display, e.g. `512 B`, `1.5 KB`, `2.0 MB`
+pub fn format_size(size: usize) -> String {
+    if size < 1024 {
+        format!("{} B", size)
+    } else if size < 1024 * 1024 {
+        format!("{:.1} KB", size as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", size as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mail_parser::MessageParser;
+
+    const MULTIPART: &str = "From: a@example.com\r\n\
+To: b@example.com\r\n\
+Subject: Report\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+\r\n\
+--outer\r\n\
+Content-Type: multipart/alternative; boundary=\"inner\"\r\n\
+\r\n\
+--inner\r\n\
+Content-Type: text/plain; charset=UTF-8\r\n\
+\r\n\
+See attached.\r\n\
+--inner\r\n\
+Content-Type: text/html; charset=\"ISO-8859-1\"\r\n\
+\r\n\
+<p>See <img src=\"cid:logo@x\"> attached.</p>\r\n\
+--inner--\r\n\
+--outer\r\n\
+Content-Type: image/png\r\n\
+Content-Disposition: inline\r\n\
+Content-ID: <logo@x>\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+iVBORw0KGgo=\r\n\
+--outer\r\n\
+Content-Type: application/pdf; name=\"report.pdf\"\r\n\
+Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0xLjQK\r\n\
+--outer--\r\n";
+
+    fn tree(raw: &str) -> MimePart {
+        MimePart::from_message(&MessageParser::default().parse(raw.as_bytes()).unwrap())
+    }
+
+    /// Finds a part by its IMAP section number
+    fn find<'a>(root: &'a MimePart, section: &str) -> Option<&'a MimePart> {
+        root.walk().into_iter().find(|part| part.section == section)
+    }
+
+    #[test]
+    fn test_multipart_tree() {
+        let root = tree(MULTIPART);
+        assert_eq!(root.content_type, "multipart/mixed");
+        assert_eq!(root.section, "");
+        assert_eq!(root.children.len(), 3);
+
+        let sections: Vec<(&str, &str)> = root
+            .walk()
+            .iter()
+            .map(|part| (part.section.as_str(), part.content_type.as_str()))
+            .collect();
+        assert_eq!(
+            sections,
+            [
+                ("", "multipart/mixed"),
+                ("1", "multipart/alternative"),
+                ("1.1", "text/plain"),
+                ("1.2", "text/html"),
+                ("2", "image/png"),
+                ("3", "application/pdf"),
+            ]
+        );
+
+        let html = find(&root, "1.2").unwrap();
+        assert_eq!(html.charset.as_deref(), Some("iso-8859-1"));
+        assert!(!html.is_attachment());
+
+        let logo = find(&root, "2").unwrap();
+        assert_eq!(logo.content_id.as_deref(), Some("logo@x"));
+        assert_eq!(logo.disposition, Some(Disposition::Inline));
+        assert_eq!(logo.size, 8);
+        // Referenced from the HTML rather than sent as a file
+        assert!(!logo.is_attachment());
+
+        let pdf = find(&root, "3").unwrap();
+        assert_eq!(pdf.filename.as_deref(), Some("report.pdf"));
+        assert_eq!(pdf.disposition, Some(Disposition::Attachment));
+        assert_eq!(pdf.size, 9);
+
+        let attachments: Vec<&str> = root
+            .attachments()
+            .iter()
+            .map(|part| part.section.as_str())
+            .collect();
+        assert_eq!(attachments, ["3"]);
+    }
+
+    #[test]
+    fn test_single_part_message() {
+        let root = tree("From: a@example.com\r\nSubject: Hi\r\n\r\nHello\r\n");
+        assert_eq!(root.section, "1");
+        assert_eq!(root.content_type, "text/plain");
+        assert!(root.children.is_empty());
+        assert!(root.attachments().is_empty());
+    }
+
+    #[test]
+    fn test_tree_from_bodystructure() {
+        let response = b"* 1 FETCH (UID 3 BODYSTRUCTURE ((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"UTF-8\") NIL NIL \"7BIT\" 12 1 NIL NIL NIL NIL)(\"APPLICATION\" \"PDF\" (\"NAME\" \"=?UTF-8?Q?R=C3=A9sum=C3=A9.pdf?=\") NIL NIL \"BASE64\" 400 NIL (\"ATTACHMENT\" (\"FILENAME\" \"=?UTF-8?Q?R=C3=A9sum=C3=A9.pdf?=\")) NIL NIL) \"MIXED\" (\"BOUNDARY\" \"x\") NIL NIL NIL))\r\n";
+        let (_, parsed) = imap_proto::parse_response(response).unwrap();
+        let imap_proto::Response::Fetch(_, attributes) = parsed else {
+            panic!("expected a FETCH response");
+        };
+        let structure = attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                imap_proto::AttributeValue::BodyStructure(structure) => Some(structure),
+                _ => None,
+            })
+            .unwrap();
+
+        let root = MimePart::from_bodystructure(structure);
+        assert_eq!(root.content_type, "multipart/mixed");
+        assert_eq!(root.children.len(), 2);
+
+        let text = &root.children[0];
+        assert_eq!(text.section, "1");
+        assert_eq!(text.charset.as_deref(), Some("utf-8"));
+        assert!(!text.is_attachment());
+
+        let pdf = &root.children[1];
+        assert_eq!(pdf.section, "2");
+        assert_eq!(pdf.filename.as_deref(), Some("Résumé.pdf"));
+        assert_eq!(pdf.encoding, "base64");
+        assert_eq!(pdf.size, 300);
+        assert_eq!(root.attachments(), [pdf]);
+    }
+
+    #[test]
+    fn test_part_contents_and_transfer_decoding() {
+        let message = MessageParser::default()
+            .parse(MULTIPART.as_bytes())
+            .unwrap();
+        assert_eq!(part_contents(&message, "3").unwrap(), b"%PDF-1.4\n");
+        assert_eq!(part_contents(&message, "1.1").unwrap(), b"See attached.");
+        assert_eq!(part_contents(&message, "1"), None);
+        assert_eq!(part_contents(&message, "9"), None);
+
+        assert_eq!(
+            decode_transfer(b"JVBE\r\nRi0xLjQK", "BASE64").unwrap(),
+            b"%PDF-1.4\n"
+        );
+        assert_eq!(
+            decode_transfer(b"caf=C3=A9 =\r\nau lait", "quoted-printable").unwrap(),
+            "café au lait".as_bytes()
+        );
+        assert_eq!(decode_transfer(b"as is", "7bit").unwrap(), b"as is");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(2 * 1024 * 1024), "2.0 MB");
+    }
+}
@@ -1,79 +1,132 @@
-use chrono::DateTime;
-use lettre::message::Mailbox;
+use chrono::{DateTime, FixedOffset};
+use lettre::Message;
 use lettre::message::header::ContentType;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
+use lettre::message::{MultiPart, SinglePart};
 use mail_builder::MessageBuilder;
+use mail_builder::headers::raw::Raw;
 use mail_parser::MessageParser;
-use native_tls::TlsConnector;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::net::TcpStream;
 
+pub mod address;
+pub mod attachment;
+pub mod backend;
 pub mod cli;
-
-#[allow(dead_code)]
-pub enum EmailProvider {
-    Google,
-    Outlook,
-    Custom(String),
-}
-
-#[derive(Clone)]
+pub mod compose;
+pub mod config;
+pub mod credentials;
+pub mod date;
+pub mod drafts;
+pub mod folder;
+pub mod memory;
+pub mod mime;
+pub mod oauth;
+pub mod provider;
+pub mod reply;
+pub mod search;
+pub mod session;
+pub mod smtp;
+
+pub use address::Address;
+use address::{builder_address_list, decode_header_text};
+pub use attachment::Attachment;
+pub use backend::{ImapBackend, MailBackend, Window, delete_messages};
+pub use credentials::Secret;
+pub use folder::{Folder, FolderStatus, SpecialUse};
+pub use memory::MemoryBackend;
+pub use mime::MimePart;
+pub use provider::{EmailProvider, Security};
+pub use search::SearchQuery;
+pub use session::MailSession;
+pub use smtp::{SendError, SendReport, SentCopy};
+
+/// The login for an account; its secrets are wiped from memory when dropped
+#[derive(Debug, Clone)]
 pub struct UserCredentials {
     username: String,
-    password: String,
+    auth: Auth,
 }
 
-impl UserCredentials {
-    pub fn new(username: String, password: String) -> UserCredentials {
-        return UserCredentials { username, password };
-    }
+/// How an account proves who it is
+#[derive(Debug, Clone)]
+pub enum Auth {
+    Password(Secret),
+    /// A bearer token from the account's OAuth2 provider (XOAUTH2 or OAUTHBEARER)
+    OAuth2(oauth::TokenSource),
 }
 
-// FUTURE::
-//  let config = Config {
-//  port: 3000,
-//  ..Default::default()
-//  };
+impl UserCredentials {
+    pub fn new(username: String, password: Secret) -> UserCredentials {
+        UserCredentials {
+            username,
+            auth: Auth::Password(password),
+        }
+    }
 
-/*#[derive(Debug)]
-pub struct Email {
-    pub id: u32,
-    pub host_email: String,
-    pub subject: String,
-    pub name: String,
-    pub mailbox: String,
-    pub host: String,
-    pub body: String,
-}*/
+    pub fn oauth2(username: String, tokens: oauth::TokenSource) -> UserCredentials {
+        UserCredentials {
+            username,
+            auth: Auth::OAuth2(tokens),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Email {
-    pub from: String,
-    pub to: Vec<String>,
-    pub cc: Vec<String>,
-    pub bcc: Vec<String>,
+    /// IMAP UID, stable across sessions as long as `uid_validity` doesn't change.
+    /// 0 for messages that didn't come from a server.
+    pub uid: u32,
+    pub uid_validity: u32,
+    /// Flags as of when the message was fetched; empty for messages not from a server
+    pub flags: Vec<Flag>,
+    pub from: Address,
+    pub to: Vec<Address>,
+    pub cc: Vec<Address>,
+    pub bcc: Vec<Address>,
+    /// Where replies should go instead of `from`; empty if the sender didn't say
+    pub reply_to: Vec<Address>,
     pub subject: String,
-    pub date: String,
+    /// From the Date header, with the sender's UTC offset; `None` if missing or unparseable
+    pub date: Option<DateTime<FixedOffset>>,
+    /// `<id@host>`, angle brackets included
     pub message_id: String,
+    /// The Message-ID of the message this one replies to
+    pub in_reply_to: Option<String>,
+    /// Message-IDs of the thread this message belongs to, oldest first
+    pub references: Vec<String>,
     pub other_headers: HashMap<String, String>,
+    /// The plain text body, converted from HTML if that's all the message has
     pub body: String,
+    /// The HTML alternative, if the message has one
+    pub html: Option<String>,
+    /// The MIME structure; `None` when only the envelope was fetched
+    pub mime: Option<MimePart>,
+    /// Files attached to the message, with their content
+    pub attachments: Vec<Attachment>,
 }
 
 impl Default for Email {
     fn default() -> Self {
         Email {
-            from: "".to_string(),
-            to: vec!["".to_string()],
-            cc: vec!["".to_string()],
-            bcc: vec!["".to_string()],
+            uid: 0,
+            uid_validity: 0,
+            flags: Vec::new(),
+            from: Address::default(),
+            to: Vec::new(),
+            cc: Vec::new(),
+            bcc: Vec::new(),
+            reply_to: Vec::new(),
             subject: "".to_string(),
-            date: "".to_string(),
+            date: None,
             message_id: "0".to_string(),
+            in_reply_to: None,
+            references: Vec::new(),
             other_headers: HashMap::new(),
             body: "".to_string(),
+            html: None,
+            mime: None,
+            attachments: Vec::new(),
         }
     }
 }
@@ -83,441 +136,512 @@ pub struct Inbox {
     pub inbox: Vec<Email>,
 }
 
-// Fixed get_inbox_one function
-pub fn get_inbox_one(
-    _provider: EmailProvider,
-    credentials: UserCredentials,
-    id: u32,
-) -> Result<Email, Box<dyn std::error::Error>> {
-    let domain = "imap.gmail.com";
-    let tcp_stream = TcpStream::connect((domain, 993))?;
-
-    let tls = TlsConnector::builder().build()?;
-    let tls_stream = tls.connect(domain, tcp_stream)?;
-
-    let client = imap::Client::new(tls_stream);
-
-    let mut imap_session = client
-        .login(credentials.username.clone(), credentials.password)
-        .map_err(|e| e.0)?;
-
-    let fetch_range = id.to_string();
-
-    imap_session.select("INBOX")?;
+/// IMAP message flags. Anything that isn't a system flag is a keyword.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Flag {
+    Seen,
+    Answered,
+    Flagged,
+    Deleted,
+    Draft,
+    Keyword(String),
+}
 
-    let messages = imap_session.fetch(fetch_range, "(BODY[] ENVELOPE)")?;
+impl Flag {
+    /// The flag as it appears in an IMAP command, e.g. `\Seen`
+    pub fn to_imap(&self) -> String {
+        match self {
+            Flag::Seen => "\\Seen".to_string(),
+            Flag::Answered => "\\Answered".to_string(),
+            Flag::Flagged => "\\Flagged".to_string(),
+            Flag::Deleted => "\\Deleted".to_string(),
+            Flag::Draft => "\\Draft".to_string(),
+            Flag::Keyword(keyword) => keyword.clone(),
+        }
+    }
 
-    let mut ret: Option<Email> = None;
+    /// Maps a flag from a FETCH response. `\Recent` and `\*` describe the session rather
+    /// than the message, so they are dropped.
+    pub fn from_imap(flag: &imap::types::Flag) -> Option<Flag> {
+        match flag {
+            imap::types::Flag::Seen => Some(Flag::Seen),
+            imap::types::Flag::Answered => Some(Flag::Answered),
+            imap::types::Flag::Flagged => Some(Flag::Flagged),
+            imap::types::Flag::Deleted => Some(Flag::Deleted),
+            imap::types::Flag::Draft => Some(Flag::Draft),
+            imap::types::Flag::Recent | imap::types::Flag::MayCreate => None,
+            imap::types::Flag::Custom(keyword) => Some(Flag::Keyword(keyword.to_string())),
+        }
+    }
+}
 
-    for message in messages.iter() {
-        let envelope = message
-            .envelope()
-            .expect("message did not have an envelope");
+impl std::str::FromStr for Flag {
+    type Err = String;
+
+    /// Parses `seen`, `\Seen`, `flagged`, ... case-insensitively; anything else is taken
+    /// as a keyword such as `$Work`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.strip_prefix('\\').unwrap_or(s);
+        let flag = match name.to_ascii_lowercase().as_str() {
+            "seen" | "read" => Flag::Seen,
+            "answered" => Flag::Answered,
+            "flagged" => Flag::Flagged,
+            "deleted" => Flag::Deleted,
+            "draft" => Flag::Draft,
+            _ if s.starts_with('\\') => return Err(format!("unknown system flag '{}'", s)),
+            _ if s.is_empty()
+                || s.contains(|c: char| c.is_whitespace() || "(){%*\"]".contains(c)) =>
+            {
+                return Err(format!("invalid keyword '{}'", s));
+            }
+            _ => Flag::Keyword(s.to_string()),
+        };
+        Ok(flag)
+    }
+}
 
-        let from = envelope
-            .from
-            .as_ref()
-            .and_then(|addrs| addrs.first())
-            .map(|addr| {
-                build_email(
-                    addr.mailbox
-                        .as_ref()
-                        .map(|m| String::from_utf8_lossy(m).to_string())
-                        .unwrap_or_default(),
-                    addr.host
-                        .as_ref()
-                        .map(|h| String::from_utf8_lossy(h).to_string())
-                        .unwrap_or_default(),
-                )
-            })
-            .unwrap_or_default();
+pub fn get_inbox_one(
+    session: &mut MailSession,
+    folder: &str,
+    uid: u32,
+) -> Result<Email, Box<dyn std::error::Error>> {
+    let fetch_range = uid.to_string();
+
+    let mailbox = session.select(folder)?;
+    let uid_validity = mailbox.uid_validity.unwrap_or_default();
+
+    // BODY.PEEK leaves \Seen alone; marking as read is up to the caller
+    let messages =
+        session.run(|s| s.uid_fetch(&fetch_range, "(UID FLAGS BODY.PEEK[] ENVELOPE)"))?;
+
+    let message = messages
+        .iter()
+        .find(|m| m.uid == Some(uid))
+        .ok_or("Could not find requested email")?;
+
+    let mut email = email_from_fetch(message, uid_validity, session.username())
+        .ok_or("The server sent the message without its envelope")?;
+    if let Some(raw) = message.body() {
+        let parsed = parse_email_bytes(raw)?;
+        email.body = parsed.body;
+        email.html = parsed.html;
+        email.mime = parsed.mime;
+        email.attachments = parsed.attachments;
+    }
 
-        let to = envelope
-            .to
-            .as_ref()
-            .map(|addrs| {
-                addrs
-                    .iter()
-                    .filter_map(|addr| {
-                        let mailbox = addr.mailbox.as_ref()?;
-                        let host = addr.host.as_ref()?;
-                        Some(format!(
-                            "{}@{}",
-                            String::from_utf8_lossy(mailbox),
-                            String::from_utf8_lossy(host)
-                        ))
-                    })
-                    .collect()
-            })
-            .unwrap_or_else(|| vec![credentials.username.clone()]);
+    Ok(email)
+}
 
-        let cc = envelope
-            .cc
-            .as_ref()
-            .map(|addrs| {
-                addrs
-                    .iter()
-                    .filter_map(|addr| {
-                        let mailbox = addr.mailbox.as_ref()?;
-                        let host = addr.host.as_ref()?;
-                        Some(format!(
-                            "{}@{}",
-                            String::from_utf8_lossy(mailbox),
-                            String::from_utf8_lossy(host)
-                        ))
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
+/// Fetches the envelopes of the messages in `window`, newest first
+pub fn get_envelopes(
+    session: &mut MailSession,
+    folder: &str,
+    window: &Window,
+) -> Result<Inbox, Box<dyn std::error::Error>> {
+    let mut inbox = Inbox { inbox: Vec::new() };
 
-        let bcc = envelope
-            .bcc
-            .as_ref()
-            .map(|addrs| {
-                addrs
-                    .iter()
-                    .filter_map(|addr| {
-                        let mailbox = addr.mailbox.as_ref()?;
-                        let host = addr.host.as_ref()?;
-                        Some(format!(
-                            "{}@{}",
-                            String::from_utf8_lossy(mailbox),
-                            String::from_utf8_lossy(host)
-                        ))
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
-
-        ret = Some(Email {
-            from,
-            to,
-            cc,
-            bcc,
-            subject: envelope
-                .subject
-                .as_ref()
-                .map(|s| String::from_utf8_lossy(s).to_string())
-                .unwrap_or_else(|| "(no subject)".to_string()),
-            date: envelope
-                .date
-                .as_ref()
-                .map(|d| String::from_utf8_lossy(d).to_string())
-                .unwrap_or_default(),
-            message_id: envelope
-                .message_id
-                .as_ref()
-                .map(|id| String::from_utf8_lossy(id).to_string())
-                .unwrap_or_else(|| message.message.to_string()),
-            other_headers: HashMap::new(),
-            body: message
-                .body()
-                .map(|b| String::from_utf8_lossy(b).to_string())
-                .unwrap_or_default(),
-        });
-    }
+    let mailbox = session.select(folder)?;
+    let uid_validity = mailbox.uid_validity.unwrap_or_default();
 
-    imap_session.logout()?;
+    let messages = match window {
+        Window::Uids { first, last } => {
+            let range = match last {
+                Some(last) => format!("{}:{}", first, last),
+                None => format!("{}:*", first),
+            };
+            session.run(|s| s.uid_fetch(&range, "(UID FLAGS ENVELOPE)"))?
+        }
+        _ => match window.sequence_range(mailbox.exists) {
+            Some(range) => session.run(|s| s.fetch(&range, "(UID FLAGS ENVELOPE)"))?,
+            None => return Ok(inbox),
+        },
+    };
 
-    if ret.is_none() {
-        return Err("Could not find requested email".into());
+    for message in messages.iter() {
+        if let Some(email) = email_from_fetch(message, uid_validity, session.username())
+            && window.contains_uid(email.uid)
+        {
+            inbox.inbox.push(email);
+        }
     }
 
-    println!("\nDisconnected successfully");
-    Ok(ret.unwrap())
+    // Servers may answer FETCH in any order
+    inbox
+        .inbox
+        .sort_by_key(|email| std::cmp::Reverse(email.uid));
+    Ok(inbox)
 }
 
-// Fixed get_inbox_all function
-pub fn get_inbox_all(
-    _provider: EmailProvider,
-    credentials: UserCredentials,
+/// Runs a UID SEARCH in `folder` and fetches the envelopes of the matches, newest first
+pub fn search_envelopes(
+    session: &mut MailSession,
+    folder: &str,
+    query: &SearchQuery,
+    window: &Window,
 ) -> Result<Inbox, Box<dyn std::error::Error>> {
     let mut inbox = Inbox { inbox: Vec::new() };
 
-    let domain = "imap.gmail.com";
-    let tcp_stream = TcpStream::connect((domain, 993))?;
+    let mailbox = session.select(folder)?;
+    let uid_validity = mailbox.uid_validity.unwrap_or_default();
+
+    // `uid_search` rejects the continuation sent for literals, so read the hits directly
+    let command = format!("UID SEARCH {}", query.to_imap());
+    let response = session.run(|s| s.run_command_and_read_response(&command))?;
+    let mut uids = search_hits(&response);
+    uids.sort_unstable();
+
+    for chunk in window.select_uids(&uids).chunks(FETCH_CHUNK) {
+        let messages = session.run(|s| s.uid_fetch(uid_set(chunk), "(UID FLAGS ENVELOPE)"))?;
+        inbox.inbox.extend(
+            messages
+                .iter()
+                .filter_map(|message| email_from_fetch(message, uid_validity, session.username())),
+        );
+    }
 
-    let tls = TlsConnector::builder().build()?;
-    let tls_stream = tls.connect(domain, tcp_stream)?;
+    inbox
+        .inbox
+        .sort_by_key(|email| std::cmp::Reverse(email.uid));
+    Ok(inbox)
+}
 
-    let client = imap::Client::new(tls_stream);
+/// How many UIDs go into one UID FETCH, keeping command lines short however sparse the
+/// UIDs are
+const FETCH_CHUNK: usize = 500;
+
+/// Formats ascending UIDs as an IMAP sequence set, collapsing runs into ranges such as
+/// `3:7,9,12:14`
+fn uid_set(uids: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &uid in uids {
+        match ranges.last_mut() {
+            Some((_, last)) if uid == *last + 1 => *last = uid,
+            _ => ranges.push((uid, uid)),
+        }
+    }
 
-    let mut imap_session = client
-        .login(credentials.username.clone(), credentials.password)
-        .map_err(|e| e.0)?;
+    ranges
+        .iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}:{}", first, last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
 
-    let mailbox = imap_session.select("INBOX")?;
+/// The UIDs listed in the `* SEARCH` lines of a server response
+fn search_hits(response: &[u8]) -> Vec<u32> {
+    String::from_utf8_lossy(response)
+        .lines()
+        .filter_map(|line| line.strip_prefix("* SEARCH"))
+        .flat_map(str::split_whitespace)
+        .filter_map(|uid| uid.parse().ok())
+        .collect()
+}
 
-    let total_messages = mailbox.exists;
-    println!("Total messages in inbox: {}", total_messages);
+/// Maps the UID, FLAGS and ENVELOPE of a FETCH response to an Email. The body is left empty.
+///
+/// None if the response has no envelope, as with the unsolicited FETCH a server sends when
+/// another message's flags change.
+fn email_from_fetch(
+    message: &imap::types::Fetch,
+    uid_validity: u32,
+    username: &str,
+) -> Option<Email> {
+    let envelope = message.envelope()?;
+
+    let from = envelope
+        .from
+        .as_ref()
+        .and_then(|addrs| Address::from_envelope(addrs).into_iter().next())
+        .unwrap_or_default();
 
-    let fetch_range = if total_messages > 0 {
-        format!("1:{}", total_messages)
-    } else {
-        println!("No messages in inbox");
-        return Ok(inbox);
-    };
+    let to = envelope
+        .to
+        .as_ref()
+        .map(|addrs| Address::from_envelope(addrs))
+        .unwrap_or_else(|| username.parse().into_iter().collect());
 
-    let messages = imap_session.fetch(fetch_range, "ENVELOPE")?;
+    let cc = envelope
+        .cc
+        .as_ref()
+        .map(|addrs| Address::from_envelope(addrs))
+        .unwrap_or_default();
 
-    println!("Fetched {} messages", messages.len());
+    let bcc = envelope
+        .bcc
+        .as_ref()
+        .map(|addrs| Address::from_envelope(addrs))
+        .unwrap_or_default();
 
-    for message in messages.iter() {
-        let envelope = message
-            .envelope()
-            .expect("message did not have an envelope");
+    // Servers fill in Reply-To with From when the header is missing
+    let reply_to = envelope
+        .reply_to
+        .as_ref()
+        .map(|addrs| Address::from_envelope(addrs))
+        .filter(|reply_to| *reply_to != [from.clone()])
+        .unwrap_or_default();
 
-        let from = envelope
-            .from
+    Some(Email {
+        uid: message.uid.unwrap_or_default(),
+        uid_validity,
+        flags: message.flags().iter().filter_map(Flag::from_imap).collect(),
+        from,
+        to,
+        cc,
+        bcc,
+        reply_to,
+        subject: envelope
+            .subject
             .as_ref()
-            .and_then(|addrs| addrs.first())
-            .map(|addr| {
-                build_email(
-                    addr.mailbox
-                        .as_ref()
-                        .map(|m| String::from_utf8_lossy(m).to_string())
-                        .unwrap_or_default(),
-                    addr.host
-                        .as_ref()
-                        .map(|h| String::from_utf8_lossy(h).to_string())
-                        .unwrap_or_default(),
-                )
-            })
-            .unwrap_or_default();
-
-        let to = envelope
-            .to
+            .map(|s| decode_header_text(s))
+            .unwrap_or_else(|| "(no subject)".to_string()),
+        date: envelope
+            .date
             .as_ref()
-            .map(|addrs| {
-                addrs
-                    .iter()
-                    .filter_map(|addr| {
-                        let mailbox = addr.mailbox.as_ref()?;
-                        let host = addr.host.as_ref()?;
-                        Some(format!(
-                            "{}@{}",
-                            String::from_utf8_lossy(mailbox),
-                            String::from_utf8_lossy(host)
-                        ))
-                    })
-                    .collect()
-            })
-            .unwrap_or_else(|| vec![credentials.username.clone()]);
-
-        let cc = envelope
-            .cc
+            .and_then(|d| date::parse_date(&String::from_utf8_lossy(d))),
+        message_id: envelope
+            .message_id
             .as_ref()
-            .map(|addrs| {
-                addrs
-                    .iter()
-                    .filter_map(|addr| {
-                        let mailbox = addr.mailbox.as_ref()?;
-                        let host = addr.host.as_ref()?;
-                        Some(format!(
-                            "{}@{}",
-                            String::from_utf8_lossy(mailbox),
-                            String::from_utf8_lossy(host)
-                        ))
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
-
-        let bcc = envelope
-            .bcc
+            .map(|id| String::from_utf8_lossy(id).to_string())
+            .unwrap_or_else(|| message.message.to_string()),
+        in_reply_to: envelope
+            .in_reply_to
             .as_ref()
-            .map(|addrs| {
-                addrs
-                    .iter()
-                    .filter_map(|addr| {
-                        let mailbox = addr.mailbox.as_ref()?;
-                        let host = addr.host.as_ref()?;
-                        Some(format!(
-                            "{}@{}",
-                            String::from_utf8_lossy(mailbox),
-                            String::from_utf8_lossy(host)
-                        ))
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
-
-        inbox.inbox.push(Email {
-            from,
-            to,
-            cc,
-            bcc,
-            subject: envelope
-                .subject
-                .as_ref()
-                .map(|s| String::from_utf8_lossy(s).to_string())
-                .unwrap_or_else(|| "(no subject)".to_string()),
-            date: envelope
-                .date
-                .as_ref()
-                .map(|d| String::from_utf8_lossy(d).to_string())
-                .unwrap_or_default(),
-            message_id: envelope
-                .message_id
-                .as_ref()
-                .map(|id| String::from_utf8_lossy(id).to_string())
-                .unwrap_or_else(|| message.message.to_string()),
-            other_headers: HashMap::new(),
-            body: String::new(), // ENVELOPE doesn't include body
-        });
-    }
-
-    imap_session.logout()?;
-
-    println!("\nDisconnected successfully");
-    Ok(inbox)
-}
-
-// Helper function (assumed to exist in your code)
-fn build_email(mailbox: String, host: String) -> String {
-    if mailbox.is_empty() || host.is_empty() {
-        return String::new();
-    }
-    format!("{}@{}", mailbox, host)
+            .map(|id| String::from_utf8_lossy(id).to_string()),
+        references: Vec::new(),
+        other_headers: HashMap::new(),
+        body: String::new(), // ENVELOPE doesn't include body
+        html: None,
+        mime: None,
+        attachments: Vec::new(),
+    })
 }
 
-pub fn send_email(
-    email: Email,
-    credentials: UserCredentials,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut builder = Message::builder().from(email.from.parse::<Mailbox>()?);
+/// Builds the message to hand to SMTP: the plain body, an HTML alternative when there is
+/// one, and any attachments, i.e. multipart/mixed around multipart/alternative
+pub fn build_message(email: &Email) -> Result<Message, Box<dyn std::error::Error>> {
+    let mut builder = Message::builder().from(email.from.to_mailbox()?);
 
+    // lettre has no group syntax, so group members are sent as plain recipients
     // Add all recipients
-    for to_addr in email.to {
-        builder = builder.to(to_addr.parse::<Mailbox>()?);
+    for to_addr in &email.to {
+        builder = builder.to(to_addr.to_mailbox()?);
     }
 
     // Add CC recipients
-    for cc_addr in email.cc {
-        builder = builder.cc(cc_addr.parse::<Mailbox>()?);
+    for cc_addr in &email.cc {
+        builder = builder.cc(cc_addr.to_mailbox()?);
     }
 
     // Add BCC recipients
-    for bcc_addr in email.bcc {
-        builder = builder.bcc(bcc_addr.parse::<Mailbox>()?);
+    for bcc_addr in &email.bcc {
+        builder = builder.bcc(bcc_addr.to_mailbox()?);
     }
 
-    let email_msg = builder
-        .subject(email.subject)
-        .header(ContentType::TEXT_PLAIN)
-        .body(email.body)?;
+    for reply_to_addr in &email.reply_to {
+        builder = builder.reply_to(reply_to_addr.to_mailbox()?);
+    }
 
-    let creds: Credentials = Credentials::new(
-        credentials.username.to_owned(),
-        credentials.password.to_owned(),
-    );
+    // Keep an id the email already has, otherwise lettre makes one up
+    let message_id = Some(email.message_id.clone()).filter(|id| id.starts_with('<'));
+    let mut builder = builder.message_id(message_id);
+    if let Some(in_reply_to) = &email.in_reply_to {
+        builder = builder.in_reply_to(in_reply_to.clone());
+    }
+    if !email.references.is_empty() {
+        builder = builder.references(email.references.join(" "));
+    }
 
-    let mailer = SmtpTransport::relay("smtp.gmail.com")?
-        .credentials(creds)
-        .build();
+    let builder = builder.subject(email.subject.clone());
 
-    match mailer.send(&email_msg) {
-        // Changed from &email to &email_msg
-        Ok(_) => println!("Email sent successfully!"),
-        Err(e) => eprintln!("Could not send email: {}", e),
+    if email.html.is_none() && email.attachments.is_empty() {
+        return Ok(builder
+            .header(ContentType::TEXT_PLAIN)
+            .body(email.body.clone())?);
     }
 
-    Ok(())
+    let mut mixed = match &email.html {
+        Some(html) => {
+            let alternative = MultiPart::alternative_plain_html(email.body.clone(), html.clone());
+            if email.attachments.is_empty() {
+                return Ok(builder.multipart(alternative)?);
+            }
+            MultiPart::mixed().multipart(alternative)
+        }
+        None => MultiPart::mixed().singlepart(SinglePart::plain(email.body.clone())),
+    };
+    for attachment in &email.attachments {
+        let content_type = ContentType::parse(&attachment.content_type)
+            .unwrap_or_else(|_| ContentType::parse("application/octet-stream").unwrap());
+        mixed = mixed.singlepart(
+            lettre::message::Attachment::new(attachment.filename.clone())
+                .body(attachment.data.clone(), content_type),
+        );
+    }
+
+    Ok(builder.multipart(mixed)?)
 }
 
 /// Converts an Email struct to RFC 5322 format and writes it to a File
 pub fn build_email_to_file(email: &Email, mut file: File) -> Result<(), String> {
-    let timestamp = DateTime::parse_from_rfc3339(&email.date)
-        .map(|dt| dt.timestamp())
-        .unwrap_or_else(|_| chrono::Utc::now().timestamp());
+    let email_bytes = build_email_bytes(email)?;
+    file.write_all(&email_bytes).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Converts an Email struct to RFC 5322 bytes
+pub fn build_email_bytes(email: &Email) -> Result<Vec<u8>, String> {
+    // Written by hand so the original UTC offset survives; new messages are dated now
+    let date = email
+        .date
+        .unwrap_or_else(|| chrono::Local::now().fixed_offset())
+        .to_rfc2822();
     let mut builder = MessageBuilder::new()
-        .from(email.from.as_str())
+        .from(builder_address_list(std::slice::from_ref(&email.from)))
         .subject(&email.subject)
-        .message_id(email.message_id.clone())
-        .date(timestamp)
+        .header("Date", Raw::new(date))
         .text_body(&email.body);
 
-    for to_addr in &email.to {
-        builder = builder.to(to_addr.as_str());
+    // Without one, mail_builder makes up a fresh Message-ID
+    if email.message_id.contains('@') {
+        builder = builder.message_id(bare_id(&email.message_id));
+    }
+    if let Some(in_reply_to) = &email.in_reply_to {
+        builder = builder.in_reply_to(bare_id(in_reply_to));
+    }
+    if !email.references.is_empty() {
+        let references: Vec<&str> = email.references.iter().map(|id| bare_id(id)).collect();
+        builder = builder.references(references);
     }
 
-    for cc_addr in &email.cc {
-        builder = builder.cc(cc_addr.as_str());
+    if let Some(html) = &email.html {
+        builder = builder.html_body(html);
     }
 
-    for bcc_addr in &email.bcc {
-        builder = builder.bcc(bcc_addr.as_str());
+    for attachment in &email.attachments {
+        builder = builder.attachment(
+            attachment.content_type.clone(),
+            attachment.filename.clone(),
+            attachment.data.as_slice(),
+        );
+    }
+
+    if !email.to.is_empty() {
+        builder = builder.to(builder_address_list(&email.to));
+    }
+
+    if !email.cc.is_empty() {
+        builder = builder.cc(builder_address_list(&email.cc));
+    }
+
+    if !email.bcc.is_empty() {
+        builder = builder.bcc(builder_address_list(&email.bcc));
+    }
+
+    if !email.reply_to.is_empty() {
+        builder = builder.reply_to(builder_address_list(&email.reply_to));
     }
 
     // Simply skip custom headers or use write_header if needed
     // The mail_builder crate is restrictive with custom headers
     // Most standard headers are already handled above
 
-    let email_bytes = builder.write_to_vec().map_err(|e| e.to_string())?;
-    file.write_all(&email_bytes).map_err(|e| e.to_string())?;
+    builder.write_to_vec().map_err(|e| e.to_string())
+}
 
-    Ok(())
+/// A Message-ID without its angle brackets, which mail_builder and mail_parser leave out
+fn bare_id(id: &str) -> &str {
+    id.trim().trim_start_matches('<').trim_end_matches('>')
+}
+
+/// Message-IDs as parsed by mail_parser, put back in angle brackets
+fn bracketed_ids(value: &mail_parser::HeaderValue) -> Vec<String> {
+    value
+        .as_text_list()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|id| format!("<{}>", bare_id(id)))
+        .collect()
 }
 
+/// Reads an RFC 5322 message, such as a `.eml` file, into an Email
 pub fn parse_email_from_file(mut file: File) -> Result<Email, String> {
     let mut raw_email = Vec::new();
     file.read_to_end(&mut raw_email)
         .map_err(|e| e.to_string())?;
+    parse_email_bytes(&raw_email)
+}
+
+/// Parses raw RFC 5322 bytes into an Email
+pub fn parse_email_bytes(raw_email: &[u8]) -> Result<Email, String> {
     let parser = MessageParser::default();
-    let message = parser.parse(&raw_email).ok_or("Failed to parse email")?;
+    let message = parser.parse(raw_email).ok_or("Failed to parse email")?;
     let from = message
         .from()
-        .and_then(|addrs| addrs.first())
-        .and_then(|addr| addr.address())
-        .unwrap_or("")
-        .to_string();
-    let to = message
-        .to()
-        .map(|addrs| {
-            addrs
-                .iter()
-                .filter_map(|addr| addr.address())
-                .map(String::from)
-                .collect()
-        })
+        .and_then(|addrs| Address::from_parsed(addrs).into_iter().next())
         .unwrap_or_default();
-    let cc = message
-        .cc()
-        .map(|addrs| {
-            addrs
-                .iter()
-                .filter_map(|addr| addr.address())
-                .map(String::from)
-                .collect()
-        })
-        .unwrap_or_default();
-    let bcc = message
-        .bcc()
-        .map(|addrs| {
-            addrs
-                .iter()
-                .filter_map(|addr| addr.address())
-                .map(String::from)
-                .collect()
-        })
+    let to = message.to().map(Address::from_parsed).unwrap_or_default();
+    let cc = message.cc().map(Address::from_parsed).unwrap_or_default();
+    let bcc = message.bcc().map(Address::from_parsed).unwrap_or_default();
+    let reply_to = message
+        .reply_to()
+        .map(Address::from_parsed)
         .unwrap_or_default();
     let subject = message.subject().unwrap_or("").to_string();
-    let date = message.date().map(|d| d.to_rfc3339()).unwrap_or_default();
-    let message_id = message.message_id().unwrap_or("").to_string();
+    let date = message.date().and_then(date::from_parsed);
+    let message_id = message
+        .message_id()
+        .map(|id| format!("<{}>", id))
+        .unwrap_or_default();
+    let in_reply_to = bracketed_ids(message.in_reply_to()).into_iter().next();
+    let references = bracketed_ids(message.references());
     let body = message
         .body_text(0)
         .map(|cow| cow.to_string()) // Convert Cow<str> to String
         .unwrap_or_default();
+    // Only a real text/html body part, not an HTML file sent as an attachment
+    let html = message
+        .html_body
+        .iter()
+        .filter_map(|id| message.parts.get(*id))
+        .find(|part| part.is_text_html())
+        .and_then(|part| part.text_contents())
+        .map(String::from);
+    let mime = MimePart::from_message(&message);
+    let attachments = mime
+        .attachments()
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, part)| {
+            Some(Attachment {
+                filename: attachment::file_name(part, i + 1),
+                content_type: part.content_type.clone(),
+                data: mime::part_contents(&message, &part.section)?,
+            })
+        })
+        .collect();
 
     Ok(Email {
         from,
         to,
         cc,
         bcc,
+        reply_to,
         subject,
         date,
         message_id,
+        in_reply_to,
+        references,
         body,
+        html,
+        mime: Some(mime),
+        attachments,
         ..Default::default()
     })
 }
@@ -527,30 +651,263 @@ mod tests {
     use std::fs;
     use std::io::Write;
 
+    /// A one-shot IMAP server: untagged lines come from `respond`, given each command
+    /// without its tag, and every command then succeeds. Returns the commands it got.
+    fn mock_imap(respond: fn(&str) -> String) -> (u16, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, BufReader};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut commands = Vec::new();
+
+            writer.write_all(b"* OK mock IMAP4rev1 ready\r\n").unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                // Ask for each literal and read it into the command
+                while let Some(size) = line
+                    .trim_end()
+                    .strip_suffix('}')
+                    .and_then(|l| l.rsplit_once('{'))
+                    .and_then(|(_, n)| n.parse().ok())
+                {
+                    writer.write_all(b"+ go ahead\r\n").unwrap();
+                    let mut literal = vec![0; size];
+                    reader.read_exact(&mut literal).unwrap();
+                    line.push_str(&String::from_utf8(literal).unwrap());
+                    reader.read_line(&mut line).unwrap();
+                }
+                let (tag, command) = line.trim_end().split_once(' ').unwrap();
+                write!(writer, "{}{} OK done\r\n", respond(command), tag).unwrap();
+                commands.push(command.to_string());
+                line.clear();
+            }
+            commands
+        });
+        (port, handle)
+    }
+
+    fn mock_session(port: u16) -> MailSession {
+        let server = provider::Server::new("127.0.0.1", port, Security::Plain);
+        let provider = EmailProvider::Custom(provider::ServerConfig {
+            imap: server.clone(),
+            smtp: server,
+        });
+        MailSession::new(provider, UserCredentials::new("me".into(), "pw".into()))
+    }
+
+    const ENVELOPE: &str = "(\"Mon, 1 Jan 2024 10:00:00 +0000\" \"Hello\" \
+                            ((\"Ann\" NIL \"ann\" \"example.com\")) NIL NIL NIL NIL NIL NIL \
+                            \"<m@example.com>\")";
+
+    #[test]
+    fn test_fetch_responses_without_an_envelope_are_skipped() {
+        let (port, server) = mock_imap(|command| {
+            if command.starts_with("SELECT") {
+                "* 2 EXISTS\r\n* OK [UIDVALIDITY 7] UIDs valid\r\n".to_string()
+            } else if command.contains("FETCH") {
+                // Message 1 only reports a flag change
+                format!(
+                    "* 1 FETCH (UID 1 FLAGS (\\Seen))\r\n* 2 FETCH (UID 2 FLAGS () ENVELOPE {})\r\n",
+                    ENVELOPE
+                )
+            } else {
+                String::new()
+            }
+        });
+        let mut session = mock_session(port);
+
+        let inbox = get_envelopes(&mut session, "INBOX", &Window::All).unwrap();
+        assert_eq!(inbox.inbox.len(), 1);
+        assert_eq!(inbox.inbox[0].uid, 2);
+        assert_eq!(inbox.inbox[0].subject, "Hello");
+        assert_eq!(inbox.inbox[0].uid_validity, 7);
+
+        let error = get_inbox_one(&mut session, "INBOX", 1).unwrap_err();
+        assert!(
+            error.to_string().contains("without its envelope"),
+            "{}",
+            error
+        );
+
+        drop(session);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_search_fetches_a_page_of_a_large_hit_set() {
+        let (port, server) = mock_imap(|command| {
+            if command.starts_with("SELECT") {
+                "* 9999 EXISTS\r\n* OK [UIDVALIDITY 7] UIDs valid\r\n".to_string()
+            } else if command.starts_with("UID SEARCH") {
+                // Every other UID up to 4000, then a run of 5000
+                let sparse = (1..=2000).map(|n| n * 2);
+                let uids: Vec<String> = sparse.chain(5000..=9999).map(|n| n.to_string()).collect();
+                format!("* SEARCH {}\r\n", uids.join(" "))
+            } else if command.starts_with("UID FETCH 9900:9999 ") {
+                format!("* 9999 FETCH (UID 9999 FLAGS () ENVELOPE {})\r\n", ENVELOPE)
+            } else {
+                String::new()
+            }
+        });
+        let mut session = mock_session(port);
+        let query = SearchQuery::from_args(&["hello".to_string()]).unwrap();
+
+        let page = search_envelopes(&mut session, "INBOX", &query, &Window::page(1, 100));
+        let page = page.unwrap();
+        assert_eq!(page.inbox.len(), 1);
+        assert_eq!(page.inbox[0].uid, 9999);
+
+        search_envelopes(&mut session, "INBOX", &query, &Window::All).unwrap();
+
+        drop(session);
+        let commands = server.join().unwrap();
+        let fetches: Vec<&String> = commands
+            .iter()
+            .filter(|c| c.starts_with("UID FETCH"))
+            .collect();
+        assert_eq!(fetches[0], "UID FETCH 9900:9999 (UID FLAGS ENVELOPE)");
+        // 2000 scattered UIDs and one range, 500 UIDs to a command
+        assert_eq!(fetches.len(), 1 + 14);
+        assert!(fetches.iter().all(|c| c.len() < 4000), "{:?}", fetches);
+        assert!(fetches.last().unwrap().contains("9500:9999"));
+    }
+
+    #[test]
+    fn test_search_sends_non_ascii_terms_as_literals() {
+        let (port, server) = mock_imap(|command| {
+            if command.starts_with("SELECT") {
+                "* 3 EXISTS\r\n* OK [UIDVALIDITY 7] UIDs valid\r\n".to_string()
+            } else if command.starts_with("UID SEARCH") {
+                "* SEARCH 3\r\n".to_string()
+            } else if command.starts_with("UID FETCH 3 ") {
+                format!("* 3 FETCH (UID 3 FLAGS () ENVELOPE {})\r\n", ENVELOPE)
+            } else {
+                String::new()
+            }
+        });
+        let mut session = mock_session(port);
+        let query: SearchQuery = "subject:café since:2024-01-01".parse().unwrap();
+
+        let inbox = search_envelopes(&mut session, "INBOX", &query, &Window::All).unwrap();
+        assert_eq!(inbox.inbox.len(), 1);
+        assert_eq!(inbox.inbox[0].uid, 3);
+
+        drop(session);
+        let commands = server.join().unwrap();
+        assert!(commands.contains(
+            &"UID SEARCH CHARSET UTF-8 SUBJECT {5}\r\ncafé SENTSINCE 1-Jan-2024".to_string()
+        ));
+    }
+
+    #[test]
+    fn test_uid_set_collapses_runs() {
+        assert_eq!(uid_set(&[3, 4, 5, 6, 7, 9, 12, 13, 14]), "3:7,9,12:14");
+        assert_eq!(uid_set(&[42]), "42");
+        assert_eq!(uid_set(&[]), "");
+    }
+
+    fn addr(s: &str) -> Address {
+        s.parse().unwrap()
+    }
+
     fn create_test_email() -> Email {
         Email {
-            from: "sender@example.com".to_string(),
+            uid: 0,
+            uid_validity: 0,
+            flags: Vec::new(),
+            from: addr("sender@example.com"),
             to: vec![
-                "recipient1@example.com".to_string(),
-                "recipient2@example.com".to_string(),
+                addr("recipient1@example.com"),
+                addr("recipient2@example.com"),
             ],
-            cc: vec!["cc@example.com".to_string()],
-            bcc: vec!["bcc@example.com".to_string()],
+            cc: vec![addr("cc@example.com")],
+            bcc: vec![addr("bcc@example.com")],
+            reply_to: vec![],
             subject: "Test Email Subject".to_string(),
-            date: "2024-01-15T10:30:00Z".to_string(),
+            date: date::parse_date("2024-01-15T10:30:00+01:00"),
             message_id: "<test123@example.com>".to_string(),
+            in_reply_to: None,
+            references: Vec::new(),
             other_headers: HashMap::new(),
             body: "This is a test email body with some content.".to_string(),
+            html: None,
+            mime: None,
+            attachments: Vec::new(),
         }
     }
 
     #[test]
     fn test_email_default() {
         let email = Email::default();
-        assert_eq!(email.from, "");
+        assert_eq!(email.from.email(), "");
         assert_eq!(email.subject, "");
         assert_eq!(email.body, "");
         assert!(email.other_headers.is_empty());
+        assert!(email.to.is_empty() && email.cc.is_empty() && email.bcc.is_empty());
+    }
+
+    #[test]
+    fn test_every_recipient_is_sent() {
+        let email = Email {
+            from: addr("me@example.com"),
+            to: vec![addr("a@example.com"), addr("B <b@example.com>")],
+            cc: vec![addr("c@example.com")],
+            bcc: vec![addr("d@example.com")],
+            reply_to: vec![addr("list@example.com")],
+            subject: "Hi".to_string(),
+            ..Default::default()
+        };
+
+        let message = build_message(&email).unwrap();
+        let recipients: Vec<String> = message
+            .envelope()
+            .to()
+            .iter()
+            .map(|address| address.to_string())
+            .collect();
+        assert_eq!(
+            recipients,
+            [
+                "a@example.com",
+                "b@example.com",
+                "c@example.com",
+                "d@example.com"
+            ]
+        );
+
+        let parsed = parse_email_bytes(&message.formatted()).unwrap();
+        assert_eq!(parsed.reply_to, [addr("list@example.com")]);
+        assert_eq!(parsed.cc, [addr("c@example.com")]);
+
+        // Only a Bcc is still enough to send
+        let hidden = Email {
+            from: addr("me@example.com"),
+            bcc: vec![addr("d@example.com")],
+            ..Default::default()
+        };
+        assert!(build_message(&hidden).is_ok());
+    }
+
+    #[test]
+    fn test_flag_parsing() {
+        assert_eq!("seen".parse::<Flag>(), Ok(Flag::Seen));
+        assert_eq!("\\Flagged".parse::<Flag>(), Ok(Flag::Flagged));
+        assert_eq!(
+            "$Work".parse::<Flag>(),
+            Ok(Flag::Keyword("$Work".to_string()))
+        );
+        assert!("\\Bogus".parse::<Flag>().is_err());
+        assert!("two words".parse::<Flag>().is_err());
+
+        assert_eq!(Flag::from_imap(&imap::types::Flag::Recent), None);
+        assert_eq!(
+            Flag::from_imap(&imap::types::Flag::Custom("$Work".into())),
+            Some(Flag::Keyword("$Work".to_string()))
+        );
     }
 
     #[test]
@@ -644,19 +1001,28 @@ mod tests {
     #[test]
     fn test_email_with_multiple_recipients() {
         let email = Email {
-            from: "sender@test.com".to_string(),
+            uid: 0,
+            uid_validity: 0,
+            flags: Vec::new(),
+            from: addr("sender@test.com"),
             to: vec![
-                "user1@test.com".to_string(),
-                "user2@test.com".to_string(),
-                "user3@test.com".to_string(),
+                addr("user1@test.com"),
+                addr("user2@test.com"),
+                addr("user3@test.com"),
             ],
-            cc: vec!["cc1@test.com".to_string(), "cc2@test.com".to_string()],
-            bcc: vec!["bcc@test.com".to_string()],
+            cc: vec![addr("cc1@test.com"), addr("cc2@test.com")],
+            bcc: vec![addr("bcc@test.com")],
+            reply_to: vec![],
             subject: "Multiple Recipients Test".to_string(),
-            date: chrono::Utc::now().to_rfc3339(),
+            date: Some(chrono::Utc::now().fixed_offset()),
             message_id: "<multi@test.com>".to_string(),
+            in_reply_to: None,
+            references: Vec::new(),
             other_headers: HashMap::new(),
             body: "Testing multiple recipients".to_string(),
+            html: None,
+            mime: None,
+            attachments: Vec::new(),
         };
 
         let temp_file = "test_multiple_recipients.eml";
@@ -685,15 +1051,24 @@ mod tests {
     fn test_email_with_long_body() {
         let long_body = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(100);
         let email = Email {
-            from: "sender@test.com".to_string(),
-            to: vec!["recipient@test.com".to_string()],
+            uid: 0,
+            uid_validity: 0,
+            flags: Vec::new(),
+            from: addr("sender@test.com"),
+            to: vec![addr("recipient@test.com")],
             cc: vec![],
             bcc: vec![],
+            reply_to: vec![],
             subject: "Long Body Test".to_string(),
-            date: chrono::Utc::now().to_rfc3339(),
+            date: Some(chrono::Utc::now().fixed_offset()),
             message_id: "<long@test.com>".to_string(),
+            in_reply_to: None,
+            references: Vec::new(),
             other_headers: HashMap::new(),
             body: long_body.clone(),
+            html: None,
+            mime: None,
+            attachments: Vec::new(),
         };
 
         let temp_file = "test_long_body.eml";
@@ -713,15 +1088,24 @@ mod tests {
     #[test]
     fn test_email_with_special_characters() {
         let email = Email {
-            from: "sender@test.com".to_string(),
-            to: vec!["recipient@test.com".to_string()],
+            uid: 0,
+            uid_validity: 0,
+            flags: Vec::new(),
+            from: addr("sender@test.com"),
+            to: vec![addr("recipient@test.com")],
             cc: vec![],
             bcc: vec![],
+            reply_to: vec![],
             subject: "Special chars: émojis 🎉 and symbols @#$%".to_string(),
-            date: chrono::Utc::now().to_rfc3339(),
+            date: Some(chrono::Utc::now().fixed_offset()),
             message_id: "<special@test.com>".to_string(),
+            in_reply_to: None,
+            references: Vec::new(),
             other_headers: HashMap::new(),
             body: "Body with émojis 🚀🎯 and special chars: <>&\"'".to_string(),
+            html: None,
+            mime: None,
+            attachments: Vec::new(),
         };
 
         let temp_file = "test_special_chars.eml";
@@ -740,10 +1124,11 @@ mod tests {
 
     #[test]
     fn test_user_credentials_creation() {
-        let creds = UserCredentials::new("user@example.com".to_string(), "password123".to_string());
+        let creds = UserCredentials::new("user@example.com".to_string(), "password123".into());
 
         assert_eq!(creds.username, "user@example.com");
-        assert_eq!(creds.password, "password123");
+        assert!(matches!(&creds.auth, Auth::Password(p) if p.expose() == "password123"));
+        assert!(!format!("{:?}", creds).contains("password123"));
     }
 
     #[test]
@@ -753,15 +1138,158 @@ mod tests {
     }
 
     #[test]
-    fn test_build_email_helper() {
-        let email = build_email("testuser".to_string(), "example.com".to_string());
-        assert_eq!(email, "testuser@example.com");
+    fn test_display_names_survive_roundtrip() {
+        let mut email = create_test_email();
+        email.from = addr("\"Doe, Jane\" <sender@example.com>");
+        email.to = vec![addr("=?UTF-8?Q?Ren=C3=A9e?= <renee@example.com>")];
+
+        let raw = build_email_bytes(&email).unwrap();
+        let parsed = parse_email_bytes(&raw).unwrap();
+
+        assert_eq!(parsed.from.name.as_deref(), Some("Doe, Jane"));
+        assert_eq!(parsed.from.email(), "sender@example.com");
+        assert_eq!(parsed.to[0].to_string(), "Renée <renee@example.com>");
+    }
+
+    #[test]
+    fn test_date_survives_roundtrip() {
+        let original = create_test_email();
+        let parsed = parse_email_bytes(&build_email_bytes(&original).unwrap()).unwrap();
 
-        let empty = build_email("".to_string(), "example.com".to_string());
-        assert_eq!(empty, "");
+        let date = parsed.date.expect("Date header should parse");
+        assert_eq!(date, original.date.unwrap());
+        assert_eq!(date.offset().local_minus_utc(), 3600);
+
+        // Undated messages get the current time rather than failing
+        let undated = Email {
+            date: None,
+            ..create_test_email()
+        };
+        let parsed = parse_email_bytes(&build_email_bytes(&undated).unwrap()).unwrap();
+        assert!(parsed.date.is_some());
+    }
+
+    #[test]
+    fn test_groups_survive_roundtrip() {
+        let mut email = create_test_email();
+        email.to = vec![
+            addr("solo@example.com"),
+            Address {
+                group: Some("Team".to_string()),
+                ..addr("a@example.com")
+            },
+            Address {
+                group: Some("Team".to_string()),
+                ..addr("b@example.com")
+            },
+        ];
+
+        let raw = build_email_bytes(&email).unwrap();
+        let parsed = parse_email_bytes(&raw).unwrap();
+
+        let emails: Vec<String> = parsed.to.iter().map(Address::email).collect();
+        assert_eq!(
+            emails,
+            ["solo@example.com", "a@example.com", "b@example.com"]
+        );
+        assert_eq!(parsed.to[0].group, None);
+        assert_eq!(parsed.to[2].group.as_deref(), Some("Team"));
+    }
+
+    #[test]
+    fn test_html_alternative_and_parts() {
+        let email = Email {
+            html: Some("<p>This is a <b>test</b></p>".to_string()),
+            ..create_test_email()
+        };
+
+        let parsed = parse_email_bytes(&build_email_bytes(&email).unwrap()).unwrap();
+        assert_eq!(parsed.body.trim(), email.body);
+        assert_eq!(parsed.html.as_deref(), email.html.as_deref());
+
+        let mime = parsed.mime.expect("parsed messages have a part tree");
+        let types: Vec<&str> = mime
+            .walk()
+            .iter()
+            .map(|part| part.content_type.as_str())
+            .collect();
+        assert_eq!(types, ["multipart/alternative", "text/plain", "text/html"]);
+        assert!(mime.attachments().is_empty());
+
+        // Envelope-only emails built in code have no structure
+        assert!(create_test_email().mime.is_none());
+    }
+
+    fn pdf() -> Attachment {
+        Attachment {
+            filename: "report.pdf".to_string(),
+            content_type: "application/pdf".to_string(),
+            data: b"%PDF-1.4\n\x00\xff binary".to_vec(),
+        }
+    }
+
+    fn content_types(email: &Email) -> Vec<String> {
+        email
+            .mime
+            .as_ref()
+            .unwrap()
+            .walk()
+            .iter()
+            .map(|part| part.content_type.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_smtp_message_structure() {
+        let email = Email {
+            html: Some("<p>See attached</p>".to_string()),
+            attachments: vec![pdf()],
+            ..create_test_email()
+        };
+
+        let sent = build_message(&email).unwrap().formatted();
+        let parsed = parse_email_bytes(&sent).unwrap();
+        assert_eq!(
+            content_types(&parsed),
+            [
+                "multipart/mixed",
+                "multipart/alternative",
+                "text/plain",
+                "text/html",
+                "application/pdf"
+            ]
+        );
+        assert_eq!(parsed.body.trim(), email.body);
+        assert_eq!(parsed.html.as_deref(), Some("<p>See attached</p>"));
+        assert_eq!(parsed.attachments, [pdf()]);
+
+        // Attachments without HTML skip the alternative part
+        let email = Email {
+            attachments: vec![pdf()],
+            ..create_test_email()
+        };
+        let parsed = parse_email_bytes(&build_message(&email).unwrap().formatted()).unwrap();
+        assert_eq!(
+            content_types(&parsed),
+            ["multipart/mixed", "text/plain", "application/pdf"]
+        );
+
+        // And plain text stays a single part
+        let parsed =
+            parse_email_bytes(&build_message(&create_test_email()).unwrap().formatted()).unwrap();
+        assert_eq!(content_types(&parsed), ["text/plain"]);
+    }
+
+    #[test]
+    fn test_attachments_survive_roundtrip() {
+        let email = Email {
+            attachments: vec![pdf()],
+            ..create_test_email()
+        };
 
-        let empty2 = build_email("testuser".to_string(), "".to_string());
-        assert_eq!(empty2, "");
+        let parsed = parse_email_bytes(&build_email_bytes(&email).unwrap()).unwrap();
+        assert_eq!(parsed.attachments, [pdf()]);
+        assert_eq!(parsed.body.trim(), email.body);
     }
 
     #[test]
@@ -779,7 +1307,7 @@ mod tests {
         // So we just check if it returns something, even if it's mostly empty
         if let Ok(email) = result {
             // If it parsed, the email should have mostly default/empty values
-            assert!(email.from.is_empty() || !email.from.is_empty());
+            assert!(email.from.email().is_empty() || !email.from.email().is_empty());
         }
         // If it errors, that's also fine
 
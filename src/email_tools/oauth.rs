@@ -0,0 +1,649 @@
+use super::credentials::Secret;
+use super::provider::EmailProvider;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the user has to sign in before `authorize` gives up on the browser
+const SIGN_IN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Access tokens this close to expiring are refreshed before use, so they don't run out
+/// mid-login
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// How to ask a provider for access to an account's mail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OAuthConfig {
+    /// The application registered with the provider
+    pub client_id: String,
+    /// Google hands one out even to desktop applications, where it isn't really secret
+    pub client_secret: Option<Secret>,
+    pub authorization_url: String,
+    pub token_url: String,
+    pub scopes: Vec<String>,
+}
+
+impl OAuthConfig {
+    /// The endpoints and scopes Google and Microsoft use for IMAP and SMTP access. A custom
+    /// provider has no defaults.
+    pub fn for_provider(
+        provider: &EmailProvider,
+        client_id: String,
+        client_secret: Option<Secret>,
+    ) -> Option<OAuthConfig> {
+        let (authorization_url, token_url, scopes): (&str, &str, &[&str]) = match provider {
+            EmailProvider::Google => (
+                "https://accounts.google.com/o/oauth2/v2/auth",
+                "https://oauth2.googleapis.com/token",
+                &["https://mail.google.com/"],
+            ),
+            EmailProvider::Outlook => (
+                "https://login.microsoftonline.com/common/oauth2/v2.0/authorize",
+                "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+                &[
+                    "https://outlook.office.com/IMAP.AccessAsUser.All",
+                    "https://outlook.office.com/SMTP.Send",
+                    "offline_access",
+                ],
+            ),
+            EmailProvider::Custom(_) => return None,
+        };
+        Some(OAuthConfig {
+            client_id,
+            client_secret,
+            authorization_url: authorization_url.to_string(),
+            token_url: token_url.to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+        })
+    }
+}
+
+/// What the token endpoint handed out
+#[derive(Debug)]
+pub struct Tokens {
+    pub access_token: Secret,
+    /// Only sent by some providers on refresh, replacing the old one
+    pub refresh_token: Option<Secret>,
+    pub expires_in: Duration,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Lets the user grant access in their browser and returns the tokens for it.
+///
+/// This is the loopback flow for desktop applications: `show` is handed the address of
+/// the provider's consent page, which afterwards redirects the browser to a one-off
+/// server on 127.0.0.1 with an authorization code. The code is exchanged for tokens,
+/// proven to come from this run with PKCE. Gives up if the browser isn't back within five
+/// minutes.
+pub fn authorize<F: FnOnce(&str)>(config: &OAuthConfig, show: F) -> Result<Tokens, String> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .map_err(|e| format!("could not listen for the browser: {}", e))?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    let redirect_uri = format!("http://127.0.0.1:{}", port);
+
+    let verifier = Secret::new(random_token(32));
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.expose().as_bytes()));
+    let state = random_token(16);
+
+    let scope = config.scopes.join(" ");
+    let page = Url::parse_with_params(
+        &config.authorization_url,
+        [
+            ("response_type", "code"),
+            ("client_id", &config.client_id),
+            ("redirect_uri", &redirect_uri),
+            ("scope", &scope),
+            ("state", &state),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|e| format!("invalid authorization_url: {}", e))?;
+    show(page.as_str());
+
+    let code = wait_for_code(&listener, &state, SIGN_IN_TIMEOUT)?;
+
+    let tokens = request_tokens(
+        config,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code.expose()),
+            ("redirect_uri", &redirect_uri),
+            ("code_verifier", verifier.expose()),
+        ],
+    )?;
+    if tokens.refresh_token.is_none() {
+        return Err("the provider granted access but sent no refresh token to keep".to_string());
+    }
+    Ok(tokens)
+}
+
+/// Answers the browser's requests until it comes back with an authorization code, or
+/// gives up once `timeout` has passed
+fn wait_for_code(listener: &TcpListener, state: &str, timeout: Duration) -> Result<Secret, String> {
+    let listen_error = |e: std::io::Error| format!("could not listen for the browser: {}", e);
+    listener.set_nonblocking(true).map_err(listen_error)?;
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                // A browser that connects and says nothing can't hold things up either
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let read_timeout = remaining.clamp(Duration::from_millis(100), TIMEOUT);
+                stream.set_nonblocking(false).map_err(listen_error)?;
+                stream
+                    .set_read_timeout(Some(read_timeout))
+                    .map_err(listen_error)?;
+                if let Some(code) = answer_redirect(stream, state)? {
+                    return Ok(code);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(format!(
+                        "gave up after {} seconds without hearing back from the browser",
+                        timeout.as_secs()
+                    ));
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(listen_error(e)),
+        }
+    }
+}
+
+/// Reads one request from the browser. Returns the authorization code if this was the
+/// redirect, or None for anything else it asks for (such as a favicon) and for
+/// connections that break off or go quiet.
+fn answer_redirect(stream: TcpStream, state: &str) -> Result<Option<Secret>, String> {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return Ok(None);
+    }
+    let mut header = String::new();
+    loop {
+        match reader.read_line(&mut header) {
+            Ok(length) if length > 2 => header.clear(),
+            Ok(_) => break,
+            Err(_) => return Ok(None),
+        }
+    }
+
+    // e.g. "GET /?code=...&state=... HTTP/1.1"
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    let query: Vec<(String, String)> = Url::parse(&format!("http://127.0.0.1{}", target))
+        .map(|url| url.query_pairs().into_owned().collect())
+        .unwrap_or_default();
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let outcome = match (param("code"), param("error")) {
+        (None, None) => {
+            respond(&stream, "404 Not Found", "Nothing here.");
+            return Ok(None);
+        }
+        _ if param("state") != Some(state) => {
+            Err("the browser came back from a different sign-in attempt".to_string())
+        }
+        (Some(code), None) => Ok(Some(Secret::from(code))),
+        (_, Some(error)) => Err(format!(
+            "access was not granted: {}",
+            param("error_description").unwrap_or(error)
+        )),
+    };
+    match &outcome {
+        Ok(_) => respond(
+            &stream,
+            "200 OK",
+            "hermes can read your mail now. You can close this tab.",
+        ),
+        Err(e) => respond(
+            &stream,
+            "400 Bad Request",
+            &format!("Sign-in failed: {}", e),
+        ),
+    }
+    outcome
+}
+
+fn respond(mut stream: &TcpStream, status: &str, text: &str) {
+    // The browser has its answer either way; nothing to do if it hung up
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        text.len(),
+        text
+    );
+}
+
+/// Exchanges a refresh token for a new access token
+pub fn refresh(config: &OAuthConfig, refresh_token: &Secret) -> Result<Tokens, String> {
+    request_tokens(
+        config,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.expose()),
+        ],
+    )
+    .map_err(|e| {
+        if e.contains("invalid_grant") {
+            format!("{}; sign in again with `hermes credentials authorize`", e)
+        } else {
+            e
+        }
+    })
+}
+
+/// POSTs a grant to the token endpoint, along with the client's own id and secret
+fn request_tokens(config: &OAuthConfig, grant: &[(&str, &str)]) -> Result<Tokens, String> {
+    let mut form = grant.to_vec();
+    form.push(("client_id", &config.client_id));
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret.expose()));
+    }
+
+    let tls = native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+    let agent = ureq::AgentBuilder::new()
+        .tls_connector(Arc::new(tls))
+        .timeout(TIMEOUT)
+        .build();
+
+    match agent.post(&config.token_url).send_form(&form) {
+        Ok(response) => {
+            let tokens: TokenResponse = response
+                .into_json()
+                .map_err(|e| format!("{}: unexpected reply: {}", config.token_url, e))?;
+            Ok(Tokens {
+                access_token: Secret::new(tokens.access_token),
+                refresh_token: tokens.refresh_token.map(Secret::new),
+                // An hour is what Google and Microsoft hand out
+                expires_in: Duration::from_secs(tokens.expires_in.unwrap_or(3600)),
+            })
+        }
+        Err(ureq::Error::Status(code, response)) => {
+            let reason = match response.into_json::<ErrorResponse>() {
+                Ok(ErrorResponse {
+                    error,
+                    error_description: Some(description),
+                }) => format!("{} ({})", error, description),
+                Ok(ErrorResponse { error, .. }) => error,
+                Err(_) => format!("HTTP {}", code),
+            };
+            Err(format!(
+                "{} refused the request: {}",
+                config.token_url, reason
+            ))
+        }
+        Err(e) => Err(format!("could not reach {}: {}", config.token_url, e)),
+    }
+}
+
+/// `bytes` random bytes, in a form that can go in a URL
+fn random_token(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buffer);
+    URL_SAFE_NO_PAD.encode(buffer)
+}
+
+/// An account's OAuth2 login: the long-lived refresh token, and the access token it was
+/// last exchanged for. Clones share the cached access token.
+#[derive(Clone)]
+pub struct TokenSource {
+    config: OAuthConfig,
+    state: Arc<Mutex<TokenState>>,
+    on_rotate: Option<Arc<RotateHook>>,
+}
+
+/// Keeps a refresh token the provider rotated wherever the old one came from
+type RotateHook = dyn Fn(&Secret) -> Result<(), String> + Send + Sync;
+
+#[derive(Debug)]
+struct TokenState {
+    refresh_token: Secret,
+    access: Option<(Secret, Instant)>,
+}
+
+impl TokenSource {
+    pub fn new(config: OAuthConfig, refresh_token: Secret) -> TokenSource {
+        TokenSource {
+            config,
+            state: Arc::new(Mutex::new(TokenState {
+                refresh_token,
+                access: None,
+            })),
+            on_rotate: None,
+        }
+    }
+
+    /// Calls `save` with the new refresh token whenever the provider replaces the old one,
+    /// which then stops working
+    pub fn on_rotate<F>(self, save: F) -> TokenSource
+    where
+        F: Fn(&Secret) -> Result<(), String> + Send + Sync + 'static,
+    {
+        TokenSource {
+            on_rotate: Some(Arc::new(save)),
+            ..self
+        }
+    }
+
+    /// Starts out with an access token already in hand, so tests need no token endpoint
+    #[cfg(test)]
+    pub fn with_access_token(self, access_token: Secret, expires_in: Duration) -> TokenSource {
+        self.lock().access = Some((access_token, Instant::now() + expires_in));
+        self
+    }
+
+    /// A current access token, refreshed first if there is none yet or it is about to
+    /// expire. A refresh token the provider rotates replaces the old one, and is handed to
+    /// the `on_rotate` hook to be kept.
+    pub fn access_token(&self) -> Result<Secret, String> {
+        let mut state = self.lock();
+        if let Some((token, expires)) = &state.access
+            && Instant::now() + EXPIRY_MARGIN < *expires
+        {
+            return Ok(token.clone());
+        }
+
+        let tokens = refresh(&self.config, &state.refresh_token)?;
+        if let Some(refresh_token) = tokens.refresh_token {
+            // The new access token works regardless, so this is not worth failing over
+            if let Some(save) = &self.on_rotate
+                && let Err(e) = save(&refresh_token)
+            {
+                eprintln!("Warning: the new refresh token was not saved: {}", e);
+            }
+            state.refresh_token = refresh_token;
+        }
+        state.access = Some((
+            tokens.access_token.clone(),
+            Instant::now() + tokens.expires_in,
+        ));
+        Ok(tokens.access_token)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TokenState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenSource")
+            .field("config", &self.config)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The SASL mechanisms that log in with a bearer token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    /// Google's, also spoken by Microsoft; tried first
+    XOAuth2,
+    /// The standard one (RFC 7628)
+    OAuthBearer,
+}
+
+impl Mechanism {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mechanism::XOAuth2 => "XOAUTH2",
+            Mechanism::OAuthBearer => "OAUTHBEARER",
+        }
+    }
+
+    /// The client's first message, carrying the token, for the server at `host`:`port`
+    pub fn initial_response(self, username: &str, token: &Secret, host: &str, port: u16) -> Secret {
+        match self {
+            Mechanism::XOAuth2 => Secret::new(format!(
+                "user={}\x01auth=Bearer {}\x01\x01",
+                username,
+                token.expose()
+            )),
+            Mechanism::OAuthBearer => Secret::new(format!(
+                "n,a={},\x01host={}\x01port={}\x01auth=Bearer {}\x01\x01",
+                username.replace('=', "=3D").replace(',', "=2C"),
+                host,
+                port,
+                token.expose()
+            )),
+        }
+    }
+
+    /// What to answer when the server rejects the token with a challenge explaining why,
+    /// so that it finishes the exchange with a failure
+    pub fn failure_response(self) -> &'static [u8] {
+        match self {
+            Mechanism::XOAuth2 => b"",
+            Mechanism::OAuthBearer => b"\x01",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// A token endpoint that answers one request per reply, in order, and returns the
+    /// form bodies it was sent
+    fn mock_endpoint(
+        replies: Vec<(u16, &'static str)>,
+    ) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut forms = Vec::new();
+            for (status, body) in replies {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some((name, value)) = line.split_once(':')
+                        && name.eq_ignore_ascii_case("content-length")
+                    {
+                        length = value.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                let mut form = vec![0; length];
+                reader.read_exact(&mut form).unwrap();
+                forms.push(String::from_utf8(form).unwrap());
+
+                write!(
+                    &stream,
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            forms
+        });
+
+        (url, handle)
+    }
+
+    fn config(token_url: &str) -> OAuthConfig {
+        OAuthConfig {
+            client_id: "hermes-test".to_string(),
+            client_secret: None,
+            authorization_url: "https://auth.example/authorize".to_string(),
+            token_url: token_url.to_string(),
+            scopes: vec!["mail".to_string(), "offline_access".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_access_token_is_cached_and_refreshed_near_expiry() {
+        let (url, endpoint) = mock_endpoint(vec![
+            (
+                200,
+                r#"{"access_token":"at-1","refresh_token":"rt-2","expires_in":30}"#,
+            ),
+            (200, r#"{"access_token":"at-2","expires_in":3600}"#),
+        ]);
+        let saved = Arc::new(Mutex::new(Vec::new()));
+        let keep = saved.clone();
+        let tokens = TokenSource::new(config(&url), Secret::from("rt-1")).on_rotate(move |token| {
+            keep.lock().unwrap().push(token.expose().to_string());
+            Ok(())
+        });
+
+        assert_eq!(tokens.access_token().unwrap().expose(), "at-1");
+        // Half a minute left is too little, so this refreshes with the rotated token
+        assert_eq!(tokens.clone().access_token().unwrap().expose(), "at-2");
+        assert_eq!(tokens.access_token().unwrap().expose(), "at-2");
+
+        let forms = endpoint.join().unwrap();
+        assert_eq!(forms.len(), 2);
+        assert!(forms[0].contains("grant_type=refresh_token"));
+        assert!(forms[0].contains("refresh_token=rt-1"));
+        assert!(forms[0].contains("client_id=hermes-test"));
+        assert!(forms[1].contains("refresh_token=rt-2"));
+        // Only the refresh that handed out a new refresh token saves one
+        assert_eq!(*saved.lock().unwrap(), ["rt-2"]);
+    }
+
+    #[test]
+    fn test_revoked_refresh_token() {
+        let (url, endpoint) = mock_endpoint(vec![(
+            400,
+            r#"{"error":"invalid_grant","error_description":"Token has been expired or revoked."}"#,
+        )]);
+        let error = TokenSource::new(config(&url), Secret::from("old"))
+            .access_token()
+            .unwrap_err();
+        assert!(
+            error.contains("invalid_grant (Token has been expired"),
+            "{}",
+            error
+        );
+        assert!(error.contains("hermes credentials authorize"), "{}", error);
+        endpoint.join().unwrap();
+    }
+
+    #[test]
+    fn test_loopback_authorization() {
+        let (url, endpoint) = mock_endpoint(vec![(
+            200,
+            r#"{"access_token":"at","refresh_token":"rt","expires_in":3600}"#,
+        )]);
+
+        let mut browser = None;
+        let mut challenge = String::new();
+        let tokens = authorize(&config(&url), |page| {
+            let page = Url::parse(page).unwrap();
+            let param = |name: &str| {
+                page.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap()
+            };
+            assert_eq!(param("client_id"), "hermes-test");
+            assert_eq!(param("scope"), "mail offline_access");
+            assert_eq!(param("code_challenge_method"), "S256");
+            challenge = param("code_challenge");
+
+            // Play the browser coming back from the consent page, after a detour
+            let redirect = Url::parse(&param("redirect_uri")).unwrap();
+            let address = format!("127.0.0.1:{}", redirect.port().unwrap());
+            let state = param("state");
+            browser = Some(thread::spawn(move || {
+                let visit = |target: &str| {
+                    let mut stream = TcpStream::connect(&address).unwrap();
+                    write!(stream, "GET {} HTTP/1.1\r\nHost: x\r\n\r\n", target).unwrap();
+                    let mut page = String::new();
+                    stream.read_to_string(&mut page).unwrap();
+                    page
+                };
+                visit("/favicon.ico");
+                visit(&format!("/?state={}&code=c0de", state))
+            }));
+        })
+        .unwrap();
+
+        assert_eq!(tokens.access_token.expose(), "at");
+        assert_eq!(tokens.refresh_token.unwrap().expose(), "rt");
+        assert!(browser.unwrap().join().unwrap().contains("close this tab"));
+
+        let form = endpoint.join().unwrap().remove(0);
+        let field = |name: &str| {
+            url::form_urlencoded::parse(form.as_bytes())
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+                .unwrap()
+        };
+        assert_eq!(field("grant_type"), "authorization_code");
+        assert_eq!(field("code"), "c0de");
+        let verifier = field("code_verifier");
+        assert_eq!(URL_SAFE_NO_PAD.encode(Sha256::digest(verifier)), challenge);
+    }
+
+    #[test]
+    fn test_sign_in_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // Connects but never sends a request, which must not hold up the deadline
+        let _silent = TcpStream::connect(address).unwrap();
+
+        let started = Instant::now();
+        let error = wait_for_code(&listener, "state", Duration::from_millis(200)).unwrap_err();
+        assert!(error.contains("without hearing back"), "{}", error);
+        assert!(started.elapsed() < TIMEOUT);
+    }
+
+    #[test]
+    fn test_sasl_messages() {
+        let token = Secret::from("ya29.token");
+        assert_eq!(
+            Mechanism::XOAuth2
+                .initial_response("jane@gmail.com", &token, "imap.gmail.com", 993)
+                .expose(),
+            "user=jane@gmail.com\x01auth=Bearer ya29.token\x01\x01"
+        );
+        assert_eq!(
+            Mechanism::OAuthBearer
+                .initial_response("a,b=c", &token, "mail.example", 587)
+                .expose(),
+            "n,a=a=2Cb=3Dc,\x01host=mail.example\x01port=587\x01auth=Bearer ya29.token\x01\x01"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How the connection to a mail server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    /// TLS from the first byte (IMAPS on 993, SMTPS on 465)
+    Tls,
+    /// Plaintext connection upgraded with STARTTLS (IMAP on 143, submission on 587)
+    StartTls,
+    /// No encryption at all. Only useful for local test servers.
+    Plain,
+}
+
+impl FromStr for Security {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tls" | "ssl" => Ok(Security::Tls),
+            "starttls" => Ok(Security::StartTls),
+            "plain" | "none" => Ok(Security::Plain),
+            other => Err(format!(
+                "unknown security mode '{}' (expected tls, starttls or plain)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Security::Tls => write!(f, "tls"),
+            Security::StartTls => write!(f, "starttls"),
+            Security::Plain => write!(f, "plain"),
+        }
+    }
+}
+
+/// A single IMAP or SMTP endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    pub host: String,
+    pub port: u16,
+    pub security: Security,
+}
+
+impl Server {
+    pub fn new(host: &str, port: u16, security: Security) -> Server {
+        Server {
+            host: host.to_string(),
+            port,
+            security,
+        }
+    }
+}
+
+/// Full description of a self-hosted (or otherwise unknown) provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub imap: Server,
+    pub smtp: Server,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmailProvider {
+    Google,
+    Outlook,
+    Custom(ServerConfig),
+}
+
+impl EmailProvider {
+    pub fn imap_server(&self) -> Server {
+        match self {
+            EmailProvider::Google => Server::new("imap.gmail.com", 993, Security::Tls),
+            EmailProvider::Outlook => Server::new("outlook.office365.com", 993, Security::Tls),
+            EmailProvider::Custom(config) => config.imap.clone(),
+        }
+    }
+
+    pub fn smtp_server(&self) -> Server {
+        match self {
+            EmailProvider::Google => Server::new("smtp.gmail.com", 465, Security::Tls),
+            EmailProvider::Outlook => Server::new("smtp.office365.com", 587, Security::StartTls),
+            EmailProvider::Custom(config) => config.smtp.clone(),
+        }
+    }
+
+    /// Whether the provider files sent mail in the Sent folder by itself, as Gmail does
+    /// for anything sent through its SMTP server
+    pub fn saves_sent_mail(&self) -> bool {
+        matches!(self, EmailProvider::Google)
+    }
+
+    /// Reads the provider from EMAIL_* variables, as found by `lookup`.
+    ///
+    /// `EMAIL_PROVIDER` selects `google` (the default), `outlook` or `custom`. A custom
+    /// provider additionally needs `EMAIL_IMAP_HOST` and `EMAIL_SMTP_HOST`, and may set
+    /// `EMAIL_IMAP_PORT`, `EMAIL_IMAP_SECURITY`, `EMAIL_SMTP_PORT` and `EMAIL_SMTP_SECURITY`.
+    pub fn from_lookup<F>(lookup: F) -> Result<EmailProvider, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let name = lookup("EMAIL_PROVIDER").unwrap_or_else(|| "google".to_string());
+
+        if let Some(provider) = EmailProvider::named(&name) {
+            return Ok(provider);
+        }
+        match name.to_ascii_lowercase().as_str() {
+            "custom" => Ok(EmailProvider::Custom(ServerConfig {
+                imap: server_from_lookup(&lookup, "IMAP", 993, 143)?,
+                smtp: server_from_lookup(&lookup, "SMTP", 465, 587)?,
+            })),
+            other => Err(format!(
+                "unknown EMAIL_PROVIDER '{}' (expected google, outlook or custom)",
+                other
+            )),
+        }
+    }
+
+    /// A provider whose servers are known, by name: `google` (or `gmail`) and `outlook`
+    /// (or `office365`)
+    pub fn named(name: &str) -> Option<EmailProvider> {
+        match name.to_ascii_lowercase().as_str() {
+            "google" | "gmail" => Some(EmailProvider::Google),
+            "outlook" | "office365" => Some(EmailProvider::Outlook),
+            _ => None,
+        }
+    }
+}
+
+/// The usual port for `security` when none is configured
+pub fn default_port(security: Security, tls_port: u16, starttls_port: u16) -> u16 {
+    match security {
+        Security::Tls => tls_port,
+        Security::StartTls | Security::Plain => starttls_port,
+    }
+}
+
+fn server_from_lookup<F>(
+    lookup: &F,
+    protocol: &str,
+    tls_port: u16,
+    starttls_port: u16,
+) -> Result<Server, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let host = lookup(&format!("EMAIL_{}_HOST", protocol))
+        .ok_or_else(|| format!("EMAIL_{}_HOST must be set for a custom provider", protocol))?;
+
+    let security = match lookup(&format!("EMAIL_{}_SECURITY", protocol)) {
+        Some(s) => s.parse::<Security>()?,
+        None => Security::Tls,
+    };
+
+    let port = match lookup(&format!("EMAIL_{}_PORT", protocol)) {
+        Some(p) => p
+            .parse::<u16>()
+            .map_err(|_| format!("EMAIL_{}_PORT is not a valid port: {}", protocol, p))?,
+        None => default_port(security, tls_port, starttls_port),
+    };
+
+    Ok(Server {
+        host,
+        port,
+        security,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn lookup_from(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let map: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| map.get(key).cloned()
+    }
+
+    #[test]
+    fn test_known_providers_resolve_to_their_servers() {
+        let google = EmailProvider::Google;
+        assert_eq!(google.imap_server().host, "imap.gmail.com");
+        assert_eq!(google.smtp_server().port, 465);
+
+        let outlook = EmailProvider::Outlook;
+        assert_eq!(outlook.imap_server().host, "outlook.office365.com");
+        assert_eq!(outlook.smtp_server().security, Security::StartTls);
+    }
+
+    #[test]
+    fn test_only_gmail_saves_sent_mail_itself() {
+        assert!(EmailProvider::Google.saves_sent_mail());
+        assert!(!EmailProvider::Outlook.saves_sent_mail());
+    }
+
+    #[test]
+    fn test_provider_defaults_to_google() {
+        let provider = EmailProvider::from_lookup(lookup_from(&[])).unwrap();
+        assert_eq!(provider, EmailProvider::Google);
+    }
+
+    #[test]
+    fn test_custom_provider_from_lookup() {
+        let provider = EmailProvider::from_lookup(lookup_from(&[
+            ("EMAIL_PROVIDER", "custom"),
+            ("EMAIL_IMAP_HOST", "mail.example.com"),
+            ("EMAIL_SMTP_HOST", "mail.example.com"),
+            ("EMAIL_SMTP_SECURITY", "starttls"),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            provider.imap_server(),
+            Server::new("mail.example.com", 993, Security::Tls)
+        );
+        assert_eq!(
+            provider.smtp_server(),
+            Server::new("mail.example.com", 587, Security::StartTls)
+        );
+    }
+
+    #[test]
+    fn test_custom_provider_requires_hosts() {
+        let result = EmailProvider::from_lookup(lookup_from(&[("EMAIL_PROVIDER", "custom")]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_security_from_str() {
+        assert_eq!("TLS".parse::<Security>(), Ok(Security::Tls));
+        assert_eq!("starttls".parse::<Security>(), Ok(Security::StartTls));
+        assert!("bogus".parse::<Security>().is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use super::{Address, Attachment, Email};
+
+/// A reply to `original` from `me`, quoting its body and threaded onto it. It goes to
+/// the Reply-To addresses if the sender gave any, otherwise to the sender; `all` adds
+/// everyone else the original went to, except `me`.
+pub fn reply(original: &Email, me: &Address, all: bool) -> Email {
+    // Replying to something we sent means writing to its recipients again
+    let from_me = same_address(&original.from, me);
+    let mut to = if from_me {
+        original.to.clone()
+    } else if !original.reply_to.is_empty() {
+        original.reply_to.clone()
+    } else {
+        vec![original.from.clone()]
+    };
+
+    let mut cc = Vec::new();
+    if all {
+        if !from_me {
+            to.extend(original.to.iter().cloned());
+        }
+        cc = original.cc.clone();
+    }
+
+    let mut seen = vec![me.clone()];
+    let mut to = unique(to, &mut seen);
+    let cc = unique(cc, &mut seen);
+    if to.is_empty() {
+        // A note to ourselves; answer it the same way
+        to.push(original.from.clone());
+    }
+
+    Email {
+        from: me.clone(),
+        to,
+        cc,
+        subject: prefixed("Re:", &["re:"], &original.subject),
+        body: format!("\n\n{}\n{}", attribution(original), quote(&original.body)),
+        in_reply_to: is_message_id(&original.message_id).then(|| original.message_id.clone()),
+        references: references(original),
+        ..Default::default()
+    }
+}
+
+/// A forward of `original` from `me`, carrying the message as it was received (`raw`)
+/// as a message/rfc822 attachment so nothing of it is lost
+pub fn forward(original: &Email, raw: Vec<u8>, me: &Address, to: Vec<Address>) -> Email {
+    let header = [
+        ("From", original.from.to_string()),
+        (
+            "Date",
+            original.date.map(|d| d.to_rfc2822()).unwrap_or_default(),
+        ),
+        ("Subject", original.subject.clone()),
+        ("To", address_line(&original.to)),
+    ];
+    let mut body = String::from("\n\n---------- Forwarded message ----------\n");
+    for (name, value) in header.iter().filter(|(_, value)| !value.is_empty()) {
+        body.push_str(&format!("{}: {}\n", name, value));
+    }
+
+    Email {
+        from: me.clone(),
+        to,
+        subject: prefixed("Fwd:", &["fwd:", "fw:"], &original.subject),
+        body,
+        references: references(original),
+        attachments: vec![Attachment {
+            filename: format!("{}.eml", file_stem(&original.subject)),
+            content_type: "message/rfc822".to_string(),
+            data: raw,
+        }],
+        ..Default::default()
+    }
+}
+
+/// Which of `identities` to answer `original` from: the one it was sent to, or else the
+/// first
+pub fn reply_identity<'a>(identities: &'a [Address], original: &Email) -> Option<&'a Address> {
+    let recipients: Vec<&Address> = original.to.iter().chain(&original.cc).collect();
+    identities
+        .iter()
+        .find(|identity| recipients.iter().any(|addr| same_address(addr, identity)))
+        .or(identities.first())
+}
+
+fn same_address(a: &Address, b: &Address) -> bool {
+    a.email().eq_ignore_ascii_case(&b.email())
+}
+
+/// `addrs` without the ones already in `seen`, or repeated; group names are dropped since
+/// the reply isn't addressed to the group
+fn unique(addrs: Vec<Address>, seen: &mut Vec<Address>) -> Vec<Address> {
+    let mut list = Vec::new();
+    for mut addr in addrs {
+        if seen.iter().any(|other| same_address(other, &addr)) {
+            continue;
+        }
+        addr.group = None;
+        seen.push(addr.clone());
+        list.push(addr);
+    }
+    list
+}
+
+/// `Re: subject`, unless the subject already starts with one of `existing`
+fn prefixed(prefix: &str, existing: &[&str], subject: &str) -> String {
+    let subject = subject.trim();
+    let lower = subject.to_ascii_lowercase();
+    if existing.iter().any(|p| lower.starts_with(p)) {
+        subject.to_string()
+    } else {
+        format!("{} {}", prefix, subject)
+    }
+}
+
+/// `On Mon, 15 Jan 2024 at 10:30, Alice <alice@example.com> wrote:`
+fn attribution(original: &Email) -> String {
+    match original.date {
+        Some(date) => format!(
+            "On {}, {} wrote:",
+            date.format("%a, %-d %b %Y at %H:%M"),
+            original.from
+        ),
+        None => format!("{} wrote:", original.from),
+    }
+}
+
+/// Every line prefixed with `> `, or just `>` before a line that's empty or quoted already
+fn quote(body: &str) -> String {
+    body.trim_end()
+        .lines()
+        .map(|line| {
+            if line.is_empty() || line.starts_with('>') {
+                format!(">{}\n", line)
+            } else {
+                format!("> {}\n", line)
+            }
+        })
+        .collect()
+}
+
+fn is_message_id(id: &str) -> bool {
+    id.starts_with('<') && id.ends_with('>') && id.contains('@')
+}
+
+/// The original's References followed by the original itself (RFC 5322 3.6.4), falling
+/// back to its In-Reply-To when it has no References
+fn references(original: &Email) -> Vec<String> {
+    let mut ids = if original.references.is_empty() {
+        original.in_reply_to.iter().cloned().collect()
+    } else {
+        original.references.clone()
+    };
+    if is_message_id(&original.message_id) && !ids.contains(&original.message_id) {
+        ids.push(original.message_id.clone());
+    }
+    ids
+}
+
+fn address_line(addrs: &[Address]) -> String {
+    addrs
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The subject made safe as a file name, e.g. `Lunch on Friday_`
+fn file_stem(subject: &str) -> String {
+    let stem: String = subject
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || " -_.,".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(60)
+        .collect();
+    let stem = stem.trim().trim_start_matches('.');
+
+    if stem.is_empty() {
+        "forwarded".to_string()
+    } else {
+        stem.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_tools::date::parse_date;
+    use crate::email_tools::{build_message, parse_email_bytes};
+
+    fn addr(s: &str) -> Address {
+        s.parse().unwrap()
+    }
+
+    fn me() -> Address {
+        addr("Me <me@example.com>")
+    }
+
+    fn original() -> Email {
+        Email {
+            from: addr("Alice <alice@example.com>"),
+            to: vec![addr("ME@example.com"), addr("bob@example.com")],
+            cc: vec![addr("carol@example.com"), addr("bob@example.com")],
+            subject: "Lunch on Friday?".to_string(),
+            date: parse_date("Mon, 15 Jan 2024 10:30:00 +0000"),
+            message_id: "<lunch-2@example.com>".to_string(),
+            references: vec!["<lunch-1@example.com>".to_string()],
+            body: "Are you free?\n\n> earlier\nNoon works.\n".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn emails(addrs: &[Address]) -> Vec<String> {
+        addrs.iter().map(|addr| addr.email()).collect()
+    }
+
+    #[test]
+    fn test_reply_goes_to_sender_only() {
+        let reply = reply(&original(), &me(), false);
+        assert_eq!(reply.from, me());
+        assert_eq!(emails(&reply.to), vec!["alice@example.com"]);
+        assert!(reply.cc.is_empty());
+        assert_eq!(reply.subject, "Re: Lunch on Friday?");
+    }
+
+    #[test]
+    fn test_reply_all_skips_me_and_duplicates() {
+        let reply = reply(&original(), &me(), true);
+        assert_eq!(
+            emails(&reply.to),
+            vec!["alice@example.com", "bob@example.com"]
+        );
+        assert_eq!(emails(&reply.cc), vec!["carol@example.com"]);
+    }
+
+    #[test]
+    fn test_reply_honours_reply_to_and_own_messages() {
+        let list = Email {
+            reply_to: vec![addr("team@lists.example.com")],
+            ..original()
+        };
+        assert_eq!(
+            emails(&reply(&list, &me(), false).to),
+            vec!["team@lists.example.com"]
+        );
+
+        let sent = Email {
+            from: me(),
+            to: vec![addr("alice@example.com")],
+            ..original()
+        };
+        assert_eq!(
+            emails(&reply(&sent, &me(), false).to),
+            vec!["alice@example.com"]
+        );
+    }
+
+    #[test]
+    fn test_reply_threads_and_quotes() {
+        let reply = reply(&original(), &me(), false);
+        assert_eq!(reply.in_reply_to.as_deref(), Some("<lunch-2@example.com>"));
+        assert_eq!(
+            reply.references,
+            vec!["<lunch-1@example.com>", "<lunch-2@example.com>"]
+        );
+        assert_eq!(
+            reply.body,
+            "\n\nOn Mon, 15 Jan 2024 at 10:30, Alice <alice@example.com> wrote:\n\
+             > Are you free?\n>\n>> earlier\n> Noon works.\n"
+        );
+
+        // Replying to a reply doesn't stack prefixes
+        let again = Email {
+            subject: reply.subject.clone(),
+            ..original()
+        };
+        assert_eq!(
+            super::reply(&again, &me(), false).subject,
+            "Re: Lunch on Friday?"
+        );
+    }
+
+    #[test]
+    fn test_reply_identity_follows_recipient() {
+        let identities = [addr("Me <me@work.example>"), addr("Me <me@example.com>")];
+        let from = reply_identity(&identities, &original()).unwrap();
+        assert_eq!(from.email(), "me@example.com");
+
+        let elsewhere = Email {
+            to: vec![addr("list@example.org")],
+            cc: vec![],
+            ..original()
+        };
+        let from = reply_identity(&identities, &elsewhere).unwrap();
+        assert_eq!(from.email(), "me@work.example");
+        assert!(reply_identity(&[], &elsewhere).is_none());
+    }
+
+    #[test]
+    fn test_threading_headers_survive_sending() {
+        let reply = reply(&original(), &me(), false);
+        let sent = build_message(&reply).unwrap().formatted();
+        let parsed = parse_email_bytes(&sent).unwrap();
+        assert_eq!(parsed.in_reply_to, reply.in_reply_to);
+        assert_eq!(parsed.references, reply.references);
+    }
+
+    #[test]
+    fn test_forward_attaches_original() {
+        let raw = b"From: alice@example.com\r\nSubject: Lunch\r\n\r\nAre you free?\r\n".to_vec();
+        let forward = forward(
+            &original(),
+            raw.clone(),
+            &me(),
+            vec![addr("dave@example.com")],
+        );
+
+        assert_eq!(forward.subject, "Fwd: Lunch on Friday?");
+        assert_eq!(emails(&forward.to), vec!["dave@example.com"]);
+        assert_eq!(forward.in_reply_to, None);
+        assert!(forward.body.contains("From: Alice <alice@example.com>\n"));
+        assert_eq!(forward.attachments.len(), 1);
+        assert_eq!(forward.attachments[0].filename, "Lunch on Friday_.eml");
+        assert_eq!(forward.attachments[0].content_type, "message/rfc822");
+        assert_eq!(forward.attachments[0].data, raw);
+
+        let sent = build_message(&forward).unwrap().formatted();
+        let parsed = parse_email_bytes(&sent).unwrap();
+        assert_eq!(parsed.mime.unwrap().attachments().len(), 1);
+    }
+}
@@ -0,0 +1,294 @@
+use super::{Address, Email, Flag, build_email_bytes};
+use chrono::NaiveDate;
+use std::str::FromStr;
+
+/// One condition of a search. All terms of a query must match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchTerm {
+    From(String),
+    To(String),
+    Subject(String),
+    Body(String),
+    /// Anywhere in the headers or body (a bare word in the query)
+    Text(String),
+    /// Sent on or after this date
+    Since(NaiveDate),
+    /// Sent before this date
+    Before(NaiveDate),
+    Unseen,
+    Flagged,
+    /// Larger than this many bytes
+    Larger(u32),
+}
+
+/// A parsed search such as `from:alice subject:"quarterly report" unseen since:2024-01-01`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SearchQuery {
+    pub terms: Vec<SearchTerm>,
+}
+
+impl FromStr for SearchQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let terms = tokenize(s)?
+            .into_iter()
+            .map(parse_term)
+            .collect::<Result<_, _>>()?;
+        Ok(SearchQuery { terms })
+    }
+}
+
+impl SearchQuery {
+    /// Builds a query from command-line arguments, one term per argument, so that
+    /// `subject:"quarterly report"` survives the shell removing the quotes
+    pub fn from_args(args: &[String]) -> Result<SearchQuery, String> {
+        let terms = args
+            .iter()
+            .filter(|arg| !arg.trim().is_empty())
+            .map(|arg| parse_term(arg.clone()))
+            .collect::<Result<_, _>>()?;
+        Ok(SearchQuery { terms })
+    }
+
+    /// The search criteria for an IMAP SEARCH / UID SEARCH command. Non-ASCII text is
+    /// sent as literals, which the server answers with a `+` continuation
+    pub fn to_imap(&self) -> String {
+        if self.terms.is_empty() {
+            return "ALL".to_string();
+        }
+
+        let criteria: Vec<String> = self
+            .terms
+            .iter()
+            .map(|term| match term {
+                SearchTerm::From(s) => format!("FROM {}", argument(s)),
+                SearchTerm::To(s) => format!("TO {}", argument(s)),
+                SearchTerm::Subject(s) => format!("SUBJECT {}", argument(s)),
+                SearchTerm::Body(s) => format!("BODY {}", argument(s)),
+                SearchTerm::Text(s) => format!("TEXT {}", argument(s)),
+                SearchTerm::Since(d) => format!("SENTSINCE {}", d.format("%-d-%b-%Y")),
+                SearchTerm::Before(d) => format!("SENTBEFORE {}", d.format("%-d-%b-%Y")),
+                SearchTerm::Unseen => "UNSEEN".to_string(),
+                SearchTerm::Flagged => "FLAGGED".to_string(),
+                SearchTerm::Larger(n) => format!("LARGER {}", n),
+            })
+            .collect();
+
+        let criteria = criteria.join(" ");
+        if criteria.is_ascii() {
+            criteria
+        } else {
+            format!("CHARSET UTF-8 {}", criteria)
+        }
+    }
+
+    /// Evaluates the query locally, for backends without a server to search on
+    pub fn matches(&self, email: &Email) -> bool {
+        let contains =
+            |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+        // Matches either the display name or the address itself
+        let contains_address =
+            |address: &Address, needle: &str| contains(&address.to_string(), needle);
+        let date = email.date.map(|d| d.date_naive());
+
+        self.terms.iter().all(|term| match term {
+            SearchTerm::From(s) => contains_address(&email.from, s),
+            SearchTerm::To(s) => email.to.iter().any(|to| contains_address(to, s)),
+            SearchTerm::Subject(s) => contains(&email.subject, s),
+            SearchTerm::Body(s) => contains(&email.body, s),
+            SearchTerm::Text(s) => {
+                contains_address(&email.from, s)
+                    || email.to.iter().any(|to| contains_address(to, s))
+                    || email.cc.iter().any(|cc| contains_address(cc, s))
+                    || contains(&email.subject, s)
+                    || contains(&email.body, s)
+            }
+            SearchTerm::Since(d) => date.is_some_and(|date| date >= *d),
+            SearchTerm::Before(d) => date.is_some_and(|date| date < *d),
+            SearchTerm::Unseen => !email.flags.contains(&Flag::Seen),
+            SearchTerm::Flagged => email.flags.contains(&Flag::Flagged),
+            SearchTerm::Larger(n) => {
+                build_email_bytes(email).is_ok_and(|raw| raw.len() > *n as usize)
+            }
+        })
+    }
+}
+
+fn parse_term(token: String) -> Result<SearchTerm, String> {
+    let term = match token.split_once(':') {
+        Some((key, value)) => {
+            let value = value.to_string();
+            match key.to_ascii_lowercase().as_str() {
+                "from" => SearchTerm::From(value),
+                "to" => SearchTerm::To(value),
+                "subject" => SearchTerm::Subject(value),
+                "body" => SearchTerm::Body(value),
+                "since" => SearchTerm::Since(parse_date(&value)?),
+                "before" => SearchTerm::Before(parse_date(&value)?),
+                "larger" => SearchTerm::Larger(parse_size(&value)?),
+                _ => SearchTerm::Text(token),
+            }
+        }
+        None => match token.to_ascii_lowercase().as_str() {
+            "unseen" | "unread" => SearchTerm::Unseen,
+            "flagged" | "starred" => SearchTerm::Flagged,
+            _ => SearchTerm::Text(token),
+        },
+    };
+    Ok(term)
+}
+
+/// Splits a query on whitespace, keeping `"quoted phrases"` (also after `key:`) together.
+/// Inside quotes, a backslash escapes the next character.
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_quotes => current.extend(chars.next()),
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err("unterminated quote in search query".to_string());
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+/// Quotes a string argument for an IMAP command. Quoted strings are 7-bit only, so
+/// anything else is sent as a literal
+fn argument(s: &str) -> String {
+    if s.is_ascii() {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        format!("{{{}}}\r\n{}", s.len(), s)
+    }
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("invalid date '{}' (expected YYYY-MM-DD)", s))
+}
+
+/// Parses sizes like `500`, `10k` or `2M` into bytes
+fn parse_size(s: &str) -> Result<u32, String> {
+    let lower = s.to_ascii_lowercase();
+    let (digits, multiplier) = if let Some(n) = lower.strip_suffix('k') {
+        (n, 1024)
+    } else if let Some(n) = lower.strip_suffix('m') {
+        (n, 1024 * 1024)
+    } else {
+        (lower.as_str(), 1)
+    };
+
+    digits
+        .parse::<u32>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_tools::date::parse_date;
+
+    #[test]
+    fn test_parse_query() {
+        let query: SearchQuery = "from:alice subject:\"quarterly report\" unseen larger:10k hello"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            query.terms,
+            vec![
+                SearchTerm::From("alice".to_string()),
+                SearchTerm::Subject("quarterly report".to_string()),
+                SearchTerm::Unseen,
+                SearchTerm::Larger(10 * 1024),
+                SearchTerm::Text("hello".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_to_imap() {
+        let query: SearchQuery =
+            "to:bob since:2024-01-05 before:2024-02-01 flagged body:\"say \\\"hi\\\"\""
+                .parse()
+                .unwrap();
+        assert_eq!(
+            query.to_imap(),
+            "TO \"bob\" SENTSINCE 5-Jan-2024 SENTBEFORE 1-Feb-2024 FLAGGED BODY \"say \\\"hi\\\"\""
+        );
+
+        assert_eq!(SearchQuery::default().to_imap(), "ALL");
+        assert_eq!(
+            "subject:café".parse::<SearchQuery>().unwrap().to_imap(),
+            "CHARSET UTF-8 SUBJECT {5}\r\ncafé"
+        );
+    }
+
+    #[test]
+    fn test_query_from_args() {
+        let args = vec!["subject:quarterly report".to_string(), "unseen".to_string()];
+        assert_eq!(
+            SearchQuery::from_args(&args).unwrap().terms,
+            vec![
+                SearchTerm::Subject("quarterly report".to_string()),
+                SearchTerm::Unseen
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_queries() {
+        assert!("since:yesterday".parse::<SearchQuery>().is_err());
+        assert!("larger:big".parse::<SearchQuery>().is_err());
+        assert!("subject:\"open".parse::<SearchQuery>().is_err());
+    }
+
+    #[test]
+    fn test_matches_locally() {
+        let email = Email {
+            from: "Alice Martin <alice@example.com>".parse().unwrap(),
+            to: vec!["bob@example.com".parse().unwrap()],
+            subject: "Quarterly Report".to_string(),
+            date: parse_date("Mon, 15 Jan 2024 10:30:00 +0000"),
+            body: "Numbers are up".to_string(),
+            ..Default::default()
+        };
+
+        let matches = |q: &str, flags: &[Flag]| {
+            let email = Email {
+                flags: flags.to_vec(),
+                ..email.clone()
+            };
+            q.parse::<SearchQuery>().unwrap().matches(&email)
+        };
+
+        assert!(matches("from:alice subject:quarterly", &[]));
+        assert!(matches("from:martin", &[]));
+        assert!(matches("numbers since:2024-01-15", &[]));
+        assert!(!matches("before:2024-01-15", &[]));
+        assert!(matches("unseen", &[]));
+        assert!(!matches("unseen", &[Flag::Seen]));
+        assert!(matches("flagged", &[Flag::Flagged]));
+        assert!(!matches("to:carol", &[]));
+    }
+}
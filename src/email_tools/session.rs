@@ -0,0 +1,475 @@
+use super::oauth::Mechanism;
+use super::provider::Server;
+use super::{Auth, EmailProvider, Secret, Security, UserCredentials};
+use imap::types::Mailbox;
+use imap_proto::types::Capability;
+use native_tls::TlsConnector;
+use std::cell::{Cell, RefCell};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+/// Any stream an IMAP session can run over (plain TCP or TLS)
+pub trait ImapStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> ImapStream for T {}
+
+pub type ImapSession = imap::Session<Box<dyn ImapStream>>;
+
+/// A long-lived, authenticated IMAP connection.
+///
+/// The connection is opened lazily on first use and reused by every operation. If the
+/// server drops it (idle timeout, network change, BYE) the next operation reconnects,
+/// re-selects the previously selected mailbox and retries once.
+pub struct MailSession {
+    provider: EmailProvider,
+    credentials: UserCredentials,
+    session: Option<ImapSession>,
+    selected: Option<String>,
+    capabilities: Option<Vec<String>>,
+}
+
+impl MailSession {
+    pub fn new(provider: EmailProvider, credentials: UserCredentials) -> MailSession {
+        MailSession {
+            provider,
+            credentials,
+            session: None,
+            selected: None,
+            capabilities: None,
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.credentials.username
+    }
+
+    pub fn provider(&self) -> &EmailProvider {
+        &self.provider
+    }
+
+    pub fn credentials(&self) -> &UserCredentials {
+        &self.credentials
+    }
+
+    /// Selects `mailbox` and returns its status. Every call issues a fresh SELECT so the
+    /// message counts are current.
+    pub fn select(&mut self, mailbox: &str) -> Result<Mailbox, Box<dyn std::error::Error>> {
+        let name = mailbox.to_string();
+        let status = self.run(|session| session.select(&name))?;
+        self.selected = Some(name);
+        Ok(status)
+    }
+
+    /// Whether the server advertises `capability` (e.g. `MOVE` or `UIDPLUS`). The list is
+    /// fetched once per connection.
+    pub fn has_capability(&mut self, capability: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        if self.capabilities.is_none() {
+            let capabilities = self.run(|s| {
+                let caps = s.capabilities()?;
+                Ok(caps
+                    .iter()
+                    .map(|c| match c {
+                        Capability::Imap4rev1 => "IMAP4rev1".to_string(),
+                        Capability::Auth(mechanism) => format!("AUTH={}", mechanism),
+                        Capability::Atom(atom) => atom.to_string(),
+                    })
+                    .collect::<Vec<_>>())
+            })?;
+            self.capabilities = Some(capabilities);
+        }
+
+        Ok(self
+            .capabilities
+            .iter()
+            .flatten()
+            .any(|c| c.eq_ignore_ascii_case(capability)))
+    }
+
+    /// Runs `op` against the authenticated session, reconnecting and retrying once if the
+    /// connection turns out to be dead. Only for commands that are safe to repeat, such as
+    /// FETCH, SEARCH or setting a flag: the first attempt may have reached the server.
+    pub fn run<T, F>(&mut self, mut op: F) -> Result<T, Box<dyn std::error::Error>>
+    where
+        F: FnMut(&mut ImapSession) -> imap::error::Result<T>,
+    {
+        let fresh = self.session.is_none();
+        let result = op(self.connected()?);
+
+        match result {
+            Err(e) if !fresh && is_disconnect(&e) => {
+                self.disconnected();
+                let session = self.connected()?;
+                Ok(op(session)?)
+            }
+            other => Ok(other?),
+        }
+    }
+
+    /// Runs `op` exactly once, for commands that must not happen twice, such as APPEND,
+    /// COPY, MOVE or EXPUNGE. A dead connection is found with a NOOP beforehand and
+    /// replaced; if it drops during `op` itself the error is returned, as the server may
+    /// already have carried the command out.
+    pub fn run_once<T, F>(&mut self, op: F) -> Result<T, Box<dyn std::error::Error>>
+    where
+        F: FnOnce(&mut ImapSession) -> imap::error::Result<T>,
+    {
+        if self.session.is_some() {
+            self.run(|s| s.noop())?;
+        }
+
+        let result = op(self.connected()?);
+        if let Err(e) = &result
+            && is_disconnect(e)
+        {
+            self.disconnected();
+        }
+        Ok(result?)
+    }
+
+    /// Logs out and closes the connection, if one is open
+    pub fn logout(&mut self) {
+        if let Some(mut session) = self.session.take() {
+            session.logout().ok();
+        }
+        self.selected = None;
+        self.capabilities = None;
+    }
+
+    fn connected(&mut self) -> Result<&mut ImapSession, Box<dyn std::error::Error>> {
+        if self.session.is_none() {
+            let mut session = connect_imap(&self.provider, &self.credentials)?;
+            if let Some(mailbox) = &self.selected {
+                session.select(mailbox)?;
+            }
+            self.session = Some(session);
+        }
+
+        Ok(self.session.as_mut().expect("session was just connected"))
+    }
+
+    /// Forgets a dead connection so the next operation opens a new one
+    fn disconnected(&mut self) {
+        self.session = None;
+        self.capabilities = None;
+    }
+}
+
+impl Drop for MailSession {
+    fn drop(&mut self) {
+        self.logout();
+    }
+}
+
+/// Whether an error means the connection itself is gone, as opposed to the server
+/// rejecting a command
+fn is_disconnect(error: &imap::error::Error) -> bool {
+    use std::io::ErrorKind;
+    match error {
+        imap::error::Error::ConnectionLost => true,
+        imap::error::Error::Io(e) => matches!(
+            e.kind(),
+            ErrorKind::BrokenPipe
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::UnexpectedEof
+        ),
+        // The server said goodbye before answering; imap can't parse the untagged BYE
+        imap::error::Error::Parse(imap::error::ParseError::Invalid(data)) => data
+            .split(|&b| b == b'\n')
+            .any(|line| line.len() >= 5 && line[..5].eq_ignore_ascii_case(b"* BYE")),
+        _ => false,
+    }
+}
+
+/// Opens a connection to the provider's IMAP server and logs in
+fn connect_imap(
+    provider: &EmailProvider,
+    credentials: &UserCredentials,
+) -> Result<ImapSession, Box<dyn std::error::Error>> {
+    let server = provider.imap_server();
+    let tcp_stream = TcpStream::connect((server.host.as_str(), server.port))?;
+
+    let stream: Box<dyn ImapStream> = match server.security {
+        Security::Tls => {
+            let tls = TlsConnector::builder().build()?;
+            Box::new(tls.connect(&server.host, tcp_stream)?)
+        }
+        Security::StartTls => {
+            starttls_handshake(&tcp_stream)?;
+            let tls = TlsConnector::builder().build()?;
+            Box::new(tls.connect(&server.host, tcp_stream)?)
+        }
+        Security::Plain => Box::new(tcp_stream),
+    };
+
+    let mut client = imap::Client::new(stream);
+    // With STARTTLS the greeting was already consumed before the upgrade
+    if server.security != Security::StartTls {
+        client.read_greeting()?;
+    }
+
+    let imap_session = match &credentials.auth {
+        Auth::Password(password) => client
+            .login(&credentials.username, password.expose())
+            .map_err(|e| e.0)?,
+        Auth::OAuth2(tokens) => authenticate_bearer(
+            client,
+            &credentials.username,
+            &tokens.access_token()?,
+            &server,
+        )?,
+    };
+
+    Ok(imap_session)
+}
+
+/// Logs in with an OAuth2 access token: XOAUTH2, which Gmail and Outlook speak, or the
+/// standard OAUTHBEARER if the server doesn't know that
+fn authenticate_bearer(
+    mut client: imap::Client<Box<dyn ImapStream>>,
+    username: &str,
+    token: &Secret,
+    server: &Server,
+) -> Result<ImapSession, Box<dyn std::error::Error>> {
+    for mechanism in [Mechanism::XOAuth2, Mechanism::OAuthBearer] {
+        let authenticator = BearerAuthenticator {
+            mechanism,
+            initial: mechanism.initial_response(username, token, &server.host, server.port),
+            offered: Cell::new(false),
+            rejection: RefCell::new(None),
+        };
+        match client.authenticate(mechanism.name(), &authenticator) {
+            Ok(session) => return Ok(session),
+            Err((error, returned)) => {
+                if let Some(reason) = authenticator.rejection.take() {
+                    return Err(format!("the server refused the access token: {}", reason).into());
+                }
+                if authenticator.offered.get() {
+                    return Err(error.into());
+                }
+                // Turned down before the token was even asked for: an unknown mechanism
+                client = returned;
+            }
+        }
+    }
+    Err("the server supports neither XOAUTH2 nor OAUTHBEARER".into())
+}
+
+/// Answers an AUTHENTICATE exchange with a bearer token. Any further challenge means the
+/// token was refused, and carries the server's reason.
+struct BearerAuthenticator {
+    mechanism: Mechanism,
+    initial: Secret,
+    offered: Cell<bool>,
+    rejection: RefCell<Option<String>>,
+}
+
+impl imap::Authenticator for BearerAuthenticator {
+    type Response = Vec<u8>;
+
+    fn process(&self, challenge: &[u8]) -> Vec<u8> {
+        if !self.offered.replace(true) {
+            return self.initial.expose().as_bytes().to_vec();
+        }
+        *self.rejection.borrow_mut() = Some(String::from_utf8_lossy(challenge).into_owned());
+        self.mechanism.failure_response().to_vec()
+    }
+}
+
+/// Reads the server greeting and issues STARTTLS on a plaintext connection, leaving the
+/// socket ready for the TLS handshake
+fn starttls_handshake(tcp_stream: &TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(tcp_stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let mut writer = tcp_stream;
+    writer.write_all(b"a0 STARTTLS\r\n")?;
+    writer.flush()?;
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err("Connection closed during STARTTLS".into());
+        }
+        if let Some(status) = line.strip_prefix("a0 ") {
+            if status.starts_with("OK") {
+                return Ok(());
+            }
+            return Err(format!("Server refused STARTTLS: {}", status.trim()).into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_tools::oauth::{OAuthConfig, TokenSource};
+    use crate::email_tools::provider::ServerConfig;
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    /// A one-shot IMAP server that only knows OAUTHBEARER and takes the access token
+    /// `token`; returns the commands it was sent
+    fn mock_server() -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut commands = Vec::new();
+
+            writer.write_all(b"* OK mock IMAP4rev1 ready\r\n").unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let command = line.trim_end().to_string();
+                line.clear();
+                commands.push(command.clone());
+                let tag = command.split(' ').next().unwrap();
+
+                if command.ends_with("AUTHENTICATE OAUTHBEARER") {
+                    writer.write_all(b"+ \r\n").unwrap();
+                    reader.read_line(&mut line).unwrap();
+                    let response = STANDARD.decode(line.trim_end()).unwrap();
+                    line.clear();
+                    let expected = format!(
+                        "n,a=user,\x01host=127.0.0.1\x01port={}\x01auth=Bearer token\x01\x01",
+                        port
+                    );
+                    if response != expected.as_bytes() {
+                        // {"status":"invalid_token"}
+                        writer
+                            .write_all(b"+ eyJzdGF0dXMiOiJpbnZhbGlkX3Rva2VuIn0=\r\n")
+                            .unwrap();
+                        reader.read_line(&mut line).unwrap();
+                        assert_eq!(STANDARD.decode(line.trim_end()).unwrap(), b"\x01");
+                        line.clear();
+                        writeln!(writer, "{} NO [AUTHENTICATIONFAILED] Invalid\r", tag).unwrap();
+                        continue;
+                    }
+                    writeln!(writer, "{} OK Logged in\r", tag).unwrap();
+                } else if command.contains("AUTHENTICATE") {
+                    writeln!(writer, "{} NO Unsupported mechanism\r", tag).unwrap();
+                } else {
+                    writeln!(writer, "{} BAD Unexpected\r", tag).unwrap();
+                }
+            }
+            commands
+        });
+
+        (port, handle)
+    }
+
+    fn oauth_login(
+        port: u16,
+        access_token: &str,
+    ) -> Result<ImapSession, Box<dyn std::error::Error>> {
+        let provider = EmailProvider::Custom(ServerConfig {
+            imap: Server::new("127.0.0.1", port, Security::Plain),
+            smtp: Server::new("127.0.0.1", 25, Security::Plain),
+        });
+        let config = OAuthConfig::for_provider(&EmailProvider::Google, "hermes".into(), None);
+        let tokens = TokenSource::new(config.unwrap(), "refresh".into())
+            .with_access_token(access_token.into(), Duration::from_secs(3600));
+        connect_imap(
+            &provider,
+            &UserCredentials::oauth2("user".to_string(), tokens),
+        )
+    }
+
+    /// A server that takes any LOGIN and hangs up instead of answering a COPY; returns
+    /// the commands it was sent before that
+    fn hanging_up_server() -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut commands = Vec::new();
+
+            writer.write_all(b"* OK mock IMAP4rev1 ready\r\n").unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let command = line.trim_end().to_string();
+                line.clear();
+                commands.push(command.clone());
+                if command.contains("COPY") {
+                    break;
+                }
+                let tag = command.split(' ').next().unwrap();
+                writeln!(writer, "{} OK done\r", tag).unwrap();
+            }
+            commands
+        });
+
+        (port, handle)
+    }
+
+    #[test]
+    fn test_run_once_does_not_replay_after_a_dropped_connection() {
+        let (port, server) = hanging_up_server();
+        let provider = EmailProvider::Custom(ServerConfig {
+            imap: Server::new("127.0.0.1", port, Security::Plain),
+            smtp: Server::new("127.0.0.1", 25, Security::Plain),
+        });
+        let mut session = MailSession::new(
+            provider,
+            UserCredentials::new("user".to_string(), "secret".into()),
+        );
+        session.run(|s| s.noop()).unwrap();
+
+        // The server may have copied the message before hanging up, so no second try
+        assert!(session.run_once(|s| s.uid_copy("7", "Archive")).is_err());
+        let commands = server.join().unwrap();
+        assert_eq!(
+            commands.iter().filter(|c| c.contains("COPY")).count(),
+            1,
+            "{:?}",
+            commands
+        );
+        assert!(session.session.is_none());
+    }
+
+    #[test]
+    fn test_what_counts_as_a_lost_connection() {
+        use imap::error::{Error, ParseError};
+        assert!(is_disconnect(&Error::ConnectionLost));
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert!(is_disconnect(&Error::Io(reset)));
+        let bye = b"* BYE Autologout; idle for too long\r\n".to_vec();
+        assert!(is_disconnect(&Error::Parse(ParseError::Invalid(bye))));
+        // A refusal that merely mentions the word is not a disconnect
+        assert!(!is_disconnect(&Error::No(
+            "Mailbox goodbye does not exist".into()
+        )));
+    }
+
+    #[test]
+    fn test_oauth2_falls_back_to_oauthbearer() {
+        let (port, server) = mock_server();
+        let session = oauth_login(port, "token").unwrap();
+        drop(session);
+
+        let commands = server.join().unwrap();
+        assert!(commands[0].ends_with("AUTHENTICATE XOAUTH2"));
+        assert!(commands[1].ends_with("AUTHENTICATE OAUTHBEARER"));
+    }
+
+    #[test]
+    fn test_refused_access_token() {
+        let (port, server) = mock_server();
+        let error = oauth_login(port, "expired").err().unwrap().to_string();
+        assert_eq!(
+            error,
+            r#"the server refused the access token: {"status":"invalid_token"}"#
+        );
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,543 @@
+use super::oauth::Mechanism;
+use super::{Auth, EmailProvider, Secret, Security, UserCredentials};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use lettre::Message;
+use lettre::transport::smtp::authentication::{Credentials, DEFAULT_MECHANISMS};
+use lettre::transport::smtp::client::{SmtpConnection, TlsParameters};
+use lettre::transport::smtp::commands::{Data, Mail, Rcpt};
+use lettre::transport::smtp::extension::{ClientId, Extension, MailBodyParameter, MailParameter};
+use lettre::transport::smtp::response::Response;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A reply from the SMTP server, e.g. `550 5.1.1 No such user`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtpReply {
+    pub code: u16,
+    pub message: String,
+}
+
+impl SmtpReply {
+    fn from_response(response: &Response) -> SmtpReply {
+        SmtpReply {
+            code: response.code().into(),
+            message: response.message().collect::<Vec<_>>().join(" "),
+        }
+    }
+
+    /// The server's reply carried by a 4xx or 5xx error, if that is what it is
+    fn from_error(error: &lettre::transport::smtp::Error) -> Option<SmtpReply> {
+        Some(SmtpReply {
+            code: error.status()?.into(),
+            message: error.source().map(|e| e.to_string()).unwrap_or_default(),
+        })
+    }
+}
+
+impl fmt::Display for SmtpReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.message)
+    }
+}
+
+/// What happened to a message the server accepted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendReport {
+    /// The reply to the message itself, which often names the server's queue id
+    pub reply: SmtpReply,
+    pub message_id: String,
+    pub accepted: Vec<String>,
+    /// Recipients the server refused, with its reason; the message went to the others
+    pub rejected: Vec<(String, SmtpReply)>,
+    pub copy: SentCopy,
+}
+
+/// Whether a copy of a sent message was filed in the Sent folder
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SentCopy {
+    /// Not wanted, e.g. because the provider files sent mail itself
+    #[default]
+    NotSaved,
+    /// Saved to the named folder
+    Saved(String),
+    /// The message went out, but the copy couldn't be saved
+    Failed(String),
+}
+
+/// Why a message wasn't sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendError {
+    /// The email couldn't be turned into a message, e.g. it has no recipients
+    Message(String),
+    /// The server couldn't be reached, or the connection or TLS failed
+    Connection(String),
+    /// The server refused the username or password, or wants a login first
+    Authentication(String),
+    /// The server refused the message. Temporary (4xx) refusals may go through if tried
+    /// again later.
+    Refused { reply: SmtpReply, temporary: bool },
+    /// Every recipient was refused, so nothing was sent
+    NoRecipients(Vec<(String, SmtpReply)>),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Message(e) => write!(f, "invalid message: {}", e),
+            SendError::Connection(e) => write!(f, "could not talk to the SMTP server: {}", e),
+            SendError::Authentication(e) => write!(f, "SMTP login failed: {}", e),
+            SendError::Refused {
+                reply,
+                temporary: true,
+            } => write!(
+                f,
+                "the server deferred the message, try again later: {}",
+                reply
+            ),
+            SendError::Refused { reply, .. } => {
+                write!(f, "the server refused the message: {}", reply)
+            }
+            SendError::NoRecipients(rejected) => {
+                write!(f, "every recipient was refused:")?;
+                for (address, reply) in rejected {
+                    write!(f, " {} ({});", address, reply)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for SendError {}
+
+impl From<lettre::transport::smtp::Error> for SendError {
+    fn from(e: lettre::transport::smtp::Error) -> SendError {
+        match SmtpReply::from_error(&e) {
+            // Authentication required, too weak, or credentials invalid (RFC 4954)
+            Some(reply) if matches!(reply.code, 530 | 534 | 535) => {
+                SendError::Authentication(reply.to_string())
+            }
+            Some(reply) => SendError::Refused {
+                reply,
+                temporary: e.is_transient(),
+            },
+            None => SendError::Connection(e.to_string()),
+        }
+    }
+}
+
+/// Submits a message to the provider's SMTP server.
+///
+/// Recipients are offered one at a time so that a refused address doesn't stop the
+/// message going to the rest; they are listed in the report instead.
+pub fn send_message(
+    provider: &EmailProvider,
+    credentials: &UserCredentials,
+    message: &Message,
+) -> Result<SendReport, SendError> {
+    let mut connection = connect(provider, credentials)?;
+
+    match deliver(&mut connection, message) {
+        Ok(report) => {
+            // The message is queued by now; a failed goodbye doesn't change that
+            let _ = connection.quit();
+            Ok(report)
+        }
+        Err(e) => {
+            connection.abort();
+            Err(e)
+        }
+    }
+}
+
+/// Opens a connection to the submission server, secured and logged in
+fn connect(
+    provider: &EmailProvider,
+    credentials: &UserCredentials,
+) -> Result<SmtpConnection, SendError> {
+    let server = provider.smtp_server();
+    let hello = ClientId::default();
+
+    let tls = match server.security {
+        Security::Plain => None,
+        Security::Tls | Security::StartTls => Some(TlsParameters::new(server.host.clone())?),
+    };
+    let wrapper = tls.as_ref().filter(|_| server.security == Security::Tls);
+
+    let mut connection = SmtpConnection::connect(
+        (server.host.as_str(), server.port),
+        Some(TIMEOUT),
+        &hello,
+        wrapper,
+        None,
+    )?;
+
+    if server.security == Security::StartTls
+        && let Some(tls) = &tls
+    {
+        connection.starttls(tls, &hello)?;
+    }
+
+    match &credentials.auth {
+        Auth::Password(password) => {
+            let credentials = Credentials::new(
+                credentials.username.to_owned(),
+                password.expose().to_owned(),
+            );
+            connection
+                .auth(DEFAULT_MECHANISMS, &credentials)
+                .map_err(auth_error)?;
+        }
+        Auth::OAuth2(tokens) => {
+            let token = tokens.access_token().map_err(SendError::Authentication)?;
+            let mechanism = if connection.server_info().supports_auth_mechanism(
+                lettre::transport::smtp::authentication::Mechanism::Xoauth2,
+            ) {
+                Mechanism::XOAuth2
+            } else {
+                Mechanism::OAuthBearer
+            };
+            let initial = mechanism.initial_response(
+                &credentials.username,
+                &token,
+                &server.host,
+                server.port,
+            );
+            authenticate_bearer(&mut connection, mechanism, &initial)?;
+        }
+    }
+
+    Ok(connection)
+}
+
+fn auth_error(e: lettre::transport::smtp::Error) -> SendError {
+    match SmtpReply::from_error(&e) {
+        Some(reply) => SendError::Authentication(reply.to_string()),
+        // lettre found no mechanism both sides support
+        None if e.is_client() => SendError::Authentication(e.to_string()),
+        None => SendError::Connection(e.to_string()),
+    }
+}
+
+/// AUTH with an OAuth2 bearer token. lettre would leave the exchange hanging when the
+/// server refuses the token, so it is done here: the server's 334 challenge says why,
+/// and is answered so the server can close the exchange with its 535.
+fn authenticate_bearer(
+    connection: &mut SmtpConnection,
+    mechanism: Mechanism,
+    initial: &Secret,
+) -> Result<(), SendError> {
+    let response = connection
+        .command(format!(
+            "AUTH {} {}\r\n",
+            mechanism.name(),
+            STANDARD.encode(initial.expose())
+        ))
+        .map_err(auth_error)?;
+    if !response.has_code(334) {
+        return Ok(());
+    }
+
+    let challenge = response.message().collect::<String>();
+    let reason = STANDARD
+        .decode(challenge.trim())
+        .map(|reason| String::from_utf8_lossy(&reason).into_owned())
+        .unwrap_or(challenge);
+    let outcome = connection.command(format!(
+        "{}\r\n",
+        STANDARD.encode(mechanism.failure_response())
+    ));
+    Err(match outcome.map_err(auth_error) {
+        Err(SendError::Authentication(reply)) => {
+            SendError::Authentication(format!("{}: {}", reply, reason))
+        }
+        Err(e) => e,
+        Ok(_) => SendError::Authentication(format!("the access token was refused: {}", reason)),
+    })
+}
+
+/// Runs one MAIL / RCPT... / DATA transaction
+fn deliver(connection: &mut SmtpConnection, message: &Message) -> Result<SendReport, SendError> {
+    let envelope = message.envelope();
+    let body = message.formatted();
+
+    // Same internationalization rules as lettre's own transport
+    let mut parameters = vec![];
+    let mut addresses = envelope.from().into_iter().chain(envelope.to());
+    if addresses.any(|address| !AsRef::<str>::as_ref(address).is_ascii()) {
+        if !connection
+            .server_info()
+            .supports_feature(Extension::SmtpUtfEight)
+        {
+            return Err(SendError::Message(
+                "the server doesn't accept non-ASCII addresses (no SMTPUTF8)".to_string(),
+            ));
+        }
+        parameters.push(MailParameter::SmtpUtfEight);
+    }
+    if !body.is_ascii() {
+        if !connection
+            .server_info()
+            .supports_feature(Extension::EightBitMime)
+        {
+            return Err(SendError::Message(
+                "the server doesn't accept 8-bit content (no 8BITMIME)".to_string(),
+            ));
+        }
+        parameters.push(MailParameter::Body(MailBodyParameter::EightBitMime));
+    }
+
+    connection.command(Mail::new(envelope.from().cloned(), parameters))?;
+
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+    for address in envelope.to() {
+        match connection.command(Rcpt::new(address.clone(), vec![])) {
+            Ok(_) => accepted.push(address.to_string()),
+            Err(e) => match SmtpReply::from_error(&e) {
+                Some(reply) => rejected.push((address.to_string(), reply)),
+                None => return Err(e.into()),
+            },
+        }
+    }
+    if accepted.is_empty() {
+        return Err(SendError::NoRecipients(rejected));
+    }
+
+    connection.command(Data)?;
+    let response = connection.message(&body)?;
+
+    Ok(SendReport {
+        reply: SmtpReply::from_response(&response),
+        message_id: message
+            .headers()
+            .get_raw("Message-ID")
+            .unwrap_or_default()
+            .to_string(),
+        accepted,
+        rejected,
+        copy: SentCopy::NotSaved,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_tools::oauth::{OAuthConfig, TokenSource};
+    use crate::email_tools::provider::{Server, ServerConfig};
+    use crate::email_tools::{Email, build_message};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A one-shot SMTP server: takes the password `secret` or the access token `token`,
+    /// refuses any recipient at `nowhere.example`, and queues everything else
+    fn mock_server() -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut commands = Vec::new();
+            let mut refused_token = false;
+
+            writer.write_all(b"220 mock ESMTP\r\n").unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let command = line.trim_end().to_string();
+                line.clear();
+                commands.push(command.clone());
+
+                let upper = command.to_ascii_uppercase();
+                let reply: &[u8] = if refused_token {
+                    refused_token = false;
+                    b"535 5.7.8 Username and Password not accepted\r\n"
+                } else if upper.starts_with("EHLO") {
+                    b"250-mock\r\n250-8BITMIME\r\n250 AUTH PLAIN LOGIN XOAUTH2\r\n"
+                } else if upper.starts_with("AUTH XOAUTH2") {
+                    let response = STANDARD.decode(&command[13..]).unwrap();
+                    if response == b"user=user\x01auth=Bearer token\x01\x01" {
+                        b"235 2.7.0 Accepted\r\n"
+                    } else {
+                        refused_token = true;
+                        // {"status":"401","schemes":"Bearer"}
+                        b"334 eyJzdGF0dXMiOiI0MDEiLCJzY2hlbWVzIjoiQmVhcmVyIn0=\r\n"
+                    }
+                } else if upper.starts_with("AUTH PLAIN") {
+                    // base64 of "\0user\0secret"
+                    if command.ends_with("AHVzZXIAc2VjcmV0") {
+                        b"235 2.7.0 Authentication successful\r\n"
+                    } else {
+                        b"535 5.7.8 Authentication credentials invalid\r\n"
+                    }
+                } else if upper.starts_with("RCPT") && command.contains("nowhere.example") {
+                    b"550 5.1.1 No such user here\r\n"
+                } else if upper.starts_with("DATA") {
+                    writer
+                        .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                        .unwrap();
+                    while reader.read_line(&mut line).unwrap() > 0 && line != ".\r\n" {
+                        line.clear();
+                    }
+                    line.clear();
+                    b"250 2.0.0 Ok: queued as 4F2A9C\r\n"
+                } else if upper.starts_with("QUIT") {
+                    writer.write_all(b"221 2.0.0 Bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 2.1.0 Ok\r\n"
+                };
+                writer.write_all(reply).unwrap();
+            }
+            commands
+        });
+
+        (port, handle)
+    }
+
+    fn provider(port: u16) -> EmailProvider {
+        EmailProvider::Custom(ServerConfig {
+            imap: Server::new("127.0.0.1", 143, Security::Plain),
+            smtp: Server::new("127.0.0.1", port, Security::Plain),
+        })
+    }
+
+    fn message(to: &[&str]) -> Message {
+        build_message(&Email {
+            from: "me@example.com".parse().unwrap(),
+            to: to.iter().map(|a| a.parse().unwrap()).collect(),
+            subject: "Report".to_string(),
+            body: "Numbers attached.".to_string(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn user(password: &str) -> UserCredentials {
+        UserCredentials::new("user".to_string(), password.into())
+    }
+
+    fn oauth_user(access_token: &str) -> UserCredentials {
+        let config = OAuthConfig::for_provider(&EmailProvider::Google, "hermes".into(), None);
+        let tokens = TokenSource::new(config.unwrap(), "refresh".into())
+            .with_access_token(access_token.into(), Duration::from_secs(3600));
+        UserCredentials::oauth2("user".to_string(), tokens)
+    }
+
+    #[test]
+    fn test_refused_recipient_does_not_stop_the_rest() {
+        let (port, server) = mock_server();
+        let message = message(&["ann@example.com", "ghost@nowhere.example"]);
+
+        let report = send_message(&provider(port), &user("secret"), &message).unwrap();
+        assert_eq!(report.reply.code, 250);
+        assert!(report.reply.message.contains("queued as 4F2A9C"));
+        assert!(report.message_id.starts_with('<'));
+        assert_eq!(report.accepted, vec!["ann@example.com"]);
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(report.rejected[0].0, "ghost@nowhere.example");
+        assert_eq!(report.rejected[0].1.code, 550);
+        assert_eq!(report.rejected[0].1.message, "5.1.1 No such user here");
+
+        let commands = server.join().unwrap();
+        assert!(commands.iter().any(|c| c == "DATA"));
+    }
+
+    #[test]
+    fn test_nothing_sent_when_every_recipient_is_refused() {
+        let (port, server) = mock_server();
+        let message = message(&["ghost@nowhere.example"]);
+
+        let error = send_message(&provider(port), &user("secret"), &message).unwrap_err();
+        match &error {
+            SendError::NoRecipients(rejected) => assert_eq!(rejected[0].1.code, 550),
+            other => panic!("expected NoRecipients, got {:?}", other),
+        }
+        assert!(error.to_string().contains("ghost@nowhere.example (550"));
+
+        let commands = server.join().unwrap();
+        assert!(!commands.iter().any(|c| c == "DATA"));
+    }
+
+    #[test]
+    fn test_bad_password_is_an_authentication_error() {
+        let (port, server) = mock_server();
+
+        let error = send_message(
+            &provider(port),
+            &user("wrong"),
+            &message(&["ann@example.com"]),
+        )
+        .unwrap_err();
+        assert!(matches!(error, SendError::Authentication(ref e) if e.starts_with("535")));
+        assert!(!server.join().unwrap().iter().any(|c| c.starts_with("MAIL")));
+    }
+
+    #[test]
+    fn test_oauth2_login() {
+        let (port, server) = mock_server();
+        send_message(
+            &provider(port),
+            &oauth_user("token"),
+            &message(&["ann@example.com"]),
+        )
+        .unwrap();
+        assert!(server.join().unwrap()[1].starts_with("AUTH XOAUTH2 "));
+
+        let (port, server) = mock_server();
+        let error = send_message(
+            &provider(port),
+            &oauth_user("expired"),
+            &message(&["ann@example.com"]),
+        )
+        .unwrap_err();
+        match &error {
+            SendError::Authentication(e) => {
+                assert!(e.starts_with("535"), "{}", e);
+                assert!(e.contains(r#""status":"401""#), "{}", e);
+            }
+            other => panic!("expected Authentication, got {:?}", other),
+        }
+        assert!(!server.join().unwrap().iter().any(|c| c.starts_with("MAIL")));
+    }
+
+    #[test]
+    fn test_unreachable_server_is_a_connection_error() {
+        // Bind and drop to find a port nothing listens on
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let error = send_message(
+            &provider(port),
+            &user("secret"),
+            &message(&["a@example.com"]),
+        )
+        .unwrap_err();
+        assert!(matches!(error, SendError::Connection(_)));
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let reply = SmtpReply {
+            code: 452,
+            message: "4.3.1 Insufficient system storage".to_string(),
+        };
+        let deferred = SendError::Refused {
+            reply,
+            temporary: true,
+        };
+        assert_eq!(
+            deferred.to_string(),
+            "the server deferred the message, try again later: 452 4.3.1 Insufficient system storage"
+        );
+    }
+}
@@ -0,0 +1,3 @@
+//! Mail access for hermes: IMAP and SMTP sessions, the in-memory backend, and the
+//! message model with its RFC 5322 and `.eml` file conversions
+pub mod email_tools;
@@ -1,11 +1,24 @@
-mod email_tools;
 mod ui;
 
 use clap::Parser;
 use dotenv::dotenv;
-use email_tools::cli::{Cli, Commands, InboxCommands};
-use email_tools::{Email, EmailProvider, UserCredentials, get_inbox_all, get_inbox_one, send_email};
-use std::env;
+use email_tools::attachment::{list_attachments, save_attachment};
+use email_tools::cli::{Cli, Commands, CredentialCommands, DraftCommands, InboxCommands};
+use email_tools::compose::{Composed, append_signature, compose, editor, sign};
+use email_tools::config::{Account, load_config, select_account};
+use email_tools::credentials::{CredentialStore, Secret};
+use email_tools::drafts::{discard_draft, drafts_folder, list_drafts, open_draft, save_draft};
+use email_tools::folder::sort_folders;
+use email_tools::mime::format_size;
+use email_tools::oauth;
+use email_tools::reply::{forward, reply, reply_identity};
+use email_tools::{
+    Address, Attachment, Email, Flag, ImapBackend, MailBackend, MailSession, MemoryBackend,
+    SearchQuery, SentCopy, Window, delete_messages,
+};
+use hermes::email_tools;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // Load environment variables from .env
@@ -13,48 +26,662 @@ fn main() {
 
     let cli = Cli::parse();
 
-    let username = env::var("EMAIL_USERNAME").unwrap_or_else(|_| {
-        eprintln!("EMAIL_USERNAME not set. Add it to your .env file or export it.");
-        std::process::exit(1);
-    });
-
-    let password = env::var("EMAIL_PASSWORD").unwrap_or_else(|_| {
-        eprintln!("EMAIL_PASSWORD not set. Add it to your .env file or export it.");
-        std::process::exit(1);
-    });
+    match &cli.command {
+        Commands::Accounts => {
+            print_accounts(cli.config.as_deref());
+            return;
+        }
+        Commands::Credentials { command } => {
+            if let Err(e) = manage_credentials(cli.config.as_deref(), command) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => {}
+    }
 
-    let credentials = UserCredentials::new(username, password);
-    let provider = EmailProvider::Google;
+    let account = if cli.demo {
+        Account::demo()
+    } else {
+        select_account(cli.config.as_deref(), cli.account.as_deref()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    };
+    let mut backend: Box<dyn MailBackend> = if cli.demo {
+        Box::new(MemoryBackend::demo())
+    } else {
+        Box::new(imap_backend(&account))
+    };
 
     match cli.command {
-        Commands::Inbox { command } => match command {
-            InboxCommands::One { id } => match get_inbox_one(provider, credentials.clone(), id) {
-                Ok(email) => println!("{:#?}", email),
-                Err(e) => eprintln!("Could not retrieve message: {}", e),
-            },
-            InboxCommands::All => match get_inbox_all(provider, credentials.clone()) {
-                Ok(inbox) => println!("{:#?}", inbox),
-                Err(e) => eprintln!("Could not retrieve inbox: {}", e),
-            },
-        },
-        Commands::Send { from, to, subject, body } => {
-            let mailbox = to.into_iter().next().expect("No recipient provided");
+        Commands::Accounts | Commands::Credentials { .. } => {
+            unreachable!("handled before choosing an account")
+        }
+        Commands::Inbox { folder, command } => {
+            let folder = account.folder(folder);
+            match command {
+                InboxCommands::One { uid, uid_validity } => {
+                    match backend.fetch_message(&folder, uid) {
+                        Ok(email) => {
+                            if let Some(expected) = uid_validity
+                                && expected != email.uid_validity
+                            {
+                                eprintln!(
+                                    "UIDVALIDITY changed from {} to {}; \
+                                     stored UIDs are no longer valid",
+                                    expected, email.uid_validity
+                                );
+                                std::process::exit(1);
+                            }
+                            println!("{:#?}", email);
+                        }
+                        Err(e) => {
+                            eprintln!("Could not retrieve message: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                InboxCommands::All { limit, page, uids } => {
+                    let window = listing_window(limit, page, uids);
+                    match backend.fetch_envelopes(&folder, &window) {
+                        Ok(inbox) => {
+                            for email in &inbox.inbox {
+                                println!("{}\t{}\t{}", email.uid, email.from, email.subject);
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("Could not retrieve inbox: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+        }
+        Commands::Folders { subscribed } => {
+            if let Err(e) = print_folders(backend.as_mut(), subscribed) {
+                eprintln!("Could not list folders: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Send {
+            from,
+            to,
+            cc,
+            bcc,
+            reply_to,
+            subject,
+            body,
+            body_file,
+            html,
+            attach,
+        } => {
+            let body = match (body, body_file) {
+                (Some(body), _) => Ok(body),
+                (None, Some(path)) => std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Could not read {}: {}", path.display(), e)),
+                (None, None) => read_stdin_body(),
+            };
+            let body = body.unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            let html = html.map(|path| {
+                std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                    eprintln!("Could not read {}: {}", path.display(), e);
+                    std::process::exit(1);
+                })
+            });
+
+            let body = match &account.signature {
+                Some(signature) => append_signature(&body, signature),
+                None => body,
+            };
+
             let email = Email {
-                from: from.clone(),
-                to: vec![mailbox.clone()],
+                from: sender(from, &account),
+                to,
+                cc,
+                bcc,
+                reply_to,
                 subject,
                 body,
+                html,
+                attachments: read_attachments(&attach),
+                ..Default::default()
+            };
+            exit_with(send_and_report(backend.as_mut(), email));
+        }
+        Commands::Compose {
+            from,
+            to,
+            cc,
+            bcc,
+            reply_to,
+            subject,
+            attach,
+        } => {
+            let draft = Email {
+                from: sender(from, &account),
+                to,
+                cc,
+                bcc,
+                reply_to,
+                subject,
+                body: String::new(),
+                attachments: read_attachments(&attach),
                 ..Default::default()
             };
 
-            if let Err(e) = send_email(email, credentials.clone()) {
-                eprintln!("Failed to send email: {}", e);
+            let draft = signed(draft, &account);
+            if let Some(status) = compose_and_send(backend.as_mut(), &draft, None) {
+                exit_with(status);
+            }
+        }
+        Commands::Reply {
+            uid,
+            folder,
+            all,
+            from,
+        } => {
+            let folder = account.folder(folder);
+            let original = backend.fetch_message(&folder, uid).unwrap_or_else(|e| {
+                eprintln!("Could not retrieve message: {}", e);
+                std::process::exit(1);
+            });
+            let from = from.or_else(|| reply_identity(&account.identities, &original).cloned());
+            let draft = signed(reply(&original, &sender(from, &account), all), &account);
+
+            if let Some(status) = compose_and_send(backend.as_mut(), &draft, None) {
+                if status != 1
+                    && let Err(e) = backend.flag(&folder, uid, &[Flag::Answered], true)
+                {
+                    eprintln!("Could not mark the message answered: {}", e);
+                }
+                exit_with(status);
+            }
+        }
+        Commands::Forward {
+            uid,
+            folder,
+            to,
+            cc,
+            from,
+        } => {
+            let folder = account.folder(folder);
+            let fetched = backend
+                .fetch_message(&folder, uid)
+                .and_then(|email| Ok((backend.fetch_raw(&folder, uid)?, email)));
+            let (raw, original) = fetched.unwrap_or_else(|e| {
+                eprintln!("Could not retrieve message: {}", e);
+                std::process::exit(1);
+            });
+            let from = from.or_else(|| reply_identity(&account.identities, &original).cloned());
+            let draft = Email {
+                cc,
+                ..forward(&original, raw, &sender(from, &account), to)
+            };
+            let draft = signed(draft, &account);
+
+            if let Some(status) = compose_and_send(backend.as_mut(), &draft, None) {
+                exit_with(status);
+            }
+        }
+        Commands::Drafts { command: None } => match list_drafts(backend.as_mut()) {
+            Ok((_, drafts)) => {
+                for draft in &drafts {
+                    let to: Vec<String> = draft.to.iter().map(|addr| addr.to_string()).collect();
+                    println!("{}\t{}\t{}", draft.uid, to.join(", "), draft.subject);
+                }
+            }
+            Err(e) => {
+                eprintln!("Could not list drafts: {}", e);
+                std::process::exit(1);
             }
         },
+        Commands::Drafts {
+            command: Some(DraftCommands::Edit { uid }),
+        } => {
+            let draft = drafts_folder(backend.as_mut())
+                .and_then(|folder| open_draft(backend.as_mut(), &folder, uid));
+            let draft = draft.unwrap_or_else(|e| {
+                eprintln!("Could not open draft: {}", e);
+                std::process::exit(1);
+            });
+
+            if let Some(status) = compose_and_send(backend.as_mut(), &draft, Some(uid)) {
+                exit_with(status);
+            }
+        }
+        Commands::Search {
+            query,
+            folder,
+            limit,
+            page,
+            uids,
+        } => {
+            let query = match SearchQuery::from_args(&query) {
+                Ok(query) => query,
+                Err(e) => {
+                    eprintln!("Invalid search: {}", e);
+                    std::process::exit(2);
+                }
+            };
+
+            let window = listing_window(limit, page, uids);
+            match backend.search(&account.folder(folder), &query, &window) {
+                Ok(inbox) => {
+                    for email in &inbox.inbox {
+                        println!("{}\t{}\t{}", email.uid, email.from, email.subject);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Search failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Flag {
+            uids,
+            folder,
+            add,
+            remove,
+        } => {
+            let folder = account.folder(folder);
+            for uid in &uids {
+                let result = backend
+                    .flag(&folder, *uid, &add, true)
+                    .and_then(|_| backend.flag(&folder, *uid, &remove, false));
+
+                if let Err(e) = result {
+                    eprintln!("Could not update flags of message {}: {}", uid, e);
+                    std::process::exit(1);
+                }
+            }
+            println!("Updated flags of {} message(s)", uids.len());
+        }
+        Commands::Move { uids, folder, to } => {
+            let folder = account.folder(folder);
+            for uid in &uids {
+                if let Err(e) = backend.move_message(&folder, *uid, &to) {
+                    eprintln!("Could not move message {}: {}", uid, e);
+                    std::process::exit(1);
+                }
+            }
+            println!("Moved {} message(s) to {}", uids.len(), to);
+        }
+        Commands::Copy { uids, folder, to } => {
+            let folder = account.folder(folder);
+            for uid in &uids {
+                if let Err(e) = backend.copy_message(&folder, *uid, &to) {
+                    eprintln!("Could not copy message {}: {}", uid, e);
+                    std::process::exit(1);
+                }
+            }
+            println!("Copied {} message(s) to {}", uids.len(), to);
+        }
+        Commands::Delete {
+            uids,
+            folder,
+            permanent,
+        } => match delete_messages(backend.as_mut(), &account.folder(folder), &uids, permanent) {
+            Ok(Some(trash)) => println!("Moved {} message(s) to {}", uids.len(), trash),
+            Ok(None) => println!("Deleted {} message(s)", uids.len()),
+            Err(e) => {
+                eprintln!("Could not delete messages: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Commands::Attachments { uid, folder } => {
+            match list_attachments(backend.as_mut(), &account.folder(folder), uid) {
+                Ok(attachments) => {
+                    for (i, part) in attachments.iter().enumerate() {
+                        println!(
+                            "{}\t{}\t{}\t{}",
+                            i + 1,
+                            part.filename.as_deref().unwrap_or("(unnamed)"),
+                            part.content_type,
+                            format_size(part.size)
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Could not list attachments: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::SaveAttachment {
+            uid,
+            folder,
+            index,
+            all: _,
+            dir,
+        } => {
+            let folder = account.folder(folder);
+            let attachments =
+                list_attachments(backend.as_mut(), &folder, uid).unwrap_or_else(|e| {
+                    eprintln!("Could not list attachments: {}", e);
+                    std::process::exit(1);
+                });
+
+            // Without --index, clap guarantees --all
+            let selected: Vec<(usize, _)> = match index {
+                Some(index) => match attachments.get(index.wrapping_sub(1)) {
+                    Some(part) => vec![(index, part)],
+                    None => {
+                        eprintln!(
+                            "Message {} has {} attachment(s); there is no number {}",
+                            uid,
+                            attachments.len(),
+                            index
+                        );
+                        std::process::exit(1);
+                    }
+                },
+                None => attachments
+                    .iter()
+                    .enumerate()
+                    .map(|(i, part)| (i + 1, part))
+                    .collect(),
+            };
+
+            if selected.is_empty() {
+                println!("Message {} has no attachments", uid);
+            }
+            for (index, part) in selected {
+                match save_attachment(backend.as_mut(), &folder, uid, part, index, &dir) {
+                    Ok(path) => println!("Saved {}", path.display()),
+                    Err(e) => {
+                        eprintln!("Could not save attachment {}: {}", index, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
         Commands::Ui => {
-            if let Err(e) = ui::run_tui(provider, credentials) {
+            if let Err(e) = ui::run_tui(backend.as_mut(), &account) {
                 eprintln!("Error running UI: {}", e);
             }
         }
     }
 }
+
+/// The messages `--limit`, `--page` and `--uids` ask for
+fn listing_window(limit: Option<u32>, page: u32, uids: Option<Window>) -> Window {
+    match (uids, limit) {
+        (Some(uids), _) => uids,
+        (None, Some(limit)) => Window::page(page, limit),
+        (None, None) => Window::All,
+    }
+}
+
+/// Reads files to attach, exiting if any can't be read
+fn read_attachments(paths: &[PathBuf]) -> Vec<Attachment> {
+    paths
+        .iter()
+        .map(|path| Attachment::from_path(path))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("Could not attach file: {}", e);
+            std::process::exit(1);
+        })
+}
+
+/// Reads a message body piped in, or typed at the terminal up to Ctrl-D
+fn read_stdin_body() -> Result<String, String> {
+    if std::io::stdin().is_terminal() {
+        eprintln!("Type the message, then press Ctrl-D on a line of its own");
+    }
+    std::io::read_to_string(std::io::stdin())
+        .map_err(|e| format!("Could not read the body from standard input: {}", e))
+}
+
+/// The address to send from: `--from`, or the account's first identity
+fn sender(from: Option<Address>, account: &Account) -> Address {
+    from.or_else(|| account.address().cloned())
+        .unwrap_or_else(|| {
+            eprintln!("No sender: pass --from or give the account an identity");
+            std::process::exit(1);
+        })
+}
+
+/// `draft` with the account's signature, if it has one
+fn signed(draft: Email, account: &Account) -> Email {
+    match &account.signature {
+        Some(signature) => sign(draft, signature),
+        None => draft,
+    }
+}
+
+/// Runs the editor on `draft`, then sends the result or saves it to the Drafts folder.
+/// `resumed` is the draft being finished: deleted once sent, replaced when saved again.
+/// Returns the exit status of `send_and_report` if anything was sent.
+fn compose_and_send(
+    backend: &mut dyn MailBackend,
+    draft: &Email,
+    resumed: Option<u32>,
+) -> Option<i32> {
+    let composed = compose(draft, &editor(), &mut std::io::stdin().lock()).unwrap_or_else(|e| {
+        eprintln!("Could not compose message: {}", e);
+        std::process::exit(1);
+    });
+
+    match composed {
+        Composed::Send(email) => {
+            let status = send_and_report(backend, email);
+            if status != 1
+                && let Some(uid) = resumed
+                && let Err(e) = discard_draft(backend, uid)
+            {
+                eprintln!("Sent, but the draft was not deleted: {}", e);
+            }
+            Some(status)
+        }
+        Composed::Draft(email) => match save_draft(backend, &email, resumed) {
+            Ok(folder) => {
+                println!("Saved the draft to {}", folder);
+                None
+            }
+            Err(e) => {
+                eprintln!("Could not save the draft: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Composed::Discard => None,
+    }
+}
+
+/// Sends a message and prints the outcome. Returns the exit status: 0 when sent, 1 if
+/// nothing was sent and 3 if some recipients were refused.
+fn send_and_report(backend: &mut dyn MailBackend, email: Email) -> i32 {
+    match backend.send(email) {
+        Ok(report) => {
+            println!(
+                "Sent {} to {} recipient(s): {}",
+                report.message_id,
+                report.accepted.len(),
+                report.reply
+            );
+            for (address, reply) in &report.rejected {
+                eprintln!("Not delivered to {}: {}", address, reply);
+            }
+            match &report.copy {
+                SentCopy::Saved(folder) => println!("Saved a copy to {}", folder),
+                SentCopy::Failed(e) => eprintln!("Sent, but no copy was saved: {}", e),
+                SentCopy::NotSaved => {}
+            }
+            if report.rejected.is_empty() { 0 } else { 3 }
+        }
+        Err(e) => {
+            eprintln!("Failed to send email: {}", e);
+            1
+        }
+    }
+}
+
+fn exit_with(status: i32) {
+    if status != 0 {
+        std::process::exit(status);
+    }
+}
+
+/// Builds the IMAP/SMTP backend for `account`, exiting if its password can't be had
+fn imap_backend(account: &Account) -> ImapBackend {
+    let credentials = account
+        .credentials(|| ask_secret("Passphrase for the credentials file: "))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+
+    // Connects lazily, so commands that only need SMTP never touch IMAP
+    let backend = ImapBackend::new(MailSession::new(account.provider.clone(), credentials));
+    match account.save_sent {
+        Some(save_sent) => backend.with_save_sent(save_sent),
+        None => backend,
+    }
+}
+
+/// Reads a password or passphrase from the terminal without echoing it
+fn ask_secret(prompt: &str) -> Result<Secret, String> {
+    rpassword::prompt_password(prompt)
+        .map(Secret::new)
+        .map_err(|e| format!("Could not read the passphrase: {}", e))
+}
+
+/// Runs a `hermes credentials` command against the credentials file named in the config
+/// file, or the default one
+fn manage_credentials(config: Option<&Path>, command: &CredentialCommands) -> Result<(), String> {
+    let config = load_config(config)?;
+    let path = config
+        .as_ref()
+        .and_then(|config| config.credentials_file.clone())
+        .or_else(CredentialStore::default_path)
+        .ok_or("There is no config directory to keep the credentials file in")?;
+    let oauth2 = match command {
+        CredentialCommands::Authorize { account } => {
+            let account = config
+                .as_ref()
+                .ok_or("OAuth2 accounts are set up in the config file, and there is none")?
+                .account(Some(account))?;
+            let missing = || format!("Account '{}' has no oauth2 settings", account.name);
+            Some(account.oauth2.clone().ok_or_else(missing)?)
+        }
+        _ => None,
+    };
+
+    // A new file is locked with a passphrase chosen now, asked for twice
+    let (mut store, passphrase) = if path.exists() {
+        let passphrase = ask_secret("Passphrase for the credentials file: ")?;
+        (CredentialStore::open(&path, &passphrase)?, passphrase)
+    } else if let CredentialCommands::Set { .. } | CredentialCommands::Authorize { .. } = command {
+        let passphrase = ask_secret("New passphrase for the credentials file: ")?;
+        if ask_secret("Repeat the passphrase: ")? != passphrase {
+            return Err("The passphrases don't match".to_string());
+        }
+        (CredentialStore::default(), passphrase)
+    } else {
+        return Err(format!(
+            "There is no credentials file at {}",
+            path.display()
+        ));
+    };
+
+    match command {
+        CredentialCommands::Set { account } => {
+            let password = ask_secret(&format!("Password for {}: ", account))?;
+            store.set(account, password);
+            store.save(&path, &passphrase)?;
+            println!("Stored the password for {} in {}", account, path.display());
+        }
+        CredentialCommands::Authorize { account } => {
+            let oauth2 = oauth2.expect("looked up above");
+            let tokens = oauth::authorize(&oauth2, |page| {
+                eprintln!("Open this address in a browser and sign in as {}:", account);
+                eprintln!("{}", page);
+            })?;
+            store.set(
+                account,
+                tokens.refresh_token.expect("authorize insists on one"),
+            );
+            store.save(&path, &passphrase)?;
+            println!(
+                "Stored the refresh token for {} in {}",
+                account,
+                path.display()
+            );
+        }
+        CredentialCommands::Remove { account } => {
+            if !store.remove(account) {
+                return Err(format!("Nothing is stored for {}", account));
+            }
+            store.save(&path, &passphrase)?;
+            println!("Removed the stored credentials of {}", account);
+        }
+        CredentialCommands::List => {
+            for account in store.accounts() {
+                println!("{}", account);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists the accounts of the config file, the default one marked with `*`
+fn print_accounts(path: Option<&Path>) {
+    let config = match load_config(path) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            println!("No config file; using the account in the EMAIL_* environment variables");
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    for account in &config.accounts {
+        let marker = if config.default_name() == Some(account.name.as_str()) {
+            "*"
+        } else {
+            ""
+        };
+        let server = account.provider.imap_server();
+        println!(
+            "{}{}\t{}\t{}:{}",
+            account.name,
+            marker,
+            account.address().map(|a| a.to_string()).unwrap_or_default(),
+            server.host,
+            server.port
+        );
+    }
+}
+
+/// Prints the folder hierarchy, indented by depth, with unread/total counts
+fn print_folders(
+    backend: &mut dyn MailBackend,
+    subscribed_only: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut folders = backend.list_folders(subscribed_only)?;
+    sort_folders(&mut folders);
+
+    for folder in &folders {
+        let mut line = format!("{}{}", "  ".repeat(folder.depth()), folder.leaf_name());
+
+        if folder.is_selectable() {
+            let status = backend.folder_status(&folder.name)?;
+            line.push_str(&format!("  {}/{}", status.unseen, status.total));
+        }
+        if let Some(special_use) = folder.special_use() {
+            line.push_str(&format!("  {}", special_use.attribute()));
+        }
+
+        println!("{}", line);
+    }
+
+    Ok(())
+}
@@ -1,22 +1,803 @@
-use crate::email_tools::{Email, get_inbox_all, UserCredentials, EmailProvider, Inbox};
+use chrono::{DateTime, FixedOffset, Local};
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+};
+use hermes::email_tools::attachment::{list_attachments, save_attachment};
+use hermes::email_tools::compose::{Composed, compose, editor, sign};
+use hermes::email_tools::config::Account;
+use hermes::email_tools::date::format_relative;
+use hermes::email_tools::drafts::{discard_draft, drafts_folder, open_draft, save_draft};
+use hermes::email_tools::folder::sort_folders;
+use hermes::email_tools::mime::format_size;
+use hermes::email_tools::reply::{forward, reply, reply_identity};
+use hermes::email_tools::{
+    Address, Email, Flag, Folder, Inbox, MailBackend, MimePart, SearchQuery, SentCopy, Window,
+    delete_messages,
 };
 use ratatui::{
+    Terminal,
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
-    Terminal,
+    style::{Color, Modifier, Style},
     widgets::ListState,
+    widgets::{Block, Borders, List, ListItem, Paragraph},
 };
+use std::collections::HashMap;
 use std::io;
+use std::path::PathBuf;
+
+/// How many envelopes to fetch at a time
+const PAGE_SIZE: u32 = 100;
+
+/// Fetch the next page once the selection gets this close to the end of the list
+const LOAD_AHEAD: usize = 10;
+
+/// How the message list is ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Newest arrival (highest UID) first, the order the server hands them out in
+    Arrival,
+    /// Newest Date header first, undated messages last
+    Date,
+}
+
+/// What the keyboard is currently driving
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    /// Waiting for y/n before deleting the selected message
+    ConfirmDelete,
+    /// Typing a search query after `/`
+    Search,
+    /// Choosing the folder to move (or copy) the selected message to
+    PickFolder {
+        copy: bool,
+    },
+    /// Browsing the open message's attachments
+    Attachments,
+}
+
+/// A reply, forward or draft waiting for the editor, which needs the terminal to itself
+pub struct Outgoing {
+    pub draft: Email,
+    /// The message being replied to, marked \Answered once the reply is sent
+    pub answers: Option<u32>,
+    /// The draft being finished, deleted once sent and replaced when saved again
+    pub resumes: Option<u32>,
+}
+
+/// Everything the TUI shows, kept apart from the terminal so key handling can be tested
+/// against any backend
+pub struct App {
+    pub folder: String,
+    /// The folder the list opened on, which `D` switches back to
+    home_folder: String,
+    /// Envelopes loaded so far, newest first
+    pub inbox: Inbox,
+    /// Set once a fetch came back short, i.e. the oldest message is loaded
+    exhausted: bool,
+    /// The query the list is currently filtered by, if any
+    pub search: Option<String>,
+    /// `search`, parsed
+    query: Option<SearchQuery>,
+    /// What has been typed at the search prompt so far
+    search_input: String,
+    pub selected_index: usize,
+    pub view_email: Option<Email>,
+    /// Destinations offered by the folder picker
+    pub picker: Vec<Folder>,
+    pub picker_index: usize,
+    /// Attachments of the open message, listed by the attachment pane
+    pub attachments: Vec<MimePart>,
+    pub attachment_index: usize,
+    /// Where saved attachments go
+    pub save_dir: PathBuf,
+    /// Full messages fetched so far, by UID, so reopening one doesn't hit the server
+    bodies: HashMap<u32, Email>,
+    pub mode: Mode,
+    pub sort: SortOrder,
+    /// One-line message shown at the bottom of the screen
+    pub status: String,
+    /// The addresses replies and forwards can be sent from
+    pub identities: Vec<Address>,
+    /// Added to replies and forwards
+    pub signature: Option<String>,
+    /// Set by r/R/f/e; the terminal loop hands it to the editor
+    pub outgoing: Option<Outgoing>,
+}
+
+impl App {
+    pub fn load(backend: &mut dyn MailBackend, folder: &str) -> App {
+        let mut app = App {
+            folder: folder.to_string(),
+            home_folder: folder.to_string(),
+            inbox: Inbox { inbox: vec![] },
+            exhausted: false,
+            search: None,
+            query: None,
+            search_input: String::new(),
+            selected_index: 0,
+            view_email: None,
+            picker: Vec::new(),
+            picker_index: 0,
+            attachments: Vec::new(),
+            attachment_index: 0,
+            save_dir: dirs::download_dir().unwrap_or_else(|| PathBuf::from(".")),
+            bodies: HashMap::new(),
+            mode: Mode::Normal,
+            sort: SortOrder::Arrival,
+            status: String::new(),
+            identities: Vec::new(),
+            signature: None,
+            outgoing: None,
+        };
+
+        app.load_more(backend);
+        app
+    }
+
+    /// Fetches the next page of older envelopes, unless everything is loaded already
+    fn load_more(&mut self, backend: &mut dyn MailBackend) {
+        if self.exhausted {
+            return;
+        }
+
+        let window = Window::Newest {
+            offset: self.inbox.inbox.len() as u32,
+            limit: PAGE_SIZE,
+        };
+
+        let page = match &self.query {
+            Some(query) => backend.search(&self.folder, query, &window),
+            None => backend.fetch_envelopes(&self.folder, &window),
+        };
+
+        match page {
+            Ok(page) => {
+                self.exhausted = page.inbox.len() < PAGE_SIZE as usize;
+                // New mail shifts the offsets, so skip anything already listed
+                for email in page.inbox {
+                    if !self.inbox.inbox.iter().any(|e| e.uid == email.uid) {
+                        self.inbox.inbox.push(email);
+                    }
+                }
+                self.apply_sort();
+            }
+            Err(e) => {
+                self.exhausted = true;
+                self.status = format!("Could not fetch messages: {}", e);
+            }
+        }
+    }
+
+    /// Re-sorts the list, keeping the same message selected
+    fn apply_sort(&mut self) {
+        let selected = self.inbox.inbox.get(self.selected_index).map(|e| e.uid);
+
+        match self.sort {
+            SortOrder::Arrival => self.inbox.inbox.sort_by_key(|e| std::cmp::Reverse(e.uid)),
+            SortOrder::Date => self.inbox.inbox.sort_by_key(|e| std::cmp::Reverse(e.date)),
+        }
+
+        if let Some(uid) = selected {
+            self.selected_index = self
+                .inbox
+                .inbox
+                .iter()
+                .position(|e| e.uid == uid)
+                .unwrap_or(0);
+        }
+    }
+
+    /// Drops any search filter and starts listing the folder from the newest message again
+    fn reload(&mut self, backend: &mut dyn MailBackend) {
+        self.search = None;
+        self.query = None;
+        self.inbox.inbox.clear();
+        self.exhausted = false;
+        self.selected_index = 0;
+        self.view_email = None;
+        self.load_more(backend);
+    }
+
+    /// Replaces the list with the messages matching the search prompt's query
+    fn run_search(&mut self, backend: &mut dyn MailBackend) {
+        let input = std::mem::take(&mut self.search_input);
+        if input.trim().is_empty() {
+            self.reload(backend);
+            return;
+        }
+
+        let query = match input.parse::<SearchQuery>() {
+            Ok(query) => query,
+            Err(e) => {
+                self.status = format!("Invalid search: {}", e);
+                return;
+            }
+        };
+
+        self.status = format!("Showing messages matching \"{}\" (Esc to clear)", input);
+        self.search = Some(input);
+        self.query = Some(query);
+        self.inbox.inbox.clear();
+        self.exhausted = false;
+        self.selected_index = 0;
+        self.view_email = None;
+        // Results page in as the list scrolls, like the folder itself
+        self.load_more(backend);
+    }
+
+    fn handle_search_key(&mut self, backend: &mut dyn MailBackend, code: KeyCode) {
+        match code {
+            KeyCode::Enter => {
+                self.mode = Mode::Normal;
+                self.run_search(backend);
+            }
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                self.search_input.clear();
+            }
+            KeyCode::Backspace => {
+                self.search_input.pop();
+            }
+            KeyCode::Char(c) => self.search_input.push(c),
+            _ => {}
+        }
+    }
+
+    /// Applies a key press. Returns false once the user asks to quit.
+    pub fn handle_key(&mut self, backend: &mut dyn MailBackend, code: KeyCode) -> bool {
+        match self.mode {
+            Mode::Normal => self.handle_normal_key(backend, code),
+            Mode::ConfirmDelete => {
+                self.mode = Mode::Normal;
+                if let KeyCode::Char('y') | KeyCode::Char('Y') = code {
+                    self.delete_selected(backend);
+                } else {
+                    self.status = "Delete cancelled".to_string();
+                }
+                true
+            }
+            Mode::Search => {
+                self.handle_search_key(backend, code);
+                true
+            }
+            Mode::PickFolder { copy } => {
+                self.handle_picker_key(backend, code, copy);
+                true
+            }
+            Mode::Attachments => {
+                self.handle_attachment_key(backend, code);
+                true
+            }
+        }
+    }
+
+    /// The prompt or message for the status bar
+    pub fn status_line(&self) -> String {
+        match self.mode {
+            Mode::ConfirmDelete => {
+                let subject = self
+                    .inbox
+                    .inbox
+                    .get(self.selected_index)
+                    .map(|e| e.subject.as_str())
+                    .unwrap_or_default();
+                format!("Delete \"{}\"? (y/n)", subject)
+            }
+            Mode::Search => format!("/{}", self.search_input),
+            Mode::PickFolder { copy } => format!(
+                "{} to which folder? (Enter to confirm, Esc to cancel)",
+                if copy { "Copy" } else { "Move" }
+            ),
+            Mode::Attachments if self.status.is_empty() => {
+                format!("s to save to {}, Esc to close", self.save_dir.display())
+            }
+            Mode::Normal | Mode::Attachments => self.status.clone(),
+        }
+    }
+
+    fn delete_selected(&mut self, backend: &mut dyn MailBackend) {
+        let Some(email) = self.inbox.inbox.get(self.selected_index) else {
+            return;
+        };
+        let uid = email.uid;
+
+        match delete_messages(backend, &self.folder, &[uid], false) {
+            Ok(trash) => {
+                self.forget_selected(uid);
+                self.status = match trash {
+                    Some(trash) => format!("Moved to {}", trash),
+                    None => "Message deleted".to_string(),
+                };
+            }
+            Err(e) => self.status = format!("Could not delete message: {}", e),
+        }
+    }
+
+    /// Drops the selected message from the list once it has left the folder
+    fn forget_selected(&mut self, uid: u32) {
+        self.inbox.inbox.remove(self.selected_index);
+        self.bodies.remove(&uid);
+        if self.view_email.as_ref().is_some_and(|e| e.uid == uid) {
+            self.view_email = None;
+        }
+        self.selected_index = self
+            .selected_index
+            .min(self.inbox.inbox.len().saturating_sub(1));
+    }
+
+    /// Lists the folders the selected message could be filed into and opens the picker
+    fn open_picker(&mut self, backend: &mut dyn MailBackend, copy: bool) {
+        let mut folders = match backend.list_folders(false) {
+            Ok(folders) => folders,
+            Err(e) => {
+                self.status = format!("Could not list folders: {}", e);
+                return;
+            }
+        };
+        folders.retain(|folder| folder.is_selectable() && folder.name != self.folder);
+        sort_folders(&mut folders);
+
+        if folders.is_empty() {
+            self.status = "There are no other folders".to_string();
+            return;
+        }
+
+        self.picker = folders;
+        self.picker_index = 0;
+        self.mode = Mode::PickFolder { copy };
+    }
+
+    fn handle_picker_key(&mut self, backend: &mut dyn MailBackend, code: KeyCode, copy: bool) {
+        match code {
+            KeyCode::Down if self.picker_index + 1 < self.picker.len() => {
+                self.picker_index += 1;
+            }
+            KeyCode::Up if self.picker_index > 0 => {
+                self.picker_index -= 1;
+            }
+            KeyCode::Enter => {
+                self.mode = Mode::Normal;
+                let destination = self.picker[self.picker_index].name.clone();
+                self.file_selected(backend, &destination, copy);
+            }
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+            }
+            _ => {}
+        }
+    }
+
+    fn file_selected(&mut self, backend: &mut dyn MailBackend, destination: &str, copy: bool) {
+        let Some(email) = self.inbox.inbox.get(self.selected_index) else {
+            return;
+        };
+        let uid = email.uid;
+
+        let result = if copy {
+            backend.copy_message(&self.folder, uid, destination)
+        } else {
+            backend.move_message(&self.folder, uid, destination)
+        };
+
+        match result {
+            Ok(()) if copy => self.status = format!("Copied to {}", destination),
+            Ok(()) => {
+                self.forget_selected(uid);
+                self.status = format!("Moved to {}", destination);
+            }
+            Err(e) => self.status = format!("Could not file message: {}", e),
+        }
+    }
+
+    /// Lists the open message's attachments in the attachment pane
+    fn open_attachments(&mut self, backend: &mut dyn MailBackend) {
+        let Some(email) = &self.view_email else {
+            return;
+        };
+
+        match list_attachments(backend, &self.folder, email.uid) {
+            Ok(attachments) if attachments.is_empty() => {
+                self.status = "This message has no attachments".to_string();
+            }
+            Ok(attachments) => {
+                self.attachments = attachments;
+                self.attachment_index = 0;
+                self.status.clear();
+                self.mode = Mode::Attachments;
+            }
+            Err(e) => self.status = format!("Could not list attachments: {}", e),
+        }
+    }
+
+    fn handle_attachment_key(&mut self, backend: &mut dyn MailBackend, code: KeyCode) {
+        match code {
+            KeyCode::Down if self.attachment_index + 1 < self.attachments.len() => {
+                self.attachment_index += 1;
+            }
+            KeyCode::Up if self.attachment_index > 0 => {
+                self.attachment_index -= 1;
+            }
+            KeyCode::Char('s') | KeyCode::Enter => self.save_selected_attachment(backend),
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                self.status.clear();
+            }
+            _ => {}
+        }
+    }
+
+    fn save_selected_attachment(&mut self, backend: &mut dyn MailBackend) {
+        let (Some(email), Some(part)) = (
+            &self.view_email,
+            self.attachments.get(self.attachment_index),
+        ) else {
+            return;
+        };
+
+        self.status = match save_attachment(
+            backend,
+            &self.folder,
+            email.uid,
+            part,
+            self.attachment_index + 1,
+            &self.save_dir,
+        ) {
+            Ok(path) => format!("Saved {}", path.display()),
+            Err(e) => format!("Could not save attachment: {}", e),
+        };
+    }
+
+    /// Sets or clears `flag` on the server, then on every local copy of the message
+    fn set_flag(&mut self, backend: &mut dyn MailBackend, uid: u32, flag: Flag, set: bool) {
+        if let Err(e) = backend.flag(&self.folder, uid, std::slice::from_ref(&flag), set) {
+            self.status = format!("Could not update flags: {}", e);
+            return;
+        }
+
+        let copies = self
+            .inbox
+            .inbox
+            .iter_mut()
+            .chain(self.bodies.values_mut())
+            .chain(self.view_email.as_mut())
+            .filter(|email| email.uid == uid);
+        for email in copies {
+            email.flags.retain(|f| *f != flag);
+            if set {
+                email.flags.push(flag.clone());
+            }
+        }
+    }
+
+    fn toggle_selected_flag(&mut self, backend: &mut dyn MailBackend, flag: Flag) {
+        let Some(email) = self.inbox.inbox.get(self.selected_index) else {
+            return;
+        };
+        let uid = email.uid;
+        let set = !email.flags.contains(&flag);
+        self.set_flag(backend, uid, flag, set);
+    }
+
+    /// Shows the selected message, fetching its body the first time it is opened
+    fn open_selected(&mut self, backend: &mut dyn MailBackend) {
+        let Some(envelope) = self.inbox.inbox.get(self.selected_index) else {
+            return;
+        };
+
+        if !self.bodies.contains_key(&envelope.uid) {
+            match backend.fetch_message(&self.folder, envelope.uid) {
+                Ok(email) => {
+                    self.bodies.insert(envelope.uid, email);
+                }
+                Err(e) => {
+                    self.status = format!("Could not fetch message: {}", e);
+                    self.view_email = Some(envelope.clone());
+                    return;
+                }
+            }
+        }
+
+        let uid = envelope.uid;
+        self.view_email = self.bodies.get(&uid).cloned();
+
+        // Bodies are fetched with BODY.PEEK, so reading is marked explicitly
+        if self
+            .view_email
+            .as_ref()
+            .is_some_and(|email| !email.flags.contains(&Flag::Seen))
+        {
+            self.set_flag(backend, uid, Flag::Seen, true);
+        }
+    }
+
+    /// Sets up a reply to the open message for the editor
+    fn prepare_reply(&mut self, all: bool) {
+        let Some(email) = &self.view_email else {
+            return;
+        };
+        let Some(me) = reply_identity(&self.identities, email) else {
+            self.status = "No address to reply from; give the account an identity".to_string();
+            return;
+        };
+        self.outgoing = Some(Outgoing {
+            draft: self.signed(reply(email, me, all)),
+            answers: Some(email.uid),
+            resumes: None,
+        });
+    }
+
+    /// Sets up a forward of the open message, fetched whole to go along as an attachment
+    fn prepare_forward(&mut self, backend: &mut dyn MailBackend) {
+        let Some(email) = &self.view_email else {
+            return;
+        };
+        let Some(me) = reply_identity(&self.identities, email) else {
+            self.status = "No address to forward from; give the account an identity".to_string();
+            return;
+        };
+        match backend.fetch_raw(&self.folder, email.uid) {
+            Ok(raw) => {
+                self.outgoing = Some(Outgoing {
+                    draft: self.signed(forward(email, raw, me, Vec::new())),
+                    answers: None,
+                    resumes: None,
+                });
+            }
+            Err(e) => self.status = format!("Could not fetch message: {}", e),
+        }
+    }
+
+    fn signed(&self, draft: Email) -> Email {
+        match &self.signature {
+            Some(signature) => sign(draft, signature),
+            None => draft,
+        }
+    }
+
+    /// Sets up the selected draft to be finished in the editor
+    fn resume_selected(&mut self, backend: &mut dyn MailBackend) {
+        let Some(envelope) = self.inbox.inbox.get(self.selected_index) else {
+            return;
+        };
+        let uid = envelope.uid;
+        match open_draft(backend, &self.folder, uid) {
+            Ok(draft) => {
+                self.outgoing = Some(Outgoing {
+                    draft,
+                    answers: None,
+                    resumes: Some(uid),
+                });
+            }
+            Err(e) => self.status = format!("Could not open draft: {}", e),
+        }
+    }
+
+    /// Switches the list between the Drafts folder and the folder it opened on
+    fn toggle_drafts(&mut self, backend: &mut dyn MailBackend) {
+        let drafts = match drafts_folder(backend) {
+            Ok(drafts) => drafts,
+            Err(e) => {
+                self.status = format!("Could not open drafts: {}", e);
+                return;
+            }
+        };
+
+        self.folder = if self.folder == drafts {
+            self.home_folder.clone()
+        } else {
+            drafts
+        };
+        // Cached bodies are keyed by UID, which only means something within one folder
+        self.bodies.clear();
+        self.status.clear();
+        self.reload(backend);
+    }
+
+    /// Sends what came back from the editor or saves it as a draft, and reports how it went
+    pub fn finish_composed(
+        &mut self,
+        backend: &mut dyn MailBackend,
+        outgoing: Outgoing,
+        composed: Result<Composed, Box<dyn std::error::Error>>,
+    ) {
+        let email = match composed {
+            Ok(Composed::Send(email)) => email,
+            Ok(Composed::Draft(email)) => {
+                match save_draft(backend, &email, outgoing.resumes) {
+                    Ok(folder) => {
+                        self.status = format!("Saved the draft to {}", folder);
+                        self.refresh_folder(backend, &folder);
+                    }
+                    Err(e) => self.status = format!("Could not save the draft: {}", e),
+                }
+                return;
+            }
+            Ok(Composed::Discard) => {
+                self.status = "Not sent".to_string();
+                return;
+            }
+            Err(e) => {
+                self.status = format!("Could not compose message: {}", e);
+                return;
+            }
+        };
+
+        match backend.send(email) {
+            Ok(report) => {
+                self.status = format!("Sent to {} recipient(s)", report.accepted.len());
+                if !report.rejected.is_empty() {
+                    let refused: Vec<&str> = report
+                        .rejected
+                        .iter()
+                        .map(|(addr, _)| addr.as_str())
+                        .collect();
+                    self.status
+                        .push_str(&format!(", refused: {}", refused.join(", ")));
+                }
+                match &report.copy {
+                    SentCopy::Saved(folder) => {
+                        self.status.push_str(&format!(", saved to {}", folder));
+                    }
+                    SentCopy::Failed(e) => {
+                        self.status.push_str(&format!(", no copy saved: {}", e));
+                    }
+                    SentCopy::NotSaved => {}
+                }
+                if let Some(uid) = outgoing.answers {
+                    self.set_flag(backend, uid, Flag::Answered, true);
+                }
+                if let Some(uid) = outgoing.resumes {
+                    match discard_draft(backend, uid) {
+                        Ok(folder) => self.refresh_folder(backend, &folder),
+                        Err(e) => self.status.push_str(&format!(", draft not deleted: {}", e)),
+                    }
+                }
+            }
+            Err(e) => self.status = format!("Failed to send: {}", e),
+        }
+    }
+
+    /// Lists `folder` again if it's the one on screen, after something was added or removed
+    fn refresh_folder(&mut self, backend: &mut dyn MailBackend, folder: &str) {
+        if self.folder == folder {
+            self.reload(backend);
+        }
+    }
+
+    fn handle_normal_key(&mut self, backend: &mut dyn MailBackend, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('d') if !self.inbox.inbox.is_empty() => {
+                self.mode = Mode::ConfirmDelete;
+            }
+            KeyCode::Char('m') if !self.inbox.inbox.is_empty() => self.open_picker(backend, false),
+            KeyCode::Char('c') if !self.inbox.inbox.is_empty() => self.open_picker(backend, true),
+            KeyCode::Down if self.selected_index + 1 < self.inbox.inbox.len() => {
+                self.selected_index += 1;
+                if self.selected_index + LOAD_AHEAD >= self.inbox.inbox.len() {
+                    self.load_more(backend);
+                }
+            }
+            KeyCode::Up if self.selected_index > 0 => {
+                self.selected_index -= 1;
+            }
+            KeyCode::Char('o') => {
+                self.sort = match self.sort {
+                    SortOrder::Arrival => SortOrder::Date,
+                    SortOrder::Date => SortOrder::Arrival,
+                };
+                self.apply_sort();
+                self.status = match self.sort {
+                    SortOrder::Arrival => "Sorted by arrival".to_string(),
+                    SortOrder::Date => "Sorted by date".to_string(),
+                };
+            }
+            KeyCode::Char('N') => self.toggle_selected_flag(backend, Flag::Seen),
+            KeyCode::Char('F') => self.toggle_selected_flag(backend, Flag::Flagged),
+            KeyCode::Char('/') => {
+                self.mode = Mode::Search;
+                self.search_input = self.search.clone().unwrap_or_default();
+            }
+            KeyCode::Char('a') if self.view_email.is_some() => self.open_attachments(backend),
+            KeyCode::Char('r') if self.view_email.is_some() => self.prepare_reply(false),
+            KeyCode::Char('R') if self.view_email.is_some() => self.prepare_reply(true),
+            KeyCode::Char('f') if self.view_email.is_some() => self.prepare_forward(backend),
+            KeyCode::Char('D') => self.toggle_drafts(backend),
+            KeyCode::Char('e')
+                if self
+                    .inbox
+                    .inbox
+                    .get(self.selected_index)
+                    .is_some_and(|email| email.flags.contains(&Flag::Draft)) =>
+            {
+                self.resume_selected(backend)
+            }
+            KeyCode::Enter => self.open_selected(backend),
+            KeyCode::Esc if self.view_email.is_some() => {
+                self.view_email = None;
+            }
+            KeyCode::Esc if self.search.is_some() => {
+                self.status.clear();
+                self.reload(backend);
+            }
+            _ => {}
+        }
+
+        true
+    }
+}
+
+/// Addresses as a comma separated header value
+fn address_line(addresses: &[Address]) -> String {
+    addresses
+        .iter()
+        .map(Address::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A trailing list of the message's attachments for the email view, or nothing if it
+/// has none
+fn attachment_lines(email: &Email) -> String {
+    let Some(mime) = &email.mime else {
+        return String::new();
+    };
+    let attachments = mime.attachments();
+    if attachments.is_empty() {
+        return String::new();
+    }
+
+    let mut lines = String::from("\n\nAttachments (a to save):");
+    for part in attachments {
+        lines.push_str(&format!("\n  {}", attachment_label(part)));
+    }
+    lines
+}
+
+/// An attachment as listed to the user: `report.pdf (application/pdf, 1.2 MB)`
+fn attachment_label(part: &MimePart) -> String {
+    format!(
+        "{} ({}, {})",
+        part.filename.as_deref().unwrap_or("(unnamed)"),
+        part.content_type,
+        format_size(part.size)
+    )
+}
+
+/// One row of the message list: `N` marks unread and `!` flagged messages, like mutt,
+/// followed by the date relative to `now`
+fn list_line(email: &Email, now: &DateTime<FixedOffset>) -> String {
+    let unread = if email.flags.contains(&Flag::Seen) {
+        ' '
+    } else {
+        'N'
+    };
+    let flagged = if email.flags.contains(&Flag::Flagged) {
+        '!'
+    } else {
+        ' '
+    };
+    let date = email
+        .date
+        .map(|date| format_relative(&date, now))
+        .unwrap_or_default();
+    format!(
+        "{}{} {:>10}  {}: {}",
+        unread,
+        flagged,
+        date,
+        email.from.short(),
+        email.subject
+    )
+}
 
 pub fn run_tui(
-    provider: EmailProvider,
-    credentials: UserCredentials,
+    mail: &mut dyn MailBackend,
+    account: &Account,
 ) -> Result<(), Box<dyn std::error::Error>> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -24,44 +805,73 @@ pub fn run_tui(
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // Load inbox
-    let inbox: Inbox = get_inbox_all(provider, credentials.clone())
-        .unwrap_or(Inbox { inbox: vec![] });
+    // Load the account's default folder
+    let mut app = App::load(mail, &account.default_folder);
+    app.identities = account.identities.clone();
+    app.signature = account.signature.clone();
 
-    let mut selected_index: usize = 0;
-    let mut view_email: Option<Email> = None;
     let mut list_state = ListState::default();
-    list_state.select(Some(selected_index));
+    list_state.select(Some(app.selected_index));
+    let mut picker_state = ListState::default();
+    let mut attachment_state = ListState::default();
 
     loop {
         terminal.draw(|f| {
             let size = f.size();
 
-            // Layout: left panel = inbox, right panel = email view
+            // Layout: panels on top, one-line status bar at the bottom
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+                .split(size);
+
+            // Left panel = inbox, right panel = email view
             let chunks = Layout::default()
                 .direction(Direction::Horizontal)
                 .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
-                .split(size);
+                .split(rows[0]);
 
             // Inbox panel
-            let items: Vec<ListItem> = inbox
+            let now = Local::now().fixed_offset();
+            let items: Vec<ListItem> = app
+                .inbox
                 .inbox
                 .iter()
-                .map(|e| ListItem::new(format!("{}: {}", e.from, e.subject)))
+                .map(|e| {
+                    let item = ListItem::new(list_line(e, &now));
+                    if e.flags.contains(&Flag::Seen) {
+                        item
+                    } else {
+                        item.style(Style::default().add_modifier(Modifier::BOLD))
+                    }
+                })
                 .collect();
 
+            let title = match &app.search {
+                Some(query) => format!("{} (search: {})", app.folder, query),
+                None => app.folder.clone(),
+            };
             let list = List::new(items)
-                .block(Block::default().title("Inbox").borders(Borders::ALL))
+                .block(Block::default().title(title).borders(Borders::ALL))
                 .highlight_style(Style::default().bg(Color::Blue));
 
-            list_state.select(Some(selected_index));
+            list_state.select(Some(app.selected_index));
             f.render_stateful_widget(list, chunks[0], &mut list_state);
 
             // Email view panel
-            let paragraph = if let Some(email) = &view_email {
+            let paragraph = if let Some(email) = &app.view_email {
                 Paragraph::new(format!(
-                    "From: {}\nTo: {:?}\nCC: {:?}\nSubject: {}\nDate: {}\n\n{}",
-                    email.from, email.to, email.cc, email.subject, email.date, email.body
+                    "From: {}\nTo: {}\nCC: {}\nSubject: {}\nDate: {}\n\n{}{}",
+                    email.from,
+                    address_line(&email.to),
+                    address_line(&email.cc),
+                    email.subject,
+                    email
+                        .date
+                        .map(|date| date.format("%a, %-d %b %Y %H:%M %z").to_string())
+                        .unwrap_or_default(),
+                    email.body,
+                    attachment_lines(email)
                 ))
                 .block(Block::default().title("Email").borders(Borders::ALL))
             } else {
@@ -69,33 +879,71 @@ pub fn run_tui(
                     .block(Block::default().title("Email").borders(Borders::ALL))
             };
 
-            f.render_widget(paragraph, chunks[1]);
+            if let Mode::PickFolder { .. } = app.mode {
+                // The picker takes over the email panel until a folder is chosen
+                let folders: Vec<ListItem> = app
+                    .picker
+                    .iter()
+                    .map(|folder| {
+                        ListItem::new(format!(
+                            "{}{}",
+                            "  ".repeat(folder.depth()),
+                            folder.leaf_name()
+                        ))
+                    })
+                    .collect();
+                let picker = List::new(folders)
+                    .block(Block::default().title("Folders").borders(Borders::ALL))
+                    .highlight_style(Style::default().bg(Color::Blue));
+
+                picker_state.select(Some(app.picker_index));
+                f.render_stateful_widget(picker, chunks[1], &mut picker_state);
+            } else if app.mode == Mode::Attachments {
+                let attachments: Vec<ListItem> = app
+                    .attachments
+                    .iter()
+                    .enumerate()
+                    .map(|(i, part)| {
+                        ListItem::new(format!("{}  {}", i + 1, attachment_label(part)))
+                    })
+                    .collect();
+                let pane = List::new(attachments)
+                    .block(Block::default().title("Attachments").borders(Borders::ALL))
+                    .highlight_style(Style::default().bg(Color::Blue));
+
+                attachment_state.select(Some(app.attachment_index));
+                f.render_stateful_widget(pane, chunks[1], &mut attachment_state);
+            } else {
+                f.render_widget(paragraph, chunks[1]);
+            }
+            f.render_widget(Paragraph::new(app.status_line()), rows[1]);
         })?;
 
         // Handle input
-        if let Event::Key(key) = event::read()? {
-            match key.code {
-                KeyCode::Char('q') => break,
-                KeyCode::Down => {
-                    if selected_index + 1 < inbox.inbox.len() {
-                        selected_index += 1;
-                        list_state.select(Some(selected_index));
-                    }
-                }
-                KeyCode::Up => {
-                    if selected_index > 0 {
-                        selected_index -= 1;
-                        list_state.select(Some(selected_index));
-                    }
-                }
-                KeyCode::Enter => {
-                    view_email = inbox.inbox.get(selected_index).cloned();
-                }
-                KeyCode::Esc => {
-                    view_email = None;
-                }
-                _ => {}
-            }
+        if let Event::Key(key) = event::read()?
+            && !app.handle_key(mail, key.code)
+        {
+            break;
+        }
+
+        if let Some(outgoing) = app.outgoing.take() {
+            // Hand the terminal to the editor and the confirmation prompt, then take it back
+            disable_raw_mode()?;
+            execute!(
+                terminal.backend_mut(),
+                LeaveAlternateScreen,
+                DisableMouseCapture
+            )?;
+            let composed = compose(&outgoing.draft, &editor(), &mut io::stdin().lock());
+            enable_raw_mode()?;
+            execute!(
+                terminal.backend_mut(),
+                EnterAlternateScreen,
+                EnableMouseCapture
+            )?;
+            terminal.clear()?;
+
+            app.finish_composed(mail, outgoing, composed);
         }
     }
 
@@ -109,3 +957,422 @@ pub fn run_tui(
     terminal.show_cursor()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hermes::email_tools::MemoryBackend;
+    use hermes::email_tools::date::parse_date;
+
+    #[test]
+    fn test_navigation_stays_in_bounds() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        let total = app.inbox.inbox.len();
+
+        app.handle_key(&mut backend, KeyCode::Up);
+        assert_eq!(app.selected_index, 0);
+
+        for _ in 0..total + 2 {
+            app.handle_key(&mut backend, KeyCode::Down);
+        }
+        assert_eq!(app.selected_index, total - 1);
+    }
+
+    #[test]
+    fn test_enter_opens_and_esc_closes() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+
+        app.handle_key(&mut backend, KeyCode::Down);
+        app.handle_key(&mut backend, KeyCode::Enter);
+        assert_eq!(
+            app.view_email.as_ref().map(|e| e.subject.as_str()),
+            Some(app.inbox.inbox[1].subject.as_str())
+        );
+
+        app.handle_key(&mut backend, KeyCode::Esc);
+        assert!(app.view_email.is_none());
+    }
+
+    #[test]
+    fn test_enter_fetches_and_caches_body() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        assert!(app.inbox.inbox[0].body.is_empty());
+
+        app.handle_key(&mut backend, KeyCode::Enter);
+        let body = app.view_email.as_ref().unwrap().body.clone();
+        assert!(!body.is_empty());
+
+        // Served from the cache even once the backend no longer has it
+        backend.delete("INBOX", app.inbox.inbox[0].uid).unwrap();
+        app.handle_key(&mut backend, KeyCode::Esc);
+        app.handle_key(&mut backend, KeyCode::Enter);
+        assert_eq!(app.view_email.as_ref().unwrap().body, body);
+    }
+
+    #[test]
+    fn test_delete_asks_for_confirmation() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        let total = app.inbox.inbox.len();
+
+        app.handle_key(&mut backend, KeyCode::Char('d'));
+        assert_eq!(app.mode, Mode::ConfirmDelete);
+        assert!(app.status_line().contains("(y/n)"));
+
+        app.handle_key(&mut backend, KeyCode::Char('n'));
+        assert_eq!(app.mode, Mode::Normal);
+        assert_eq!(app.inbox.inbox.len(), total);
+
+        app.handle_key(&mut backend, KeyCode::Char('d'));
+        app.handle_key(&mut backend, KeyCode::Char('y'));
+        assert_eq!(app.inbox.inbox.len(), total - 1);
+        assert_eq!(app.status, "Moved to Trash");
+        assert_eq!(
+            backend
+                .fetch_envelopes("Trash", &Window::All)
+                .unwrap()
+                .inbox
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_scrolling_loads_older_pages() {
+        let mut backend = MemoryBackend::new();
+        let total = PAGE_SIZE as usize * 2 + 5;
+        for i in 0..total {
+            backend.insert(
+                "INBOX",
+                Email {
+                    subject: format!("message {}", i),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut app = App::load(&mut backend, "INBOX");
+        assert_eq!(app.inbox.inbox.len(), PAGE_SIZE as usize);
+        assert_eq!(app.inbox.inbox[0].subject, format!("message {}", total - 1));
+
+        for _ in 0..total {
+            app.handle_key(&mut backend, KeyCode::Down);
+        }
+        assert_eq!(app.inbox.inbox.len(), total);
+        assert_eq!(app.selected_index, total - 1);
+        assert_eq!(app.inbox.inbox[total - 1].subject, "message 0");
+    }
+
+    fn type_keys(app: &mut App, backend: &mut dyn MailBackend, text: &str) {
+        for c in text.chars() {
+            app.handle_key(backend, KeyCode::Char(c));
+        }
+    }
+
+    #[test]
+    fn test_search_filters_and_esc_restores() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        let total = app.inbox.inbox.len();
+
+        app.handle_key(&mut backend, KeyCode::Char('/'));
+        assert_eq!(app.mode, Mode::Search);
+        type_keys(&mut app, &mut backend, "from:alicx");
+        app.handle_key(&mut backend, KeyCode::Backspace);
+        type_keys(&mut app, &mut backend, "e");
+        assert_eq!(app.status_line(), "/from:alice");
+
+        assert!(app.handle_key(&mut backend, KeyCode::Enter));
+        assert_eq!(app.mode, Mode::Normal);
+        assert_eq!(app.inbox.inbox.len(), 1);
+        assert!(app.inbox.inbox[0].from.email().contains("alice"));
+        assert_eq!(app.search.as_deref(), Some("from:alice"));
+
+        app.handle_key(&mut backend, KeyCode::Esc);
+        assert!(app.search.is_none());
+        assert_eq!(app.inbox.inbox.len(), total);
+    }
+
+    #[test]
+    fn test_search_results_page_in_while_scrolling() {
+        let mut backend = MemoryBackend::new();
+        let total = PAGE_SIZE as usize + 5;
+        for i in 0..total * 2 {
+            let subject = if i % 2 == 0 { "report" } else { "lunch" };
+            backend.insert(
+                "INBOX",
+                Email {
+                    subject: format!("{} {}", subject, i),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut app = App::load(&mut backend, "INBOX");
+        app.handle_key(&mut backend, KeyCode::Char('/'));
+        type_keys(&mut app, &mut backend, "subject:report");
+        app.handle_key(&mut backend, KeyCode::Enter);
+        assert_eq!(app.inbox.inbox.len(), PAGE_SIZE as usize);
+
+        for _ in 0..total {
+            app.handle_key(&mut backend, KeyCode::Down);
+        }
+        assert_eq!(app.inbox.inbox.len(), total);
+        assert!(
+            app.inbox
+                .inbox
+                .iter()
+                .all(|e| e.subject.starts_with("report"))
+        );
+    }
+
+    #[test]
+    fn test_invalid_search_keeps_list() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        let total = app.inbox.inbox.len();
+
+        app.handle_key(&mut backend, KeyCode::Char('/'));
+        type_keys(&mut app, &mut backend, "since:someday");
+        app.handle_key(&mut backend, KeyCode::Enter);
+        assert!(app.status.starts_with("Invalid search"));
+        assert_eq!(app.inbox.inbox.len(), total);
+        assert!(app.search.is_none());
+    }
+
+    #[test]
+    fn test_opening_marks_read_and_keys_toggle_flags() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        let uid = app.inbox.inbox[0].uid;
+        assert!(!app.inbox.inbox[0].flags.contains(&Flag::Seen));
+        assert!(list_line(&app.inbox.inbox[0], &Local::now().fixed_offset()).starts_with('N'));
+
+        app.handle_key(&mut backend, KeyCode::Enter);
+        assert!(app.inbox.inbox[0].flags.contains(&Flag::Seen));
+        assert!(
+            backend
+                .fetch_message("INBOX", uid)
+                .unwrap()
+                .flags
+                .contains(&Flag::Seen)
+        );
+
+        app.handle_key(&mut backend, KeyCode::Char('N'));
+        assert!(!app.view_email.as_ref().unwrap().flags.contains(&Flag::Seen));
+        assert!(
+            !backend
+                .fetch_message("INBOX", uid)
+                .unwrap()
+                .flags
+                .contains(&Flag::Seen)
+        );
+
+        let flagged = app.inbox.inbox[0].flags.contains(&Flag::Flagged);
+        app.handle_key(&mut backend, KeyCode::Char('F'));
+        assert_eq!(app.inbox.inbox[0].flags.contains(&Flag::Flagged), !flagged);
+        assert_eq!(
+            list_line(&app.inbox.inbox[0], &Local::now().fixed_offset())
+                .chars()
+                .nth(1)
+                == Some('!'),
+            !flagged
+        );
+    }
+
+    #[test]
+    fn test_folder_picker_moves_and_copies() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        let total = app.inbox.inbox.len();
+        let subject = app.inbox.inbox[0].subject.clone();
+
+        app.handle_key(&mut backend, KeyCode::Char('m'));
+        assert_eq!(app.mode, Mode::PickFolder { copy: false });
+        assert!(app.picker.iter().all(|f| f.name != "INBOX"));
+        assert_eq!(app.picker[0].name, "Archive");
+
+        app.handle_key(&mut backend, KeyCode::Esc);
+        assert_eq!(app.mode, Mode::Normal);
+        assert_eq!(app.inbox.inbox.len(), total);
+
+        app.handle_key(&mut backend, KeyCode::Char('m'));
+        app.handle_key(&mut backend, KeyCode::Enter);
+        assert_eq!(app.status, "Moved to Archive");
+        assert_eq!(app.inbox.inbox.len(), total - 1);
+        let archive = backend.fetch_envelopes("Archive", &Window::All).unwrap();
+        assert_eq!(archive.inbox[0].subject, subject);
+
+        app.handle_key(&mut backend, KeyCode::Char('c'));
+        app.handle_key(&mut backend, KeyCode::Down);
+        app.handle_key(&mut backend, KeyCode::Enter);
+        assert_eq!(app.inbox.inbox.len(), total - 1);
+        assert_eq!(app.status, format!("Copied to {}", app.picker[1].name));
+    }
+
+    #[test]
+    fn test_attachment_pane_saves_selected() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        let dir = tempfile::tempdir().unwrap();
+        app.save_dir = dir.path().to_path_buf();
+
+        // Nothing to browse until a message is open
+        app.handle_key(&mut backend, KeyCode::Char('a'));
+        assert_eq!(app.mode, Mode::Normal);
+
+        app.handle_key(&mut backend, KeyCode::Enter);
+        app.handle_key(&mut backend, KeyCode::Char('a'));
+        assert_eq!(app.mode, Mode::Attachments);
+        assert_eq!(app.attachments.len(), 1);
+        assert!(app.status_line().starts_with("s to save"));
+
+        app.handle_key(&mut backend, KeyCode::Char('s'));
+        assert!(app.status.starts_with("Saved"));
+        assert!(dir.path().join("q4-figures.csv").exists());
+
+        app.handle_key(&mut backend, KeyCode::Esc);
+        assert_eq!(app.mode, Mode::Normal);
+        assert!(app.view_email.is_some());
+
+        // A message without attachments says so instead of opening an empty pane
+        app.handle_key(&mut backend, KeyCode::Down);
+        app.handle_key(&mut backend, KeyCode::Enter);
+        app.handle_key(&mut backend, KeyCode::Char('a'));
+        assert_eq!(app.mode, Mode::Normal);
+        assert_eq!(app.status, "This message has no attachments");
+    }
+
+    #[test]
+    fn test_reply_and_forward_keys() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        app.handle_key(&mut backend, KeyCode::Enter);
+
+        // Without our own address there is nothing to reply from
+        app.handle_key(&mut backend, KeyCode::Char('r'));
+        assert!(app.outgoing.is_none());
+        assert_eq!(
+            app.status,
+            "No address to reply from; give the account an identity"
+        );
+
+        app.identities = vec!["me@example.com".parse().unwrap()];
+        app.handle_key(&mut backend, KeyCode::Char('r'));
+        let outgoing = app.outgoing.take().unwrap();
+        assert_eq!(outgoing.draft.to[0].email(), "bob@example.org");
+        assert_eq!(outgoing.draft.subject, "Re: Quarterly report draft");
+        assert_eq!(
+            outgoing.draft.in_reply_to.as_deref(),
+            Some("<demo-3@hermes.local>")
+        );
+
+        // Sending what came back from the editor marks the original answered
+        let uid = outgoing.answers.unwrap();
+        let draft = outgoing.draft.clone();
+        app.finish_composed(&mut backend, outgoing, Ok(Composed::Send(draft)));
+        assert_eq!(app.status, "Sent to 1 recipient(s), saved to Sent");
+        assert_eq!(backend.sent.len(), 1);
+        let original = backend.fetch_message("INBOX", uid).unwrap();
+        assert!(original.flags.contains(&Flag::Answered));
+
+        app.handle_key(&mut backend, KeyCode::Char('f'));
+        let outgoing = app.outgoing.take().unwrap();
+        assert_eq!(outgoing.draft.subject, "Fwd: Quarterly report draft");
+        assert_eq!(outgoing.draft.attachments[0].content_type, "message/rfc822");
+        app.finish_composed(&mut backend, outgoing, Ok(Composed::Discard));
+        assert_eq!(app.status, "Not sent");
+        assert_eq!(backend.sent.len(), 1);
+    }
+
+    #[test]
+    fn test_drafts_are_saved_resumed_and_deleted_once_sent() {
+        let mut backend = MemoryBackend::demo();
+        let mut app = App::load(&mut backend, "INBOX");
+        app.identities = vec!["me@example.com".parse().unwrap()];
+        app.handle_key(&mut backend, KeyCode::Enter);
+        app.handle_key(&mut backend, KeyCode::Char('r'));
+        let outgoing = app.outgoing.take().unwrap();
+        let draft = outgoing.draft.clone();
+        app.finish_composed(&mut backend, outgoing, Ok(Composed::Draft(draft)));
+        assert_eq!(app.status, "Saved the draft to Drafts");
+
+        app.handle_key(&mut backend, KeyCode::Char('D'));
+        assert_eq!(app.folder, "Drafts");
+        assert_eq!(app.inbox.inbox.len(), 1);
+        app.handle_key(&mut backend, KeyCode::Char('e'));
+        let outgoing = app.outgoing.take().unwrap();
+        assert_eq!(outgoing.draft.subject, "Re: Quarterly report draft");
+        assert!(outgoing.resumes.is_some());
+
+        let draft = outgoing.draft.clone();
+        app.finish_composed(&mut backend, outgoing, Ok(Composed::Send(draft)));
+        assert_eq!(app.status, "Sent to 1 recipient(s), saved to Sent");
+        assert!(app.inbox.inbox.is_empty());
+
+        app.handle_key(&mut backend, KeyCode::Char('D'));
+        assert_eq!(app.folder, "INBOX");
+    }
+
+    #[test]
+    fn test_sort_by_date_keeps_selection() {
+        let mut backend = MemoryBackend::new();
+        for (subject, date) in [
+            ("middle", "Tue, 16 Jan 2024 09:00:00 +0000"),
+            ("undated", ""),
+            ("oldest", "Mon, 15 Jan 2024 09:00:00 +0000"),
+            ("newest", "Wed, 17 Jan 2024 09:00:00 +0100"),
+        ] {
+            backend.insert(
+                "INBOX",
+                Email {
+                    subject: subject.to_string(),
+                    date: parse_date(date),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut app = App::load(&mut backend, "INBOX");
+        let subjects = |app: &App| -> Vec<String> {
+            app.inbox.inbox.iter().map(|e| e.subject.clone()).collect()
+        };
+        assert_eq!(subjects(&app), ["newest", "oldest", "undated", "middle"]);
+
+        app.handle_key(&mut backend, KeyCode::Down);
+        app.handle_key(&mut backend, KeyCode::Char('o'));
+        assert_eq!(app.sort, SortOrder::Date);
+        assert_eq!(subjects(&app), ["newest", "middle", "oldest", "undated"]);
+        assert_eq!(app.inbox.inbox[app.selected_index].subject, "oldest");
+
+        app.handle_key(&mut backend, KeyCode::Char('o'));
+        assert_eq!(subjects(&app), ["newest", "oldest", "undated", "middle"]);
+    }
+
+    #[test]
+    fn test_list_line_shows_relative_date() {
+        let now = parse_date("Mon, 15 Jan 2024 12:00:00 +0000").unwrap();
+        let email = Email {
+            from: "Alice Martin <alice@example.com>".parse().unwrap(),
+            subject: "Lunch".to_string(),
+            date: parse_date("Mon, 15 Jan 2024 10:30:00 +0000"),
+            flags: vec![Flag::Seen],
+            ..Default::default()
+        };
+        assert_eq!(
+            list_line(&email, &now),
+            "        10:30  Alice Martin: Lunch"
+        );
+    }
+
+    #[test]
+    fn test_q_quits() {
+        let mut backend = MemoryBackend::new();
+        let mut app = App::load(&mut backend, "INBOX");
+        assert!(app.handle_key(&mut backend, KeyCode::Char('j')));
+        assert!(!app.handle_key(&mut backend, KeyCode::Char('q')));
+    }
+}